@@ -61,3 +61,20 @@ module cdc_pulse(
     out_pulse <= !last_req && new_req;
 
 endmodule
+
+// First-order sigma-delta modulator: the carry out of the accumulator is high code/2^BITS of
+// the time, RC-filter it for a voltage
+module sigma_delta_dac #(
+  parameter BITS = 16
+) (
+  input clk,
+  input [BITS-1:0] code,
+  output reg out = 0,
+);
+
+  reg [BITS-1:0] acc = 0;
+
+  always @(posedge clk)
+    { out, acc } <= acc + code;
+
+endmodule
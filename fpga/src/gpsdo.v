@@ -17,6 +17,8 @@ module gpsdo (
 	output gpio_3,
 	input gpio_4,
 
+	output dac_pwm,
+
 	output flash_csb,
 	output flash_clk,
 	inout  flash_io0,
@@ -115,12 +117,14 @@ module gpsdo (
 	reg  [31:0] iomem_rdata;
 
 	reg  [5:0] gpio;
+	reg  [15:0] dac_code;
 
 	generate
 		always @(posedge clk_picosoc) begin
 			if (!resetn) begin
 				gpio[3:0] <= 0;
         gpio[5] <= 0;
+				dac_code <= 0;
 			end else begin
 				iomem_ready <= 0;
 				if (iomem_valid && !iomem_ready && iomem_addr[31:24] == 8'h 03 && iomem_addr[7:0] == 8'h 00) begin
@@ -153,6 +157,12 @@ module gpsdo (
 					iomem_ready <= 1;
 					iomem_rdata[31:EPOCH_BITS] <= 0;
 					iomem_rdata[EPOCH_BITS-1:0] <= fc_epoch;
+				end else if (iomem_valid && !iomem_ready && iomem_addr[31:24] == 8'h 03 && iomem_addr[7:0] == 8'h14) begin
+					iomem_ready <= 1;
+					iomem_rdata[31:16] <= 0;
+					iomem_rdata[15:0] <= dac_code;
+					if (iomem_wstrb[0]) dac_code[7:0] <= iomem_wdata[7:0];
+					if (iomem_wstrb[1]) dac_code[15:8] <= iomem_wdata[15:8];
 				end
 			end
 		end
@@ -228,6 +238,12 @@ module gpsdo (
 		gpio[4] <= gpio_4;
 	end
 
+	sigma_delta_dac #(.BITS(16)) dac(
+		.clk(clk_picosoc),
+		.code(dac_code),
+		.out(dac_pwm),
+	);
+
   assign debug_flash_csb = flash_csb;
   assign debug_flash_clk = flash_clk;
   assign debug_flash_io0 = flash_io0_di;
@@ -0,0 +1,151 @@
+//! Splits the UART stream into text lines and binary records, see `ks_gpsdo::record`
+
+use ks_gpsdo::record::{MAX_FRAME_LEN, Record};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Line(String),
+    Record(Record),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Text,
+    /// After a zero, either between the delimiters of a frame or right past the closing one
+    Frame,
+}
+
+pub struct Demux {
+    state: State,
+    buf: Vec<u8>,
+}
+
+impl Demux {
+    pub fn new() -> Self {
+        Self { state: State::Text, buf: Vec::new() }
+    }
+
+    pub fn push(&mut self, bytes: &[u8], events: &mut Vec<Event>) {
+        for &byte in bytes {
+            self.push_byte(byte, events);
+        }
+    }
+
+    fn push_byte(&mut self, byte: u8, events: &mut Vec<Event>) {
+        match self.state {
+            State::Text => match byte {
+                0 => {
+                    self.flush_line(events);
+                    self.state = State::Frame;
+                }
+                b'\n' => self.flush_line(events),
+                _ => self.buf.push(byte),
+            },
+            State::Frame if byte == 0 => {
+                if self.buf.is_empty() {
+                    return;
+                }
+                match Record::from_frame(&self.buf) {
+                    Ok(record) => {
+                        self.buf.clear();
+                        events.push(Event::Record(record));
+                        self.state = State::Text;
+                    }
+                    // text after the closing delimiter, or a damaged frame, which isn't worth a line
+                    // of garbage. This zero may well open the next frame.
+                    Err(_) => {
+                        let text = std::mem::take(&mut self.buf);
+                        if is_text(&text) {
+                            self.lines(&text, events);
+                        }
+                    }
+                }
+            }
+            State::Frame => {
+                self.buf.push(byte);
+                if self.buf.len() > MAX_FRAME_LEN {
+                    let text = std::mem::take(&mut self.buf);
+                    self.state = State::Text;
+                    self.push(&text, events);
+                }
+            }
+        }
+    }
+
+    fn lines(&mut self, text: &[u8], events: &mut Vec<Event>) {
+        for line in text.split(|&b| b == b'\n') {
+            self.buf.extend_from_slice(line);
+            self.flush_line(events);
+        }
+    }
+
+    fn flush_line(&mut self, events: &mut Vec<Event>) {
+        let line = String::from_utf8_lossy(&self.buf);
+        let line = line.trim_end_matches('\r');
+        if !line.is_empty() {
+            events.push(Event::Line(line.to_owned()));
+        }
+        self.buf.clear();
+    }
+}
+
+fn is_text(bytes: &[u8]) -> bool {
+    std::str::from_utf8(bytes).is_ok()
+        && bytes.iter().all(|&b| !b.is_ascii_control() || matches!(b, b'\r' | b'\n' | b'\t'))
+}
+
+#[cfg(test)]
+mod tests {
+    use ks_gpsdo::record::Record;
+
+    use crate::demux::{Demux, Event};
+
+    fn record(sequence: u32) -> Record {
+        Record {
+            // a '\n' in the payload
+            dac_target: f32::from_bits(0x0a0a_0a0a),
+            dac_code: 0x0a0a,
+            ..Record::fixture(sequence, 10e6)
+        }
+    }
+
+    fn demux(chunks: &[&[u8]]) -> Vec<Event> {
+        let mut demux = Demux::new();
+        let mut events = Vec::new();
+        for chunk in chunks {
+            demux.push(chunk, &mut events);
+        }
+        events
+    }
+
+    fn line(line: &str) -> Event {
+        Event::Line(line.to_owned())
+    }
+
+    #[test]
+    fn interleaved() {
+        let frame = record(1).to_frame();
+        let (head, tail) = frame.split_at(30);
+        let mut corrupted = record(2).to_frame();
+        corrupted[10] ^= 0x40;
+
+        let events = demux(&[
+            b"hello\r\n", &frame, b"after\r\n", &record(3).to_frame(), &record(4).to_frame(),
+            b"split", head, tail, &corrupted, b"next\r\n", &record(5).to_frame(),
+        ]);
+
+        // the corrupted frame is dropped whole
+        assert_eq!(vec![
+            line("hello"), Event::Record(record(1)), line("after"), Event::Record(record(3)),
+            Event::Record(record(4)), line("split"), Event::Record(record(1)), line("next"),
+            Event::Record(record(5)),
+        ], events);
+    }
+
+    #[test]
+    fn text_only() {
+        let long = "x".repeat(200);
+        let events = demux(&[b"a\r\n\r\nb", b"\n", long.as_bytes(), b"\0", b"c\n\0"]);
+        assert_eq!(vec![line("a"), line("b"), line(&long), line("c")], events);
+    }
+}
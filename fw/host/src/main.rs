@@ -0,0 +1,144 @@
+//! Host companion for the GPSDO: decodes the binary records from the UART, keeps ADEV/TIE
+//! running, logs CSV for the simulation notebooks, and forwards console commands typed on stdin.
+
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::thread;
+
+use crate::monitor::{Dac, Monitor};
+
+mod demux;
+mod monitor;
+mod serial;
+
+const USAGE: &str = "\
+usage: ks-gpsdo-host <port> [options]
+  --baud <rate>          115200 by default
+  --csv <file>           log every record in the simulation's SystemMetrics columns
+  --v-ref <volts>        DAC reference, for dac_v_out, 5.0 by default
+  --dac-bits <bits>      16 by default
+  --command <line>       send to the console after connecting, repeatable
+  --summary <records>    print the stability summary this often, 60 by default
+  --count <records>      exit after this many
+Lines typed on stdin go to the device console.
+";
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    port: String,
+    baud: u32,
+    csv: Option<String>,
+    dac: Dac,
+    commands: Vec<String>,
+    summary_every: u64,
+    count: Option<u64>,
+}
+
+fn parse_args<I: Iterator<Item=String>>(mut args: I) -> Result<Args, String> {
+    let mut port = None;
+    let mut parsed = Args {
+        port: String::new(),
+        baud: 115200,
+        csv: None,
+        dac: Dac { v_ref: 5.0, bits: 16 },
+        commands: Vec::new(),
+        summary_every: 60,
+        count: None,
+    };
+
+    fn number<T: std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+        let value = value.ok_or_else(|| format!("{} needs a value", option))?;
+        value.parse().map_err(|_| format!("invalid {}: {}", option, value))
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--baud" => parsed.baud = number(&arg, args.next())?,
+            "--csv" => parsed.csv = Some(args.next().ok_or("--csv needs a file")?),
+            "--v-ref" => parsed.dac.v_ref = number(&arg, args.next())?,
+            "--dac-bits" => match number(&arg, args.next())? {
+                bits @ 1..=32 => parsed.dac.bits = bits,
+                bits => return Err(format!("invalid --dac-bits: {}", bits)),
+            },
+            "--command" => parsed.commands.push(args.next().ok_or("--command needs a line")?),
+            "--summary" => parsed.summary_every = number(&arg, args.next())?,
+            "--count" => parsed.count = Some(number(&arg, args.next())?),
+            option if option.starts_with("--") => return Err(format!("unknown option {}", option)),
+            _ if port.is_none() => port = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    parsed.port = port.ok_or("no port given")?;
+    Ok(parsed)
+}
+
+fn run(args: Args) -> io::Result<()> {
+    let mut port = serial::open(&args.port, args.baud)?;
+    let csv = args.csv.as_ref().map(File::create).transpose()?;
+
+    port.write_all(b"records on\n")?;
+    for command in args.commands.iter() {
+        writeln!(port, "{}", command)?;
+    }
+
+    let mut console = port.try_clone()?;
+    thread::spawn(move || -> io::Result<()> {
+        for line in io::stdin().lock().lines() {
+            writeln!(console, "{}", line?)?;
+        }
+        Ok(())
+    });
+
+    let mut monitor = Monitor::new(csv, args.dac);
+    monitor::run(port, &mut monitor, io::stdout(), args.summary_every, args.count)?;
+    print!("{}", monitor);
+    Ok(())
+}
+
+fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprint!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::monitor::Dac;
+    use crate::{Args, parse_args};
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn args() {
+        assert_eq!(Ok(Args {
+            port: "/dev/ttyUSB1".to_owned(),
+            baud: 921600,
+            csv: Some("run.csv".to_owned()),
+            dac: Dac { v_ref: 4.096, bits: 18 },
+            commands: vec!["set tau 1200".to_owned(), "holdover off".to_owned()],
+            summary_every: 60,
+            count: Some(3600),
+        }), parse(&[
+            "--baud", "921600", "/dev/ttyUSB1", "--csv", "run.csv", "--v-ref", "4.096", "--dac-bits", "18",
+            "--command", "set tau 1200", "--command", "holdover off", "--count", "3600",
+        ]));
+
+        assert_eq!(Err("no port given".to_owned()), parse(&["--baud", "9600"]));
+        assert_eq!(Err("invalid --baud: fast".to_owned()), parse(&["p", "--baud", "fast"]));
+        assert_eq!(Err("--csv needs a file".to_owned()), parse(&["p", "--csv"]));
+        assert_eq!(Err("invalid --dac-bits: 0".to_owned()), parse(&["p", "--dac-bits", "0"]));
+        assert_eq!(Err("unexpected argument q".to_owned()), parse(&["p", "q"]));
+        assert_eq!(Err("unknown option --verbose".to_owned()), parse(&["p", "--verbose"]));
+    }
+}
@@ -0,0 +1,315 @@
+//! Live stability analysis and CSV logging of the per-second records
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use ks_gpsdo::record::Record;
+use ks_gpsdo::scanner::Quantity;
+use ks_gpsdo::stability::StabilityAnalysis;
+use serde_derive::Serialize;
+
+use crate::demux::{Demux, Event};
+
+pub const NOMINAL_FREQUENCY: f64 = 10e6;
+
+/// Same columns as the simulation's, for the same notebooks. What the device can't know is left
+/// empty.
+#[derive(Serialize)]
+pub struct SystemMetrics {
+    dac_code: u16,
+    dac_v_out: f64,
+    dac_v_ref: f64,
+    ocxo_v_control: Option<f64>,
+    ocxo_frequency: Option<f64>,
+    pps_seconds: Option<f64>,
+    reported_frequency: f64,
+    filtered_frequency: f64,
+    control_i_error: Option<f64>,
+    control_p_term: f64,
+    control_i_term: f64,
+    control_d_term: f64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Dac {
+    pub v_ref: f64,
+    pub bits: u32,
+}
+
+impl SystemMetrics {
+    pub fn new(record: &Record, dac: Dac) -> Self {
+        Self {
+            dac_code: record.dac_code,
+            dac_v_out: dac.v_ref * record.dac_code as f64 / (1u64 << dac.bits) as f64,
+            dac_v_ref: dac.v_ref,
+            ocxo_v_control: None,
+            ocxo_frequency: None,
+            pps_seconds: None,
+            reported_frequency: record.frequency(),
+            filtered_frequency: record.filtered_frequency,
+            control_i_error: None,
+            control_p_term: record.p_term as f64,
+            control_i_term: record.i_term as f64,
+            control_d_term: record.d_term as f64,
+        }
+    }
+}
+
+/// Time interval error against the nominal frequency, integrated from the frequency samples
+pub struct Tie {
+    tau0: f64,
+    /// s
+    x: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Tie {
+    pub fn new(tau0: f64) -> Self {
+        Self { tau0, x: 0.0, min: 0.0, max: 0.0 }
+    }
+
+    pub fn add(&mut self, frequency: f64) {
+        self.x += (frequency / NOMINAL_FREQUENCY - 1.0) * self.tau0;
+        self.min = self.min.min(self.x);
+        self.max = self.max.max(self.x);
+    }
+
+    /// s
+    pub fn tie(&self) -> f64 {
+        self.x
+    }
+
+    /// MTIE over everything seen so far, s
+    pub fn peak_to_peak(&self) -> f64 {
+        self.max - self.min
+    }
+}
+
+pub struct Monitor<W: Write> {
+    stability: StabilityAnalysis,
+    tie: Tie,
+    csv: Option<csv::Writer<W>>,
+    dac: Dac,
+    last: Option<(u32, f64)>,
+    records: u64,
+    missed: u64,
+}
+
+impl<W: Write> Monitor<W> {
+    pub fn new(csv: Option<W>, dac: Dac) -> Self {
+        Self {
+            stability: StabilityAnalysis::new(NOMINAL_FREQUENCY, 1.0),
+            tie: Tie::new(1.0),
+            csv: csv.map(csv::Writer::from_writer),
+            dac,
+            last: None,
+            records: 0,
+            missed: 0,
+        }
+    }
+
+    pub fn stability(&self) -> &StabilityAnalysis {
+        &self.stability
+    }
+
+    pub fn tie(&self) -> &Tie {
+        &self.tie
+    }
+
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    pub fn add(&mut self, record: &Record) -> io::Result<()> {
+        if let Some(csv) = self.csv.as_mut() {
+            csv.serialize(SystemMetrics::new(record, self.dac))?;
+            // the notebooks read it while it's being written
+            csv.flush()?;
+        }
+        self.records += 1;
+
+        // the device only sends records with valid counters, steering or not
+        let frequency = record.frequency();
+        match self.last {
+            Some((sequence, _)) if record.sequence == sequence.wrapping_add(1) => {}
+            // lost records, the statistics need evenly spaced samples
+            Some((sequence, _)) if record.sequence > sequence => {
+                self.missed += (record.sequence - sequence - 1) as u64;
+                self.restart();
+            }
+            // the device restarted
+            Some(_) => self.restart(),
+            None => {}
+        }
+        self.stability.add(frequency);
+        self.tie.add(frequency);
+        self.last = Some((record.sequence, frequency));
+        Ok(())
+    }
+
+    fn restart(&mut self) {
+        self.stability.reset();
+        self.tie = Tie::new(1.0);
+    }
+}
+
+impl<W: Write> fmt::Display for Monitor<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (stability, tie) = (self.stability(), self.tie());
+        writeln!(f, "{} records, {} samples, {} missed", self.records, stability.samples(), self.missed)?;
+        if let Some((sequence, frequency)) = self.last {
+            writeln!(f, "#{}: {:.4} Hz", sequence, frequency)?;
+        }
+        write!(f, "{}", stability)?;
+        writeln!(f, "TIE {:.3e}s, peak to peak {:.3e}s", tie.tie(), tie.peak_to_peak())
+    }
+}
+
+/// Until the port closes or `limit` records were seen. Text from the device goes to `out`, along
+/// with a summary every `summary_every` records.
+pub fn run<R: Read, W: Write, O: Write>(
+    mut port: R,
+    monitor: &mut Monitor<W>,
+    mut out: O,
+    summary_every: u64,
+    limit: Option<u64>,
+) -> io::Result<()> {
+    let mut demux = Demux::new();
+    let mut events = Vec::new();
+    let mut buf = [0; 256];
+    loop {
+        let len = match port.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            // a pty whose master end went away
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        demux.push(&buf[..len], &mut events);
+        for event in events.drain(..) {
+            match event {
+                Event::Line(line) => writeln!(out, "{}", line)?,
+                Event::Record(record) => {
+                    monitor.add(&record)?;
+                    if summary_every > 0 && monitor.records() % summary_every == 0 {
+                        let ocxo = record.telemetry[Quantity::OcxoTemperature as usize];
+                        writeln!(out, "{}OCXO {:.2}°C, DAC {}", monitor, ocxo, record.dac_code)?;
+                    }
+                    if Some(monitor.records()) == limit {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        out.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::thread;
+
+    use assert_approx_eq::assert_approx_eq;
+    use ks_gpsdo::record::Record;
+
+    use crate::monitor::{Dac, Monitor, NOMINAL_FREQUENCY, run, Tie};
+    use crate::serial::{open, pty};
+
+    const RECORDS: u32 = 300;
+    /// Hz, uniform
+    const NOISE: f64 = 1.0;
+
+    /// Talks like the firmware on the far end of a pty. Returns the TIE of what it sent.
+    fn device(master: std::fs::File) -> (std::fs::File, f64) {
+        let mut rx = BufReader::new(master.try_clone().unwrap());
+        let mut tx = master;
+        tx.write_all(b"Starting up\r\n").unwrap();
+
+        let mut line = String::new();
+        while line.trim() != "records on" {
+            line.clear();
+            rx.read_line(&mut line).unwrap();
+        }
+
+        let mut tie = Tie::new(1.0);
+        let mut state = 0x2545_f491u32;
+        for sequence in 1..=RECORDS {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let noise = (state as f64 / u32::MAX as f64 * 2.0 - 1.0) * NOISE;
+            let record = Record::fixture(sequence, NOMINAL_FREQUENCY + noise);
+            tie.add(record.frequency());
+            tx.write_all(&record.to_frame()).unwrap();
+            if sequence % 100 == 0 {
+                write!(tx, "Counters: {}\r\n", sequence).unwrap();
+            }
+        }
+        (tx, tie.tie())
+    }
+
+    #[test]
+    fn over_pty() {
+        let (master, path) = pty().unwrap();
+        let mut port = open(&path, 115200).unwrap();
+        let device = thread::spawn(move || device(master));
+        port.write_all(b"records on\n").unwrap();
+
+        let mut csv = Vec::new();
+        let mut out = Vec::new();
+        let dac = Dac { v_ref: 5.0, bits: 16 };
+        let mut monitor = Monitor::new(Some(&mut csv), dac);
+        run(&mut port, &mut monitor, &mut out, 100, Some(RECORDS as u64)).unwrap();
+        let (_master, tie) = device.join().unwrap();
+
+        // white FM, uniform noise
+        let sigma = NOISE / 3f64.sqrt() / NOMINAL_FREQUENCY;
+        let adev = monitor.stability().adev(0).unwrap();
+        assert!((adev / sigma - 1.0).abs() < 0.25, "{} vs {}", adev, sigma);
+        assert_approx_eq!(tie, monitor.tie().tie(), 1e-12);
+        drop(monitor);
+
+        let csv = String::from_utf8(csv).unwrap();
+        let mut rows = csv.lines();
+        assert_eq!(
+            Some("dac_code,dac_v_out,dac_v_ref,ocxo_v_control,ocxo_frequency,pps_seconds,reported_frequency,\
+                filtered_frequency,control_i_error,control_p_term,control_i_term,control_d_term"),
+            rows.next(),
+        );
+        assert!(rows.next().unwrap().starts_with("32768,2.5,5.0,,,,1000"));
+        assert_eq!(RECORDS as usize - 1, rows.count());
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Starting up\n"));
+        assert!(out.contains("Counters: 100\n"));
+        assert!(out.contains("300 records, 300 samples, 0 missed\n"));
+    }
+
+    #[test]
+    fn gaps() {
+        let mut monitor = Monitor::<Vec<u8>>::new(None, Dac { v_ref: 5.0, bits: 16 });
+        for sequence in [1, 2, 5, 6, 7].iter() {
+            monitor.add(&Record::fixture(*sequence, NOMINAL_FREQUENCY + 1.0)).unwrap();
+        }
+        assert_eq!(3, monitor.stability().samples());
+        assert_eq!(2, monitor.missed);
+
+        // not steering, but valid counters all the same
+        let mut holdover = Record::fixture(8, NOMINAL_FREQUENCY);
+        holdover.flags = Record::HOLDOVER;
+        monitor.add(&holdover).unwrap();
+        assert_eq!(4, monitor.stability().samples());
+
+        monitor.add(&Record::fixture(20, NOMINAL_FREQUENCY)).unwrap();
+        assert_eq!(1, monitor.stability().samples());
+        assert_eq!(0.0, monitor.tie().tie());
+        assert_eq!(13, monitor.missed);
+        monitor.add(&Record::fixture(3, NOMINAL_FREQUENCY)).unwrap();
+        assert_eq!(1, monitor.stability().samples());
+        assert_eq!(13, monitor.missed);
+        assert_eq!(8, monitor.records());
+    }
+}
@@ -0,0 +1,95 @@
+//! Raw 8N1 serial ports through termios
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+fn speed(baud: u32) -> Option<libc::speed_t> {
+    Some(match baud {
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        460800 => libc::B460800,
+        921600 => libc::B921600,
+        _ => return None,
+    })
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// No echo, no line discipline, no flow control. Reads block until at least a byte arrives.
+pub fn open(path: &str, baud: u32) -> io::Result<File> {
+    let speed = speed(baud)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported baud rate {}", baud)))?;
+    let port = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)?;
+
+    let fd = port.as_raw_fd();
+    unsafe {
+        let mut termios = std::mem::zeroed::<libc::termios>();
+        check(libc::tcgetattr(fd, &mut termios))?;
+        libc::cfmakeraw(&mut termios);
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        termios.c_cflag &= !(libc::CSTOPB | libc::CRTSCTS | libc::PARENB);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        check(libc::cfsetispeed(&mut termios, speed))?;
+        check(libc::cfsetospeed(&mut termios, speed))?;
+        check(libc::tcsetattr(fd, libc::TCSANOW, &termios))?;
+    }
+    Ok(port)
+}
+
+/// A pseudo-terminal for a simulated device: the master end and the path of the slave end
+#[cfg(test)]
+pub fn pty() -> io::Result<(File, String)> {
+    use std::os::unix::io::FromRawFd;
+
+    unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        check(fd)?;
+        let master = File::from_raw_fd(fd);
+        check(libc::grantpt(fd))?;
+        check(libc::unlockpt(fd))?;
+        let mut name = [0 as libc::c_char; 128];
+        if libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let path = std::ffi::CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+        Ok((master, path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use crate::serial::{open, pty};
+
+    #[test]
+    fn raw() {
+        let (mut master, path) = pty().unwrap();
+        let mut port = open(&path, 115200).unwrap();
+
+        // no CR/LF translation, zeros pass through
+        master.write_all(b"\0a\r\n\0").unwrap();
+        let mut buf = [0; 5];
+        port.read_exact(&mut buf).unwrap();
+        assert_eq!(b"\0a\r\n\0", &buf);
+
+        assert!(open(&path, 1234).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::dac::{Error, TuningDac};
+
+/// AD5541 and pin-compatible 16-bit DACs, the code is latched when CS goes high
+pub struct AD5541<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin> {
+    spi: SPI,
+    cs: CS,
+    v_ref: f64,
+}
+
+impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin> AD5541<SPI, CS>
+    where SPI::Error: core::fmt::Debug, CS::Error: core::fmt::Debug
+{
+    pub fn new(spi: SPI, cs: CS, v_ref: f64) -> Self {
+        Self { spi, cs, v_ref }
+    }
+}
+
+impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin> TuningDac for AD5541<SPI, CS>
+    where SPI::Error: core::fmt::Debug, CS::Error: core::fmt::Debug
+{
+    type Error = Error<SPI::Error, CS::Error>;
+
+    fn resolution_bits(&self) -> u32 {
+        16
+    }
+
+    fn full_scale(&self) -> f64 {
+        self.v_ref
+    }
+
+    fn set_code(&mut self, code: u16) -> Result<(), Self::Error> {
+        self.cs.set_low().map_err(|error| Error::ChipSelectError { error })?;
+        let result = self.spi.write(&code.to_be_bytes()).map_err(|error| Error::BusError { error });
+        let cs_result = self.cs.set_high().map_err(|error| Error::ChipSelectError { error });
+        result.and(cs_result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use crate::ad5541::AD5541;
+    use crate::dac::{Error, TuningDac};
+    use crate::dac::mock::{self, Event, Fault};
+
+    #[test]
+    fn frames() {
+        let (spi, cs, bus) = mock::bus();
+        let mut dac = AD5541::new(spi, cs, 2.5);
+
+        dac.set_code(0xabcd).unwrap();
+        assert_eq!(vec![Event::CsLow, Event::Write(vec![0xab, 0xcd]), Event::CsHigh], bus.borrow().events);
+
+        bus.borrow_mut().spi_fault = true;
+        match dac.set_code(1) {
+            Err(Error::BusError { error: Fault }) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+}
@@ -4,7 +4,9 @@ use embedded_hal::timer::CountDown;
 use crate::hal;
 use byteorder::ByteOrder;
 use core::fmt::Debug;
+use core::marker::PhantomData;
 use core::pin::Pin;
+use core::task::Poll;
 use crate::util::{OutputPinHold, FullDuplexTransfer};
 
 #[derive(Debug)]
@@ -18,7 +20,9 @@ pub enum Error<E: Debug> {
     },
     BusError {
         error: E,
-    }
+    },
+    /// DOUT/DRDY didn't go low within the timeout
+    Timeout,
 }
 
 pub type ConversionResult<E> = core::result::Result<i16, Error<E>>;
@@ -101,6 +105,24 @@ impl Into<u8> for Mode {
     }
 }
 
+/// DOUT/DRDY polls before a conversion is given up on. A poll takes about 100 µs at the 12 MHz CPU
+/// clock, so this is about 30 ms or four conversion periods at 128 SPS. Slower data rates need
+/// `set_drdy_timeout`.
+pub const DEFAULT_DRDY_TIMEOUT_POLLS: u32 = 300;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConversionMode {
+    SingleShot,
+    Continuous,
+}
+
+/// The 3-bit data rate field, whose meaning depends on the variant
+pub trait DataRateCode: Copy + Debug {
+    fn code(self) -> u8;
+}
+
+/// ADS1018 data rates
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum DataRate {
     _128SPS,
     _250SPS,
@@ -111,62 +133,110 @@ pub enum DataRate {
     _3300SPS,
 }
 
-#[derive(Debug, Copy, Clone)]
-enum RawDataRate {
+impl DataRateCode for DataRate {
+    fn code(self) -> u8 {
+        match self {
+            DataRate::_128SPS => 0b000u8,
+            DataRate::_250SPS => 0b001u8,
+            DataRate::_490SPS => 0b010u8,
+            DataRate::_920SPS => 0b011u8,
+            DataRate::_1600SPS => 0b100u8,
+            DataRate::_2400SPS => 0b101u8,
+            DataRate::_3300SPS => 0b110u8,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ADS1118DataRate {
+    _8SPS,
+    _16SPS,
+    _32SPS,
+    _64SPS,
     _128SPS,
     _250SPS,
-    _490SPS,
-    _920SPS,
-    _1600SPS,
-    _2400SPS,
-    _3300SPS,
-    Invalid,
+    _475SPS,
+    _860SPS,
 }
 
-impl From<DataRate> for RawDataRate {
-    fn from(dr: DataRate) -> Self {
-        match dr {
-            DataRate::_128SPS => RawDataRate::_128SPS,
-            DataRate::_250SPS => RawDataRate::_250SPS,
-            DataRate::_490SPS => RawDataRate::_490SPS,
-            DataRate::_920SPS => RawDataRate::_920SPS,
-            DataRate::_1600SPS => RawDataRate::_1600SPS,
-            DataRate::_2400SPS => RawDataRate::_2400SPS,
-            DataRate::_3300SPS => RawDataRate::_3300SPS,
+impl DataRateCode for ADS1118DataRate {
+    fn code(self) -> u8 {
+        match self {
+            ADS1118DataRate::_8SPS => 0b000u8,
+            ADS1118DataRate::_16SPS => 0b001u8,
+            ADS1118DataRate::_32SPS => 0b010u8,
+            ADS1118DataRate::_64SPS => 0b011u8,
+            ADS1118DataRate::_128SPS => 0b100u8,
+            ADS1118DataRate::_250SPS => 0b101u8,
+            ADS1118DataRate::_475SPS => 0b110u8,
+            ADS1118DataRate::_860SPS => 0b111u8,
         }
     }
 }
 
+#[derive(Debug, Copy, Clone)]
+struct RawDataRate(u8);
+
 impl From<u8> for RawDataRate {
     fn from(v: u8) -> Self {
-        match v {
-            0b000u8 => RawDataRate::_128SPS,
-            0b001u8 => RawDataRate::_250SPS,
-            0b010u8 => RawDataRate::_490SPS,
-            0b011u8 => RawDataRate::_920SPS,
-            0b100u8 => RawDataRate::_1600SPS,
-            0b101u8 => RawDataRate::_2400SPS,
-            0b110u8 => RawDataRate::_3300SPS,
-            _ => RawDataRate::Invalid,
-        }
+        RawDataRate(v)
     }
 }
 
 impl Into<u8> for RawDataRate {
     fn into(self) -> u8 {
-        match self {
-            RawDataRate::_128SPS => 0b000u8,
-            RawDataRate::_250SPS => 0b001u8,
-            RawDataRate::_490SPS => 0b010u8,
-            RawDataRate::_920SPS => 0b011u8,
-            RawDataRate::_1600SPS => 0b100u8,
-            RawDataRate::_2400SPS => 0b101u8,
-            RawDataRate::_3300SPS => 0b110u8,
-            RawDataRate::Invalid => 0b111u8,
-        }
+        self.0
+    }
+}
+
+/// What sets the parts of the family apart, they share the protocol and the config register
+pub trait Variant: Copy + Debug {
+    /// Of a conversion result, left-justified in the 16-bit data word
+    const RESOLUTION_BITS: u32;
+    /// Of a temperature sensor result, also left-justified
+    const TEMPERATURE_BITS: u32;
+    /// ⁰C per LSB of a temperature sensor result
+    const TEMPERATURE_LSB: f64;
+
+    type DataRate: DataRateCode;
+
+    /// Results saturate at the ends of the range
+    fn is_clipped(raw: i16) -> bool {
+        let full_scale = 1i32 << (Self::RESOLUTION_BITS - 1);
+        raw as i32 >= full_scale - 1 || raw as i32 <= -full_scale
+    }
+
+    fn to_volts(gain: Gain, raw: i16) -> f64 {
+        raw as f64 * gain.full_scale() / (1u32 << (Self::RESOLUTION_BITS - 1)) as f64
+    }
+
+    fn to_celsius(raw: i16) -> f64 {
+        raw as f64 * Self::TEMPERATURE_LSB
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ADS1018Variant;
+
+impl Variant for ADS1018Variant {
+    const RESOLUTION_BITS: u32 = 12;
+    const TEMPERATURE_BITS: u32 = 12;
+    const TEMPERATURE_LSB: f64 = 0.125;
+
+    type DataRate = DataRate;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ADS1118Variant;
+
+impl Variant for ADS1118Variant {
+    const RESOLUTION_BITS: u32 = 16;
+    const TEMPERATURE_BITS: u32 = 14;
+    const TEMPERATURE_LSB: f64 = 0.03125;
+
+    type DataRate = ADS1118DataRate;
+}
+
 #[derive(Debug, Copy, Clone)]
 enum TsMode {
     ADC,
@@ -192,6 +262,7 @@ impl Into<u8> for TsMode {
 }
 
 #[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Gain {
     FSR_6_144V,
     FSR_4_096V,
@@ -201,6 +272,102 @@ pub enum Gain {
     FSR_0_256V,
 }
 
+impl Gain {
+    /// Coarsest first
+    pub const ALL: [Gain; 6] = [
+        Gain::FSR_6_144V,
+        Gain::FSR_4_096V,
+        Gain::FSR_2_048V,
+        Gain::FSR_1_024V,
+        Gain::FSR_0_512V,
+        Gain::FSR_0_256V,
+    ];
+
+    /// Volts at positive full scale
+    pub fn full_scale(self) -> f64 {
+        match self {
+            Gain::FSR_6_144V => 6.144,
+            Gain::FSR_4_096V => 4.096,
+            Gain::FSR_2_048V => 2.048,
+            Gain::FSR_1_024V => 1.024,
+            Gain::FSR_0_512V => 0.512,
+            Gain::FSR_0_256V => 0.256,
+        }
+    }
+
+    fn coarser(self) -> Option<Gain> {
+        (self as usize).checked_sub(1).map(|ix| Gain::ALL[ix])
+    }
+
+    fn finer(self) -> Option<Gain> {
+        Gain::ALL.get(self as usize + 1).copied()
+    }
+}
+
+/// Above this fraction of the full scale the next read ranges up, before it clips
+const RANGE_UP_FRACTION: f64 = 0.9;
+/// Below this fraction of the next finer full scale the next read ranges down. Well under
+/// `RANGE_UP_FRACTION`, so that a reading near a boundary doesn't toggle between two ranges.
+const RANGE_DOWN_FRACTION: f64 = 0.45;
+
+/// Per-channel PGA selection, based on the previous reading
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AutoRange<V: Variant = ADS1018Variant> {
+    gain: Gain,
+    _variant: PhantomData<V>,
+}
+
+impl<V: Variant> AutoRange<V> {
+    /// Starts at the coarsest range, which can't clip
+    pub fn new() -> Self {
+        Self::with_gain(Gain::FSR_6_144V)
+    }
+
+    pub fn with_gain(gain: Gain) -> Self {
+        Self { gain, _variant: PhantomData }
+    }
+
+    /// The range for the next conversion
+    pub fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    /// Picks the range for the next conversion from `raw`, converted at `gain()`
+    pub fn update(&mut self, raw: i16) {
+        let volts = V::to_volts(self.gain, raw).abs();
+        if V::is_clipped(raw) || volts > RANGE_UP_FRACTION * self.gain.full_scale() {
+            self.gain = self.gain.coarser().unwrap_or(self.gain);
+        } else if let Some(finer) = self.gain.finer() {
+            if volts < RANGE_DOWN_FRACTION * finer.full_scale() {
+                self.gain = finer;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RangedReading<V: Variant = ADS1018Variant> {
+    pub raw: i16,
+    /// The range `raw` was converted at
+    pub gain: Gain,
+    _variant: PhantomData<V>,
+}
+
+impl<V: Variant> RangedReading<V> {
+    pub fn new(raw: i16, gain: Gain) -> Self {
+        Self { raw, gain, _variant: PhantomData }
+    }
+
+    pub fn volts(&self) -> f64 {
+        V::to_volts(self.gain, self.raw)
+    }
+
+    /// Clipped even at the coarsest range
+    pub fn is_clipped(&self) -> bool {
+        V::is_clipped(self.raw)
+    }
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Debug, Copy, Clone)]
 enum PGA {
@@ -327,7 +494,12 @@ impl Clone for ConfigRegister {
 }
 
 impl ConfigRegister {
-    pub fn new(channel: Channel, gain: Gain, data_rate: DataRate) -> ConfigRegister {
+    pub fn new<R: DataRateCode>(channel: Channel, gain: Gain, data_rate: R) -> ConfigRegister {
+        Self::new_with_mode(channel, gain, data_rate, ConversionMode::SingleShot)
+    }
+
+    /// In single-shot mode writing the register also starts a conversion
+    pub fn new_with_mode<R: DataRateCode>(channel: Channel, gain: Gain, data_rate: R, mode: ConversionMode) -> ConfigRegister {
         let mut cfg_reg = ConfigRegister::default();
         cfg_reg.set_reserved(ReservedBit::Valid);
         cfg_reg.set_nop(Nop::Valid);
@@ -371,15 +543,52 @@ impl ConfigRegister {
                 cfg_reg.set_mux(Mux::P0N1);
             }
         }
-        cfg_reg.set_data_rate(data_rate.into());
-        cfg_reg.set_mode(Mode::SingleShot);
+        cfg_reg.set_data_rate(data_rate.code().into());
+        cfg_reg.set_mode(match mode {
+            ConversionMode::SingleShot => Mode::SingleShot,
+            ConversionMode::Continuous => Mode::Continuous,
+        });
         cfg_reg.set_pga(gain.into());
-        cfg_reg.set_singleshot_start(true);
+        cfg_reg.set_singleshot_start(mode == ConversionMode::SingleShot);
+
+        cfg_reg
+    }
 
+    /// Single-shot mode without starting a conversion, which powers the ADC down
+    fn new_power_down() -> ConfigRegister {
+        let mut cfg_reg = Self::new(Channel::Channel0, Gain::FSR_2_048V, DataRate::_128SPS);
+        cfg_reg.set_singleshot_start(false);
         cfg_reg
     }
+
+    /// The start bit reads back as 0 while converting, and the reserved bit as either
+    fn check_readback<E: Debug>(&self, applied_config: u16) -> Result<(), Error<E>> {
+        let applied_config = applied_config & 0x7fff | 0x0001;
+        let expected_config = self.0 & 0x7fff | 0x0001;
+        if applied_config != expected_config {
+            return Err(Error::ConfigValidationMismatch {
+                desired_config: ConfigRegister(expected_config),
+                actual_config: ConfigRegister(applied_config),
+            });
+        }
+        Ok(())
+    }
 }
 
+/// Results narrower than 16 bits are left-justified, the bits below them are always 0
+fn convert<V: Variant, E: Debug>(value: i16, temperature: bool) -> ConversionResult<E> {
+    let bits = if temperature { V::TEMPERATURE_BITS } else { V::RESOLUTION_BITS };
+    let shift = 16 - bits;
+    if value & ((1 << shift) - 1) != 0 {
+        Err(Error::InvalidConversionValue {
+            raw_value: value,
+        })
+    } else {
+        Ok(value >> shift)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Channel {
     Channel0,
     Channel1,
@@ -407,6 +616,7 @@ impl From<ExternalChannel> for Channel {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ExternalChannel {
     Channel0,
     Channel1,
@@ -418,187 +628,738 @@ pub enum ExternalChannel {
     Channel2Against3,
 }
 
-pub struct ADS1018<SPI, CS: OutputPin, MISO: InputPin> where
+/// The protocol both front ends share: config encoding, readback validation and result decoding.
+/// The front ends only move the frames.
+struct Protocol<V: Variant> {
+    drdy_timeout_polls: u32,
+    /// Of the config last applied
+    mode: ConversionMode,
+    /// The conversion in progress is of the temperature sensor, which has its own format
+    temperature: bool,
+    _variant: PhantomData<V>,
+}
+
+impl<V: Variant> Protocol<V> {
+    fn new() -> Self {
+        Self {
+            drdy_timeout_polls: DEFAULT_DRDY_TIMEOUT_POLLS,
+            mode: ConversionMode::SingleShot,
+            temperature: false,
+            _variant: PhantomData,
+        }
+    }
+
+    /// Keeps the conversion mode
+    fn pipelined(&self, channel: Channel, gain: Gain, data_rate: V::DataRate) -> ConfigRegister {
+        ConfigRegister::new_with_mode(channel, gain, data_rate, self.mode)
+    }
+
+    /// One 32-bit frame: the conversion result is shifted out while `cfg_reg` goes in, followed by
+    /// the config register readback. Without `cfg_reg` the NOP bits are invalid and nothing is written.
+    fn encode(cfg_reg: Option<&ConfigRegister>) -> [u8; 4] {
+        let mut buf = [0u8; 4];
+        byteorder::BE::write_u16(&mut buf[0..2], cfg_reg.map(|c| c.0).unwrap_or(0));
+        buf
+    }
+
+    /// The conversion result and the config readback
+    fn decode(buf: &[u8]) -> (i16, u16) {
+        (byteorder::BE::read_i16(&buf[0..2]), byteorder::BE::read_u16(&buf[2..4]))
+    }
+
+    /// Validates the readback of a written config, which then applies to the conversions from now on
+    fn applied<E: Debug>(&mut self, cfg_reg: &ConfigRegister, applied_config: u16) -> Result<(), Error<E>> {
+        cfg_reg.check_readback(applied_config)?;
+        self.mode = match cfg_reg.mode() {
+            Mode::Continuous => ConversionMode::Continuous,
+            Mode::SingleShot => ConversionMode::SingleShot,
+        };
+        self.temperature = matches!(cfg_reg.ts_mode(), TsMode::TemperatureSensor);
+        Ok(())
+    }
+
+    /// Of the conversion in progress
+    fn convert<E: Debug>(&self, value: i16) -> ConversionResult<E> {
+        convert::<V, E>(value, self.temperature)
+    }
+
+    /// DOUT/DRDY goes low once a result is ready, but only shows with CS low
+    fn poll_drdy<CS: OutputPin, MISO: InputPin>(cs: &mut CS, miso: &MISO) -> bool {
+        let mut delay = hal::BusyWaitTimer::new(100);
+        let _cs_active = cs.hold_low();
+        delay.wait().ok();
+        miso.is_low().unwrap_or(false)
+    }
+}
+
+/// Either single-shot, where every read configures and starts its own conversion, or continuous.
+/// `read_pipelined` reads the result of the conversion in progress while writing the config for
+/// the next one, in either mode.
+pub struct ADS1x18<SPI, CS: OutputPin, MISO: InputPin, V: Variant = ADS1018Variant> where
     SPI: embedded_hal::blocking::spi::Transfer<u8>, SPI::Error: Debug
 {
     spi: SPI,
     cs: CS,
     miso: MISO,
+    protocol: Protocol<V>,
+    /// Between DRDY polls, for what mustn't wait out a whole conversion
+    idle: fn(),
 }
 
-impl<SPI, CS: OutputPin, MISO: InputPin> ADS1018<SPI, CS, MISO>
+pub type ADS1018<SPI, CS, MISO> = ADS1x18<SPI, CS, MISO, ADS1018Variant>;
+pub type ADS1118<SPI, CS, MISO> = ADS1x18<SPI, CS, MISO, ADS1118Variant>;
+
+impl<SPI, CS: OutputPin, MISO: InputPin, V: Variant> ADS1x18<SPI, CS, MISO, V>
     where SPI: embedded_hal::blocking::spi::Transfer<u8>, SPI::Error: Debug
 {
     pub fn new(spi: SPI, cs: CS, miso: MISO) -> Self {
-        Self { spi, cs, miso }
+        Self { spi, cs, miso, protocol: Protocol::new(), idle: || {} }
+    }
+
+    pub fn set_drdy_timeout(&mut self, polls: u32) {
+        self.protocol.drdy_timeout_polls = polls;
+    }
+
+    pub fn set_idle(&mut self, idle: fn()) {
+        self.idle = idle;
     }
 
-    pub fn read_temperature(&mut self, data_rate: DataRate) -> ConversionResult<SPI::Error> {
+    pub fn read_temperature(&mut self, data_rate: V::DataRate) -> ConversionResult<SPI::Error> {
         self.read_raw(Channel::Temperature, Gain::FSR_2_048V, data_rate)
     }
 
-    pub fn read_channel(&mut self, channel: ExternalChannel, gain: Gain, data_rate: DataRate) -> ConversionResult<SPI::Error> {
+    pub fn read_channel(&mut self, channel: ExternalChannel, gain: Gain, data_rate: V::DataRate) -> ConversionResult<SPI::Error> {
         self.read_raw(channel.into(), gain, data_rate)
     }
 
-    fn read_raw(&mut self, channel: Channel, gain: Gain, data_rate: DataRate) -> ConversionResult<SPI::Error> {
-        let mut delay = hal::BusyWaitTimer::new(10);
+    /// Converts at the range picked by the previous reading. A clipped reading is retaken right
+    /// away at the coarser range.
+    pub fn read_auto_ranged(&mut self, channel: ExternalChannel, range: &mut AutoRange<V>, data_rate: V::DataRate) -> Result<RangedReading<V>, Error<SPI::Error>> {
+        loop {
+            let gain = range.gain();
+            let raw = self.read_channel(channel, gain, data_rate)?;
+            range.update(raw);
+            if !V::is_clipped(raw) || range.gain() == gain {
+                return Ok(RangedReading::new(raw, gain));
+            }
+        }
+    }
+
+    /// Subsequent conversions are read with `read_continuous` or `read_pipelined`
+    pub fn start_continuous(&mut self, channel: Channel, gain: Gain, data_rate: V::DataRate) -> Result<(), Error<SPI::Error>> {
+        self.configure(ConfigRegister::new_with_mode(channel, gain, data_rate, ConversionMode::Continuous))
+    }
+
+    pub fn stop_continuous(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.configure(ConfigRegister::new_power_down())
+    }
 
-        let cfg_reg = ConfigRegister::new(channel, gain, data_rate);
+    /// The next result in continuous mode
+    pub fn read_continuous(&mut self) -> ConversionResult<SPI::Error> {
+        self.wait_drdy()?;
+        let (value, _) = self.transfer_frame(None)?;
+        self.protocol.convert(value)
+    }
+
+    /// Reads the conversion in progress and switches to `channel` for the next one, keeping the
+    /// conversion mode. In single-shot mode a conversion must have been started by a previous
+    /// `read_pipelined`.
+    pub fn read_pipelined(&mut self, channel: Channel, gain: Gain, data_rate: V::DataRate) -> ConversionResult<SPI::Error> {
+        let cfg_reg = self.protocol.pipelined(channel, gain, data_rate);
+        self.wait_drdy()?;
+        let (value, applied_config) = self.transfer_frame(Some(&cfg_reg))?;
+        let result = self.protocol.convert(value);
+        self.protocol.applied(&cfg_reg, applied_config)?;
+        result
+    }
+
+    /// A single-shot conversion of any channel, including the temperature sensor
+    pub fn read_raw(&mut self, channel: Channel, gain: Gain, data_rate: V::DataRate) -> ConversionResult<SPI::Error> {
+        self.configure(ConfigRegister::new(channel, gain, data_rate))?;
+        self.read_continuous()
+    }
+
+    fn configure(&mut self, cfg_reg: ConfigRegister) -> Result<(), Error<SPI::Error>> {
+        let (_, applied_config) = self.transfer_frame(Some(&cfg_reg))?;
+        self.protocol.applied(&cfg_reg, applied_config)
+    }
 
-        let mut buf = [0u8, 0u8];
-        byteorder::BE::write_u16(&mut buf, cfg_reg.0);
+    fn transfer_frame(&mut self, cfg_reg: Option<&ConfigRegister>) -> Result<(i16, u16), Error<SPI::Error>> {
+        let mut delay = hal::BusyWaitTimer::new(10);
+        let mut buf = Protocol::<V>::encode(cfg_reg);
 
-        self.cs.set_low().ok();
+        let _cs_active = self.cs.hold_low();
         delay.wait().ok();
-        self.spi.transfer(&mut buf).ok();
-        byteorder::BE::write_u16(&mut buf, 0);
-        if let Ok(applied_config) = self.spi.transfer(&mut buf) {
-            let applied_config = byteorder::BE::read_u16(applied_config) & 0x7fff | 0x0001;
-            let expected_config = cfg_reg.0 & 0x7fff | 0x0001;
-            if applied_config != expected_config {
-                self.cs.set_high().ok();
-                return Err(Error::ConfigValidationMismatch {
-                    desired_config: ConfigRegister(expected_config),
-                    actual_config: ConfigRegister(applied_config),
-                });
-            }
-        }
-        self.cs.set_high().ok();
+        self.spi.transfer(&mut buf)
+            .map(Protocol::<V>::decode)
+            .map_err(|error| Error::BusError { error })
+    }
 
-        let mut large_delay = hal::BusyWaitTimer::new(100);
+    fn wait_drdy(&mut self) -> Result<(), Error<SPI::Error>> {
+        let mut delay = hal::BusyWaitTimer::new(10);
 
-        loop {
-            self.cs.set_low().ok();
-            large_delay.wait().ok();
-            if self.miso.is_low().unwrap_or(false) {
-                self.cs.set_high().ok();
+        for _ in 0..self.protocol.drdy_timeout_polls {
+            if Protocol::<V>::poll_drdy(&mut self.cs, &self.miso) {
                 delay.wait().ok();
-                self.cs.set_low().ok();
-                delay.wait().ok();
-                byteorder::BE::write_u16(&mut buf, 0);
-                let measurement = self.spi.transfer(&mut buf);
-                self.cs.set_high().ok();
-                return match measurement {
-                    Ok(measurement) => {
-                        let value = byteorder::BigEndian::read_i16(measurement);
-                        let actual_value = value >> 4;
-                        if value & 0b1111 != 0 {
-                            Err(Error::InvalidConversionValue {
-                                raw_value: value,
-                            })
-                        } else {
-                            Ok(actual_value)
-                        }
-                    },
-                    Err(error) => {
-                        Err(Error::BusError { error })
-                    }
-                }
-            } else {
-                self.cs.set_high().ok();
+                return Ok(());
             }
+            (self.idle)();
         }
+        Err(Error::Timeout)
     }
 }
 
-pub struct ADS1018Async<SPI, CS: OutputPin, MISO: InputPin> where
+pub struct ADS1x18Async<SPI, CS: OutputPin, MISO: InputPin, V: Variant = ADS1018Variant> where
     SPI: embedded_hal::spi::FullDuplex<u8>, SPI::Error: Debug
 {
     spi: SPI,
     cs: CS,
     miso: MISO,
+    protocol: Protocol<V>,
 }
 
-impl<SPI, CS: OutputPin, MISO: InputPin> ADS1018Async<SPI, CS, MISO>
+pub type ADS1018Async<SPI, CS, MISO> = ADS1x18Async<SPI, CS, MISO, ADS1018Variant>;
+pub type ADS1118Async<SPI, CS, MISO> = ADS1x18Async<SPI, CS, MISO, ADS1118Variant>;
+
+impl<SPI, CS: OutputPin, MISO: InputPin, V: Variant> ADS1x18Async<SPI, CS, MISO, V>
     where SPI: embedded_hal::spi::FullDuplex<u8>, SPI::Error: Debug
 {
     pub fn new(spi: SPI, cs: CS, miso: MISO) -> Self {
-        Self { spi, cs, miso }
+        Self { spi, cs, miso, protocol: Protocol::new() }
+    }
+
+    pub fn set_drdy_timeout(self: Pin<&mut Self>, polls: u32) {
+        self.pin_get_io().3.drdy_timeout_polls = polls;
     }
 
-    pub async fn read_temperature(self: Pin<&mut Self>, data_rate: DataRate) -> ConversionResult<SPI::Error> {
+    pub async fn read_temperature(self: Pin<&mut Self>, data_rate: V::DataRate) -> ConversionResult<SPI::Error> {
         self.read_raw(Channel::Temperature, Gain::FSR_2_048V, data_rate).await
     }
 
-    pub async fn read_channel(self: Pin<&mut Self>, channel: ExternalChannel, gain: Gain, data_rate: DataRate) -> ConversionResult<SPI::Error> {
+    pub async fn read_channel(self: Pin<&mut Self>, channel: ExternalChannel, gain: Gain, data_rate: V::DataRate) -> ConversionResult<SPI::Error> {
         self.read_raw(channel.into(), gain, data_rate).await
     }
 
-    fn pin_get_io<'a>(self: Pin<&'a mut Self>) -> (&'a mut SPI, &'a mut CS, &'a mut MISO) {
+    /// See `ADS1x18::read_auto_ranged`
+    pub async fn read_auto_ranged(mut self: Pin<&mut Self>, channel: ExternalChannel, range: &mut AutoRange<V>, data_rate: V::DataRate) -> Result<RangedReading<V>, Error<SPI::Error>> {
+        loop {
+            let gain = range.gain();
+            let raw = self.as_mut().read_channel(channel, gain, data_rate).await?;
+            range.update(raw);
+            if !V::is_clipped(raw) || range.gain() == gain {
+                return Ok(RangedReading::new(raw, gain));
+            }
+        }
+    }
+
+    /// See `ADS1x18::start_continuous`
+    pub async fn start_continuous(self: Pin<&mut Self>, channel: Channel, gain: Gain, data_rate: V::DataRate) -> Result<(), Error<SPI::Error>> {
+        let (spi, cs, _, protocol) = self.pin_get_io();
+        let cfg_reg = ConfigRegister::new_with_mode(channel, gain, data_rate, ConversionMode::Continuous);
+        Self::configure(spi, cs, protocol, cfg_reg).await
+    }
+
+    pub async fn stop_continuous(self: Pin<&mut Self>) -> Result<(), Error<SPI::Error>> {
+        let (spi, cs, _, protocol) = self.pin_get_io();
+        Self::configure(spi, cs, protocol, ConfigRegister::new_power_down()).await
+    }
+
+    pub async fn read_continuous(self: Pin<&mut Self>) -> ConversionResult<SPI::Error> {
+        let (spi, cs, miso, protocol) = self.pin_get_io();
+        Self::wait_drdy(cs, miso, protocol.drdy_timeout_polls).await?;
+        let (value, _) = Self::transfer_frame(spi, cs, None).await?;
+        protocol.convert(value)
+    }
+
+    /// See `ADS1x18::read_pipelined`
+    pub async fn read_pipelined(self: Pin<&mut Self>, channel: Channel, gain: Gain, data_rate: V::DataRate) -> ConversionResult<SPI::Error> {
+        let (spi, cs, miso, protocol) = self.pin_get_io();
+        let cfg_reg = protocol.pipelined(channel, gain, data_rate);
+        Self::wait_drdy(cs, miso, protocol.drdy_timeout_polls).await?;
+        let (value, applied_config) = Self::transfer_frame(spi, cs, Some(&cfg_reg)).await?;
+        let result = protocol.convert(value);
+        protocol.applied(&cfg_reg, applied_config)?;
+        result
+    }
+
+    fn pin_get_io<'a>(self: Pin<&'a mut Self>) -> (&'a mut SPI, &'a mut CS, &'a mut MISO, &'a mut Protocol<V>) {
         unsafe {
             let self_mut = self.get_unchecked_mut();
-            (
-                &mut self_mut.spi,
-                &mut self_mut.cs,
-                &mut self_mut.miso,
-            )
+            (&mut self_mut.spi, &mut self_mut.cs, &mut self_mut.miso, &mut self_mut.protocol)
         }
     }
 
-    async fn read_raw(self: Pin<&mut Self>, channel: Channel, gain: Gain, data_rate: DataRate) -> ConversionResult<SPI::Error> {
+    async fn read_raw(mut self: Pin<&mut Self>, channel: Channel, gain: Gain, data_rate: V::DataRate) -> ConversionResult<SPI::Error> {
+        {
+            let (spi, cs, _, protocol) = self.as_mut().pin_get_io();
+            Self::configure(spi, cs, protocol, ConfigRegister::new(channel, gain, data_rate)).await?;
+        }
+        self.read_continuous().await
+    }
+
+    async fn configure(spi: &mut SPI, cs: &mut CS, protocol: &mut Protocol<V>, cfg_reg: ConfigRegister) -> Result<(), Error<SPI::Error>> {
+        let (_, applied_config) = Self::transfer_frame(spi, cs, Some(&cfg_reg)).await?;
+        protocol.applied(&cfg_reg, applied_config)
+    }
+
+    async fn transfer_frame(spi: &mut SPI, cs: &mut CS, cfg_reg: Option<&ConfigRegister>) -> Result<(i16, u16), Error<SPI::Error>> {
         let mut delay = hal::BusyWaitTimer::new(10);
-        let (spi, cs, miso): (&mut SPI, &mut CS, &mut MISO) = self.pin_get_io();
+        let mut buf = Protocol::<V>::encode(cfg_reg);
 
-        let cfg_reg = ConfigRegister::new(channel, gain, data_rate);
+        let _cs_active = cs.hold_low();
+        delay.wait().ok();
+        spi.transfer(&mut buf).await.map_err(|error| Error::BusError { error })?;
+        Ok(Protocol::<V>::decode(&buf))
+    }
 
-        let buf = [0u8, 0u8];
-        pin_mut!(buf);
+    /// Polls DOUT/DRDY once per wakeup, so that other tasks get to run in between
+    async fn wait_drdy(cs: &mut CS, miso: &mut MISO, drdy_timeout_polls: u32) -> Result<(), Error<SPI::Error>> {
+        let mut delay = hal::BusyWaitTimer::new(10);
+        let mut polls = 0;
 
-        byteorder::BE::write_u16(&mut *buf, cfg_reg.0);
+        futures::future::poll_fn(|cx| {
+            if polls >= drdy_timeout_polls {
+                return Poll::Ready(Err(Error::Timeout));
+            }
+            polls += 1;
+            if Protocol::<V>::poll_drdy(cs, miso) {
+                delay.wait().ok();
+                Poll::Ready(Ok(()))
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }).await
+    }
+}
 
-        {
-            let _ = cs.hold_low();
-            for b in buf.iter() {
-                nb_future!(spi.send(*b)).await.map_err(|error| Error::BusError { error })?;
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use byteorder::ByteOrder;
+    use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+    use crate::ads1018::{ADS1018Variant, ADS1118Variant, ADS1x18, ADS1x18Async, AutoRange, Channel,
+                         ConfigRegister, ConversionMode, ConversionResult, DataRate, Error, ExternalChannel,
+                         Gain, RangedReading, Variant, convert};
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        CsLow,
+        CsHigh,
+        /// DOUT/DRDY sampled, ready or not
+        Drdy(bool),
+        /// The bytes sent in one frame
+        Frame(Vec<u8>),
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct BusFault;
+
+    /// What the ADC answers, frame by frame
+    enum Reply {
+        Frame([u8; 4]),
+        Fault,
+    }
+
+    #[derive(Default)]
+    struct Script {
+        events: Vec<Event>,
+        replies: VecDeque<Reply>,
+        /// Not ready once these run out
+        drdy: VecDeque<bool>,
+        /// The full-duplex frame in progress
+        sent: Vec<u8>,
+        received: VecDeque<u8>,
+        stalled: bool,
+    }
+
+    type Shared = Rc<RefCell<Script>>;
+
+    struct MockSpi(Shared);
+    struct MockCs(Shared);
+    struct MockMiso(Shared);
+
+    impl OutputPin for MockCs {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().events.push(Event::CsLow);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().events.push(Event::CsHigh);
+            Ok(())
+        }
+    }
+
+    impl InputPin for MockMiso {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Infallible> {
+            self.is_low().map(|low| !low)
+        }
+
+        fn is_low(&self) -> Result<bool, Infallible> {
+            let mut script = self.0.borrow_mut();
+            let ready = script.drdy.pop_front().unwrap_or(false);
+            script.events.push(Event::Drdy(ready));
+            Ok(ready)
+        }
+    }
+
+    impl embedded_hal::blocking::spi::Transfer<u8> for MockSpi {
+        type Error = BusFault;
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], BusFault> {
+            let mut script = self.0.borrow_mut();
+            script.events.push(Event::Frame(words.to_vec()));
+            match script.replies.pop_front().expect("unexpected frame") {
+                Reply::Frame(reply) => {
+                    words.copy_from_slice(&reply);
+                    Ok(words)
+                }
+                Reply::Fault => Err(BusFault),
             }
+        }
+    }
 
-            byteorder::BE::write_u16(&mut *buf, 0);
+    /// Stalls every other call, so that the async front end has to wait for the bus
+    impl embedded_hal::spi::FullDuplex<u8> for MockSpi {
+        type Error = BusFault;
 
-            spi.transfer(&mut *buf).await.map_err(|error| Error::BusError { error })?;
+        fn read(&mut self) -> nb::Result<u8, BusFault> {
+            let mut script = self.0.borrow_mut();
+            script.stalled = !script.stalled;
+            if script.stalled {
+                return Err(nb::Error::WouldBlock);
+            }
+            Ok(script.received.pop_front().expect("read without send"))
         }
 
-        let applied_config = byteorder::BE::read_u16(&*buf) & 0x7fff | 0x0001;
-        let expected_config = cfg_reg.0 & 0x7fff | 0x0001;
-        if applied_config != expected_config {
-            return Err(Error::ConfigValidationMismatch {
-                desired_config: ConfigRegister(expected_config),
-                actual_config: ConfigRegister(applied_config),
-            });
+        fn send(&mut self, word: u8) -> nb::Result<(), BusFault> {
+            let mut script = self.0.borrow_mut();
+            if script.sent.is_empty() {
+                match script.replies.pop_front().expect("unexpected frame") {
+                    Reply::Frame(reply) => script.received = reply.iter().copied().collect(),
+                    Reply::Fault => {
+                        script.events.push(Event::Frame(vec![word]));
+                        return Err(nb::Error::Other(BusFault));
+                    }
+                }
+            }
+            script.sent.push(word);
+            if script.sent.len() == 4 {
+                let frame = core::mem::take(&mut script.sent);
+                script.events.push(Event::Frame(frame));
+            }
+            Ok(())
         }
+    }
+
+    type Blocking<V = ADS1018Variant> = ADS1x18<MockSpi, MockCs, MockMiso, V>;
+    type NonBlocking<V = ADS1018Variant> = ADS1x18Async<MockSpi, MockCs, MockMiso, V>;
 
-        let mut large_delay = hal::BusyWaitTimer::new(100);
+    fn script(replies: Vec<Reply>, drdy: Vec<bool>) -> Shared {
+        Rc::new(RefCell::new(Script {
+            replies: replies.into(),
+            drdy: drdy.into(),
+            ..Default::default()
+        }))
+    }
+
+    fn blocking<V: Variant>(script: &Shared) -> Blocking<V> {
+        ADS1x18::new(MockSpi(script.clone()), MockCs(script.clone()), MockMiso(script.clone()))
+    }
 
+    fn non_blocking<V: Variant>(script: &Shared) -> NonBlocking<V> {
+        ADS1x18Async::new(MockSpi(script.clone()), MockCs(script.clone()), MockMiso(script.clone()))
+    }
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut f = Box::pin(f);
         loop {
-            {
-                let _ = cs.hold_low();
-                large_delay.wait().ok();
-                if !miso.is_low().unwrap_or(false) {
-                    continue
-                }
+            if let Poll::Ready(output) = f.as_mut().poll(&mut cx) {
+                return output;
             }
+        }
+    }
 
-            delay.wait().ok();
+    /// A frame as the ADC shifts it out
+    fn reply(data: i16, config: u16) -> Reply {
+        let mut frame = [0u8; 4];
+        byteorder::BE::write_i16(&mut frame[0..2], data);
+        byteorder::BE::write_u16(&mut frame[2..4], config);
+        Reply::Frame(frame)
+    }
 
-            {
-                let cs_active = cs.hold_low();
-                delay.wait().ok();
-                byteorder::BE::write_u16(&mut *buf, 0);
-
-                spi.transfer(&mut *buf).await.map_err(|error| Error::BusError { error })?;
-                drop(cs_active);
-
-                let value = byteorder::BigEndian::read_i16(&*buf);
-                let actual_value = value >> 4;
-                return if value & 0b1111 != 0 {
-                    Err(Error::InvalidConversionValue {
-                        raw_value: value,
-                    })
-                } else {
-                    Ok(actual_value)
+    /// The start bit reads back as 0 while converting
+    fn converting(config: &ConfigRegister) -> u16 {
+        config.0 & 0x7fff
+    }
+
+    fn frame(config: Option<&ConfigRegister>) -> Event {
+        let config = config.map(|c| c.0).unwrap_or(0);
+        Event::Frame(vec![(config >> 8) as u8, config as u8, 0, 0])
+    }
+
+    fn events(script: &Shared) -> Vec<Event> {
+        core::mem::take(&mut script.borrow_mut().events)
+    }
+
+    #[test]
+    fn single_shot_framing() {
+        let config = ConfigRegister::new(Channel::Channel1, Gain::FSR_4_096V, DataRate::_128SPS);
+        let expected = vec![
+            Event::CsLow, frame(Some(&config)), Event::CsHigh,
+            Event::CsLow, Event::Drdy(false), Event::CsHigh,
+            Event::CsLow, Event::Drdy(true), Event::CsHigh,
+            Event::CsLow, frame(None), Event::CsHigh,
+        ];
+        let new_script = || script(vec![reply(0, converting(&config)), reply(-1000 << 4, converting(&config))],
+                                   vec![false, true]);
+
+        let s = new_script();
+        let reading = blocking::<ADS1018Variant>(&s)
+            .read_channel(ExternalChannel::Channel1, Gain::FSR_4_096V, DataRate::_128SPS);
+        assert_eq!(Ok(-1000), reading.map_err(|_| ()));
+        assert_eq!(expected, events(&s));
+
+        let s = new_script();
+        let mut adc = non_blocking::<ADS1018Variant>(&s);
+        let reading = block_on(Pin::new(&mut adc)
+            .read_channel(ExternalChannel::Channel1, Gain::FSR_4_096V, DataRate::_128SPS));
+        assert_eq!(Ok(-1000), reading.map_err(|_| ()));
+        assert_eq!(expected, events(&s));
+    }
+
+    #[test]
+    fn config_validation_mismatch() {
+        let config = ConfigRegister::new(Channel::Channel0, Gain::FSR_2_048V, DataRate::_128SPS);
+        let wrong = ConfigRegister::new(Channel::Channel0, Gain::FSR_0_256V, DataRate::_128SPS);
+        let check = |s: &Shared, reading: ConversionResult<BusFault>| {
+            match reading {
+                Err(Error::ConfigValidationMismatch { desired_config, actual_config }) => {
+                    assert_eq!(converting(&config) | 1, desired_config.0);
+                    assert_eq!(converting(&wrong) | 1, actual_config.0);
                 }
+                r => panic!("{:?}", r),
             }
+            // no conversion is waited for
+            assert_eq!(vec![Event::CsLow, frame(Some(&config)), Event::CsHigh], events(s));
+        };
+
+        let s = script(vec![reply(0, converting(&wrong))], vec![]);
+        check(&s, blocking::<ADS1018Variant>(&s).read_channel(ExternalChannel::Channel0, Gain::FSR_2_048V, DataRate::_128SPS));
+
+        let s = script(vec![reply(0, converting(&wrong))], vec![]);
+        let mut adc = non_blocking::<ADS1018Variant>(&s);
+        check(&s, block_on(Pin::new(&mut adc).read_channel(ExternalChannel::Channel0, Gain::FSR_2_048V, DataRate::_128SPS)));
+    }
+
+    #[test]
+    fn invalid_conversion_value() {
+        let config = ConfigRegister::new(Channel::Temperature, Gain::FSR_2_048V, DataRate::_128SPS);
+        let new_script = || script(vec![reply(0, converting(&config)), reply(0x0123, 0)], vec![true]);
+        let check = |reading: ConversionResult<BusFault>| match reading {
+            Err(Error::InvalidConversionValue { raw_value: 0x0123 }) => {}
+            r => panic!("{:?}", r),
+        };
+
+        check(blocking::<ADS1018Variant>(&new_script()).read_temperature(DataRate::_128SPS));
+        let s = new_script();
+        let mut adc = non_blocking::<ADS1018Variant>(&s);
+        check(block_on(Pin::new(&mut adc).read_temperature(DataRate::_128SPS)));
+    }
+
+    #[test]
+    fn bus_error_releases_cs() {
+        let check = |s: &Shared, reading: ConversionResult<BusFault>| {
+            match reading {
+                Err(Error::BusError { error: BusFault }) => {}
+                r => panic!("{:?}", r),
+            }
+            let events = events(s);
+            assert_eq!(Some(&Event::CsLow), events.first());
+            assert_eq!(Some(&Event::CsHigh), events.last());
+        };
+
+        let s = script(vec![Reply::Fault], vec![]);
+        check(&s, blocking::<ADS1018Variant>(&s).read_temperature(DataRate::_128SPS));
+
+        let s = script(vec![Reply::Fault], vec![]);
+        let mut adc = non_blocking::<ADS1018Variant>(&s);
+        check(&s, block_on(Pin::new(&mut adc).read_temperature(DataRate::_128SPS)));
+    }
+
+    #[test]
+    fn drdy_timeout() {
+        let config = ConfigRegister::new(Channel::Channel3, Gain::FSR_1_024V, DataRate::_128SPS);
+        let mut expected = vec![Event::CsLow, frame(Some(&config)), Event::CsHigh];
+        for _ in 0..3 {
+            expected.extend(vec![Event::CsLow, Event::Drdy(false), Event::CsHigh]);
         }
+        let check = |s: &Shared, reading: ConversionResult<BusFault>| {
+            match reading {
+                Err(Error::Timeout) => {}
+                r => panic!("{:?}", r),
+            }
+            assert_eq!(expected, events(s));
+        };
+
+        static IDLE: AtomicU32 = AtomicU32::new(0);
+        let s = script(vec![reply(0, converting(&config))], vec![]);
+        let mut adc = blocking::<ADS1018Variant>(&s);
+        adc.set_drdy_timeout(3);
+        adc.set_idle(|| { IDLE.fetch_add(1, Ordering::Relaxed); });
+        check(&s, adc.read_channel(ExternalChannel::Channel3, Gain::FSR_1_024V, DataRate::_128SPS));
+        assert_eq!(3, IDLE.load(Ordering::Relaxed));
+
+        let s = script(vec![reply(0, converting(&config))], vec![]);
+        let mut adc = non_blocking::<ADS1018Variant>(&s);
+        Pin::new(&mut adc).set_drdy_timeout(3);
+        check(&s, block_on(Pin::new(&mut adc).read_channel(ExternalChannel::Channel3, Gain::FSR_1_024V, DataRate::_128SPS)));
+    }
+
+    #[test]
+    fn continuous_and_pipelined() {
+        use crate::ads1018::ADS1118DataRate;
+
+        let rate = ADS1118DataRate::_860SPS;
+        let channel = ConfigRegister::new_with_mode(Channel::Channel0Against1, Gain::FSR_0_256V, rate, ConversionMode::Continuous);
+        let temperature = ConfigRegister::new_with_mode(Channel::Temperature, Gain::FSR_2_048V, rate, ConversionMode::Continuous);
+        let new_script = || script(vec![
+            reply(0, channel.0),
+            reply(-12345, channel.0),
+            // still the differential conversion while switching over to the temperature sensor
+            reply(0x0123, temperature.0),
+            reply(800 << 2, temperature.0),
+        ], vec![true, true, true]);
+        let expected_frames = vec![frame(Some(&channel)), frame(None), frame(Some(&temperature)), frame(None)];
+        let frames = |s: &Shared| -> Vec<Event> {
+            events(s).into_iter().filter(|e| match e { Event::Frame(_) => true, _ => false }).collect()
+        };
+
+        let s = new_script();
+        let mut adc = blocking::<ADS1118Variant>(&s);
+        adc.start_continuous(Channel::Channel0Against1, Gain::FSR_0_256V, rate).unwrap();
+        assert_eq!(-12345, adc.read_continuous().unwrap());
+        assert_eq!(0x0123, adc.read_pipelined(Channel::Temperature, Gain::FSR_2_048V, rate).unwrap());
+        assert_eq!(800, adc.read_continuous().unwrap());
+        assert_eq!(expected_frames, frames(&s));
+
+        let s = new_script();
+        let mut adc = non_blocking::<ADS1118Variant>(&s);
+        block_on(Pin::new(&mut adc).start_continuous(Channel::Channel0Against1, Gain::FSR_0_256V, rate)).unwrap();
+        assert_eq!(-12345, block_on(Pin::new(&mut adc).read_continuous()).unwrap());
+        assert_eq!(0x0123, block_on(Pin::new(&mut adc).read_pipelined(Channel::Temperature, Gain::FSR_2_048V, rate)).unwrap());
+        assert_eq!(800, block_on(Pin::new(&mut adc).read_continuous()).unwrap());
+        assert_eq!(expected_frames, frames(&s));
+    }
+
+    fn raw_at(gain: Gain, volts: f64) -> i16 {
+        (volts / gain.full_scale() * 2048.0).round().clamp(-2048.0, 2047.0) as i16
+    }
+
+    /// Feeds `volts` until the range settles
+    fn settle(range: &mut AutoRange, volts: f64) -> Gain {
+        for _ in 0..Gain::ALL.len() {
+            range.update(raw_at(range.gain(), volts));
+        }
+        range.gain()
+    }
+
+    #[test]
+    fn to_volts() {
+        assert_approx_eq!(1.0, ADS1018Variant::to_volts(Gain::FSR_2_048V, 1000), 1e-12);
+        assert_approx_eq!(-0.125, ADS1018Variant::to_volts(Gain::FSR_0_256V, -1000), 1e-12);
+        assert_approx_eq!(1.0, ADS1118Variant::to_volts(Gain::FSR_2_048V, 16000), 1e-12);
+
+        let reading = RangedReading::<ADS1018Variant>::new(2047, Gain::FSR_6_144V);
+        assert_approx_eq!(6.144 * 2047.0 / 2048.0, reading.volts(), 1e-12);
+        assert!(RangedReading::<ADS1018Variant>::new(-2048, Gain::FSR_6_144V).is_clipped());
+        assert!(!RangedReading::<ADS1118Variant>::new(-2048, Gain::FSR_6_144V).is_clipped());
+        assert!(RangedReading::<ADS1118Variant>::new(i16::MAX, Gain::FSR_6_144V).is_clipped());
+    }
+
+    #[test]
+    fn conversion_formats() {
+        let convert_1018 = |value, temperature| -> ConversionResult<()> {
+            convert::<ADS1018Variant, _>(value, temperature)
+        };
+        let convert_1118 = |value, temperature| -> ConversionResult<()> {
+            convert::<ADS1118Variant, _>(value, temperature)
+        };
+
+        assert_eq!(-2, convert_1018(-32, false).unwrap());
+        assert_eq!(200, convert_1018(200 << 4, true).unwrap());
+        match convert_1018(0x0123, false) {
+            Err(Error::InvalidConversionValue { raw_value: 0x0123 }) => {}
+            r => panic!("{:?}", r),
+        }
+
+        assert_eq!(0x0123, convert_1118(0x0123, false).unwrap());
+        assert_eq!(-3, convert_1118(-12, true).unwrap());
+        assert!(convert_1118(0x0123, true).is_err());
+
+        // 25 ⁰C either way
+        assert_approx_eq!(25.0, ADS1018Variant::to_celsius(200), 1e-12);
+        assert_approx_eq!(25.0, ADS1118Variant::to_celsius(800), 1e-12);
+    }
+
+    #[test]
+    fn settles_on_a_range() {
+        let mut range: AutoRange = AutoRange::new();
+        assert_eq!(Gain::FSR_0_256V, settle(&mut range, 0.01));
+        assert_eq!(Gain::FSR_2_048V, settle(&mut range, 1.5));
+        // fits the 1.024 V range, but not by enough to range down to it
+        assert_eq!(Gain::FSR_2_048V, settle(&mut range, 0.9));
+        // differential inputs go negative
+        assert_eq!(Gain::FSR_0_512V, settle(&mut range, -0.2));
+        assert_eq!(Gain::FSR_6_144V, settle(&mut range, 5.0));
+    }
+
+    #[test]
+    fn ranges_up_on_clipping() {
+        let mut range: AutoRange = AutoRange::with_gain(Gain::FSR_0_256V);
+        range.update(2047);
+        assert_eq!(Gain::FSR_0_512V, range.gain());
+        range.update(-2048);
+        assert_eq!(Gain::FSR_1_024V, range.gain());
+
+        let mut range: AutoRange = AutoRange::new();
+        range.update(2047);
+        assert_eq!(Gain::FSR_6_144V, range.gain());
     }
-}
 
+    #[test]
+    fn hysteresis() {
+        // just under the top of the 1.024 V range, ranges up
+        let mut range: AutoRange = AutoRange::with_gain(Gain::FSR_1_024V);
+        range.update(raw_at(Gain::FSR_1_024V, 0.95));
+        assert_eq!(Gain::FSR_2_048V, range.gain());
+
+        // and doesn't come back down for readings between 0.45 and 0.9 of the finer range
+        for volts in [0.95, 0.5, 0.47, 0.9, 0.6].iter() {
+            range.update(raw_at(range.gain(), *volts));
+            assert_eq!(Gain::FSR_2_048V, range.gain(), "{}", volts);
+        }
+        range.update(raw_at(range.gain(), 0.45));
+        assert_eq!(Gain::FSR_1_024V, range.gain());
+    }
+}
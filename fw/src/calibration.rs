@@ -0,0 +1,304 @@
+//! DAC linearity and reference drift, measured through an ADS1x18 input looped back from the DAC
+//! output or the EFC node
+
+use core::fmt;
+use core::fmt::Debug;
+
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::ads1018::{ADS1018Variant, ADS1x18, Error, ExternalChannel, Gain, Variant};
+
+/// Evenly spaced across the code range, both ends included
+pub const CALIBRATION_POINTS: usize = 17;
+/// ADC conversions averaged per calibration point
+const SAMPLES_PER_POINT: u32 = 8;
+/// In `track_reference` calls
+const REFERENCE_TRACKING_TAU: f64 = 16.0;
+/// Below this fraction of full scale the ratio is mostly ADC offset
+const REFERENCE_TRACKING_MIN_FRACTION: f64 = 0.05;
+
+/// Volts at the DAC are `volts * scale + offset` at the input
+#[derive(Copy, Clone, Debug)]
+pub struct LoopbackInput<V: Variant = ADS1018Variant> {
+    pub channel: ExternalChannel,
+    pub gain: Gain,
+    pub data_rate: V::DataRate,
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl<V: Variant> LoopbackInput<V> {
+    pub fn read<SPI, CS: OutputPin, MISO: InputPin>(
+        &self,
+        adc: &mut ADS1x18<SPI, CS, MISO, V>,
+    ) -> Result<f64, Error<SPI::Error>>
+        where SPI: embedded_hal::blocking::spi::Transfer<u8>, SPI::Error: Debug
+    {
+        let raw = adc.read_channel(self.channel, self.gain, self.data_rate)?;
+        Ok(V::to_volts(self.gain, raw) * self.scale + self.offset)
+    }
+}
+
+/// Measured DAC transfer function, and how far the reference has drifted since.
+///
+/// The control loop works in ideal codes, those of a perfectly linear DAC at the nominal full
+/// scale, `corrected_code` maps them to the ones to write. Only the INL is corrected: the table is
+/// scaled so that its end points sit on the ideal line, whatever the gain and offset between the
+/// DAC and the loopback input, e.g. the EFC divider.
+#[derive(Clone, Debug)]
+pub struct DacCalibration {
+    max_code: u16,
+    lsb: f64,
+    /// On the ideal line at both ends
+    volts: [f64; CALIBRATION_POINTS],
+    reference_gain: f64,
+    /// Loopback volts are `volts * input_gain + input_offset`, as measured at the end points
+    input_gain: f64,
+    input_offset: f64,
+}
+
+impl DacCalibration {
+    /// No correction
+    pub fn ideal(resolution_bits: u32, full_scale: f64) -> Self {
+        let mut calibration = Self {
+            max_code: ((1u32 << resolution_bits) - 1) as u16,
+            lsb: full_scale / (1u32 << resolution_bits) as f64,
+            volts: [0.0; CALIBRATION_POINTS],
+            reference_gain: 1.0,
+            input_gain: 1.0,
+            input_offset: 0.0,
+        };
+        for i in 0..CALIBRATION_POINTS {
+            calibration.volts[i] = calibration.point_code(i) * calibration.lsb;
+        }
+        calibration
+    }
+
+    fn point_code(&self, i: usize) -> f64 {
+        libm::round(i as f64 * self.max_code as f64 / (CALIBRATION_POINTS - 1) as f64)
+    }
+
+    fn segment(&self, code: f64) -> usize {
+        let spacing = self.max_code as f64 / (CALIBRATION_POINTS - 1) as f64;
+        ((code / spacing) as usize).min(CALIBRATION_POINTS - 2)
+    }
+
+    /// At the time of calibration, interpolated
+    fn table_volts(&self, code: f64) -> f64 {
+        let i = self.segment(code.max(0.0));
+        let (c0, c1) = (self.point_code(i), self.point_code(i + 1));
+        self.volts[i] + (self.volts[i + 1] - self.volts[i]) * (code - c0) / (c1 - c0)
+    }
+
+    /// Expected at the DAC output
+    pub fn volts(&self, code: f64) -> f64 {
+        self.table_volts(code) * self.reference_gain
+    }
+
+    /// To write to get `volts`, within the code range
+    pub fn code_for_volts(&self, volts: f64) -> f64 {
+        let volts = volts / self.reference_gain;
+        let i = (0..CALIBRATION_POINTS - 2)
+            .find(|&i| volts < self.volts[i + 1])
+            .unwrap_or(CALIBRATION_POINTS - 2);
+        let (c0, c1) = (self.point_code(i), self.point_code(i + 1));
+        let slope = (self.volts[i + 1] - self.volts[i]) / (c1 - c0);
+        if slope <= 0.0 {
+            return c0;
+        }
+        (c0 + (volts - self.volts[i]) / slope).clamp(0.0, self.max_code as f64)
+    }
+
+    /// The code to write for an ideal one
+    pub fn corrected_code(&self, ideal_code: f64) -> f64 {
+        self.code_for_volts(ideal_code * self.lsb)
+    }
+
+    /// The ideal code for one written
+    pub fn ideal_code(&self, code: f64) -> f64 {
+        self.volts(code) / self.lsb
+    }
+
+    /// Measured at `code` through the loopback input, anywhere in the range, averages the
+    /// deviation from the table into the reference drift
+    pub fn track_reference(&mut self, code: f64, volts: f64) {
+        let volts = (volts - self.input_offset) / self.input_gain;
+        let expected = self.table_volts(code);
+        if expected < REFERENCE_TRACKING_MIN_FRACTION * self.lsb * self.max_code as f64 {
+            return;
+        }
+        self.reference_gain += (volts / expected - self.reference_gain) / REFERENCE_TRACKING_TAU;
+    }
+
+    /// Relative to the reference at the time of calibration
+    pub fn reference_gain(&self) -> f64 {
+        self.reference_gain
+    }
+
+    /// Of the loopback input relative to the ideal DAC output, and its offset in volts
+    pub fn input_gain_offset(&self) -> (f64, f64) {
+        (self.input_gain, self.input_offset)
+    }
+
+    /// Deviation from the line through the end points, in LSBs
+    pub fn inl(&self, i: usize) -> f64 {
+        let first = self.volts[0];
+        let last = self.volts[CALIBRATION_POINTS - 1];
+        let line = first + (last - first) * self.point_code(i) / self.max_code as f64;
+        let lsb = (last - first) / self.max_code as f64;
+        (self.volts[i] - line) / lsb
+    }
+}
+
+impl fmt::Display for DacCalibration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "DAC reference gain: {:.06}, loopback gain: {:.06}, offset: {:.05}V",
+                 self.reference_gain, self.input_gain, self.input_offset)?;
+        for i in 0..CALIBRATION_POINTS {
+            writeln!(f, "DAC {:5}: {:.05}V, INL {:.02}LSB", self.point_code(i), self.volts[i], self.inl(i))?;
+        }
+        Ok(())
+    }
+}
+
+/// Steps through the calibration points. `set_code` has to leave the output settled, `measure`
+/// returns the loopback volts. Without a response across the range there's nothing to correct and
+/// the calibration is the ideal one.
+pub fn calibrate<E>(
+    resolution_bits: u32,
+    full_scale: f64,
+    mut set_code: impl FnMut(u16) -> Result<(), E>,
+    mut measure: impl FnMut() -> Result<f64, E>,
+) -> Result<DacCalibration, E> {
+    let mut calibration = DacCalibration::ideal(resolution_bits, full_scale);
+    for i in 0..CALIBRATION_POINTS {
+        set_code(calibration.point_code(i) as u16)?;
+        let mut sum = 0.0;
+        for _ in 0..SAMPLES_PER_POINT {
+            sum += measure()?;
+        }
+        calibration.volts[i] = sum / SAMPLES_PER_POINT as f64;
+    }
+
+    let first = calibration.volts[0];
+    let span = calibration.volts[CALIBRATION_POINTS - 1] - first;
+    let ideal = DacCalibration::ideal(resolution_bits, full_scale);
+    let ideal_span = ideal.volts[CALIBRATION_POINTS - 1] - ideal.volts[0];
+    let input_gain = span / ideal_span;
+    if input_gain <= 0.0 || !input_gain.is_finite() {
+        return Ok(ideal);
+    }
+    calibration.input_gain = input_gain;
+    calibration.input_offset = first;
+    for volts in calibration.volts.iter_mut() {
+        *volts = (*volts - first) / input_gain;
+    }
+    Ok(calibration)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    use crate::calibration::{calibrate, CALIBRATION_POINTS, DacCalibration};
+
+    const LSB: f64 = 5.0 / 65536.0;
+
+    /// Bowed by 4 LSB mid-scale, with a 10mV offset
+    fn bowed(code: u16, v_ref: f64) -> f64 {
+        let x = code as f64 / 65536.0;
+        v_ref * (x + 4.0 / 65536.0 * 4.0 * x * (1.0 - x)) + 0.01
+    }
+
+    /// The DAC output scaled and offset so that the end points at a 5V reference land on the
+    /// ideal line, what the calibration corrects to
+    fn on_line(code: u16, v_ref: f64) -> f64 {
+        (bowed(code, v_ref) - bowed(0, 5.0)) / (bowed(0xffff, 5.0) - bowed(0, 5.0)) * 0xffff as f64 * LSB
+    }
+
+    /// Through a loopback input with a divider and an offset
+    fn calibrated(v_ref: f64, scale: f64, offset: f64) -> DacCalibration {
+        let code = Cell::new(0);
+        calibrate::<()>(16, 5.0, |c| {
+            code.set(c);
+            Ok(())
+        }, || Ok(bowed(code.get(), v_ref) * scale + offset)).unwrap()
+    }
+
+    #[test]
+    fn ideal() {
+        let calibration = DacCalibration::ideal(16, 5.0);
+        assert_approx_eq!(2.5, calibration.volts(32768.0), 1e-12);
+        assert_approx_eq!(12345.6, calibration.code_for_volts(12345.6 * 5.0 / 65536.0), 1e-6);
+        assert_approx_eq!(65535.0, calibration.code_for_volts(6.0), 1e-12);
+        assert_approx_eq!(0.0, calibration.code_for_volts(-1.0), 1e-12);
+        for i in 0..CALIBRATION_POINTS {
+            assert_approx_eq!(0.0, calibration.inl(i), 1e-9);
+        }
+    }
+
+    #[test]
+    fn corrects_inl() {
+        let calibration = calibrated(5.0, 1.0, 0.0);
+        assert_approx_eq!(4.0, calibration.inl(CALIBRATION_POINTS / 2), 0.01);
+
+        for &volts in [0.5, 1.234, 2.5, 3.9, 4.95].iter() {
+            let code = calibration.code_for_volts(volts);
+            // within a fraction of an LSB, the bow is quadratic and the table piecewise linear
+            assert_approx_eq!(volts, on_line(libm::round(code) as u16, 5.0), 0.6 * LSB);
+        }
+        assert_approx_eq!(1000.0, calibration.ideal_code(calibration.corrected_code(1000.0)), 1e-6);
+    }
+
+    #[test]
+    fn divided_loopback() {
+        let direct = calibrated(5.0, 1.0, 0.0);
+        // the EFC node behind a 100k/100k divider, read with a 20mV offset
+        let mut divided = calibrated(5.0, 0.5, 0.02);
+        let (gain, offset) = divided.input_gain_offset();
+        assert_approx_eq!(0.5, gain, 1e-3);
+        assert_approx_eq!(0.025, offset, 1e-9);
+        assert_approx_eq!(4.0, divided.inl(CALIBRATION_POINTS / 2), 0.01);
+
+        for &ideal_code in [0.0, 1000.0, 32768.0, 60000.0, 65535.0].iter() {
+            let code = divided.corrected_code(ideal_code);
+            assert_approx_eq!(direct.corrected_code(ideal_code), code, 1e-6);
+            // only the INL is corrected, the upper half of the range is still there
+            assert_approx_eq!(ideal_code, code, 4.1);
+            assert_approx_eq!(ideal_code * LSB, on_line(libm::round(code) as u16, 5.0), 0.6 * LSB);
+        }
+
+        let code = divided.code_for_volts(2.0);
+        for _ in 0..200 {
+            divided.track_reference(code, bowed(code as u16, 4.9) * 0.5 + 0.02);
+        }
+        assert_approx_eq!(0.98, divided.reference_gain(), 1e-3);
+    }
+
+    #[test]
+    fn flat_loopback() {
+        let calibration = calibrated(5.0, 0.0, 1.0);
+        assert_approx_eq!(1234.5, calibration.corrected_code(1234.5), 1e-9);
+        assert_eq!((1.0, 0.0), calibration.input_gain_offset());
+    }
+
+    #[test]
+    fn tracks_reference() {
+        let mut calibration = calibrated(5.0, 1.0, 0.0);
+        let code = calibration.code_for_volts(2.0);
+
+        for _ in 0..200 {
+            calibration.track_reference(code, bowed(code as u16, 4.9));
+        }
+        assert_approx_eq!(0.98, calibration.reference_gain(), 1e-3);
+
+        let code = calibration.code_for_volts(3.0);
+        assert_approx_eq!(3.0, on_line(libm::round(code) as u16, 4.9), 1e-3);
+
+        // no update from the bottom of the range
+        calibration.track_reference(10.0, 0.0);
+        assert_approx_eq!(0.98, calibration.reference_gain(), 1e-3);
+    }
+}
@@ -0,0 +1,173 @@
+//! Commands accepted over the UART, parsed in place
+
+use core::fmt;
+
+pub const HELP: &str = "\
+status                 summary
+dump                   control loop state
+stability              ADEV, MDEV and TDEV so far
+set p|i|d <factor>     control loop gains
+set tau <seconds>      frequency filter time constant
+holdover [on|off]      stop steering, keep the DAC where it is
+dac <code>|auto        force the DAC, or give it back to the control loop
+records [on|off]       per-second binary records instead of the text log, see `record`
+calibrate              re-run the DAC calibration, restarts the control loop
+reboot
+SCPI commands and queries, e.g. *IDN? or SYST:ERR?
+";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Term {
+    P,
+    I,
+    D,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Status,
+    Dump,
+    Stability,
+    SetGain(Term, f64),
+    SetFilterTau(u32),
+    Holdover(bool),
+    /// `None` gives the DAC back to the control loop
+    ManualDac(Option<u16>),
+    Records(bool),
+    Calibrate,
+    Reboot,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParseError<'a> {
+    Empty,
+    UnknownCommand(&'a str),
+    MissingArgument,
+    InvalidArgument(&'a str),
+    UnexpectedArgument(&'a str),
+}
+
+impl<'a> fmt::Display for ParseError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "Empty command"),
+            ParseError::UnknownCommand(command) => write!(f, "Unknown command: {}, try help", command),
+            ParseError::MissingArgument => write!(f, "Missing argument"),
+            ParseError::InvalidArgument(argument) => write!(f, "Invalid argument: {}", argument),
+            ParseError::UnexpectedArgument(argument) => write!(f, "Unexpected argument: {}", argument),
+        }
+    }
+}
+
+fn is(word: &str, keyword: &str) -> bool {
+    word.eq_ignore_ascii_case(keyword)
+}
+
+/// On unless told otherwise
+fn on_off(word: Option<&str>) -> Result<bool, ParseError> {
+    match word {
+        None => Ok(true),
+        Some(on) if is(on, "on") => Ok(true),
+        Some(off) if is(off, "off") => Ok(false),
+        Some(other) => Err(ParseError::InvalidArgument(other)),
+    }
+}
+
+/// Case-insensitive, words separated by any whitespace
+pub fn parse(line: &str) -> Result<Command, ParseError> {
+    let mut words = line.split_ascii_whitespace();
+    let command = words.next().ok_or(ParseError::Empty)?;
+    let mut argument = || words.next().ok_or(ParseError::MissingArgument);
+
+    let parsed = if is(command, "help") || command == "?" {
+        Command::Help
+    } else if is(command, "status") {
+        Command::Status
+    } else if is(command, "dump") {
+        Command::Dump
+    } else if is(command, "stability") {
+        Command::Stability
+    } else if is(command, "set") {
+        let name = argument()?;
+        let value = argument()?;
+        if is(name, "tau") {
+            match value.parse() {
+                Ok(tau) if tau > 0 => Command::SetFilterTau(tau),
+                _ => return Err(ParseError::InvalidArgument(value)),
+            }
+        } else {
+            let term = if is(name, "p") {
+                Term::P
+            } else if is(name, "i") {
+                Term::I
+            } else if is(name, "d") {
+                Term::D
+            } else {
+                return Err(ParseError::InvalidArgument(name));
+            };
+            match value.parse::<f64>() {
+                Ok(factor) if factor.is_finite() && factor >= 0.0 => Command::SetGain(term, factor),
+                _ => return Err(ParseError::InvalidArgument(value)),
+            }
+        }
+    } else if is(command, "holdover") {
+        Command::Holdover(on_off(words.next())?)
+    } else if is(command, "dac") {
+        let value = argument()?;
+        if is(value, "auto") {
+            Command::ManualDac(None)
+        } else {
+            Command::ManualDac(Some(value.parse().map_err(|_| ParseError::InvalidArgument(value))?))
+        }
+    } else if is(command, "records") {
+        Command::Records(on_off(words.next())?)
+    } else if is(command, "calibrate") {
+        Command::Calibrate
+    } else if is(command, "reboot") {
+        Command::Reboot
+    } else {
+        return Err(ParseError::UnknownCommand(command));
+    };
+
+    match words.next() {
+        Some(extra) => Err(ParseError::UnexpectedArgument(extra)),
+        None => Ok(parsed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::console::{Command, parse, ParseError, Term};
+
+    #[test]
+    fn commands() {
+        assert_eq!(Ok(Command::Help), parse("?"));
+        assert_eq!(Ok(Command::Status), parse("  STATUS "));
+        assert_eq!(Ok(Command::Dump), parse("dump"));
+        assert_eq!(Ok(Command::Stability), parse("Stability"));
+        assert_eq!(Ok(Command::SetGain(Term::I, 0.002)), parse("set I 2e-3"));
+        assert_eq!(Ok(Command::SetFilterTau(1200)), parse("set tau\t1200"));
+        assert_eq!(Ok(Command::Holdover(true)), parse("holdover"));
+        assert_eq!(Ok(Command::Holdover(false)), parse("holdover off"));
+        assert_eq!(Ok(Command::ManualDac(Some(32768))), parse("dac 32768"));
+        assert_eq!(Ok(Command::ManualDac(None)), parse("dac auto"));
+        assert_eq!(Ok(Command::Records(true)), parse("records ON"));
+        assert_eq!(Ok(Command::Calibrate), parse("calibrate"));
+        assert_eq!(Ok(Command::Reboot), parse("reboot"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Err(ParseError::Empty), parse(" \t"));
+        assert_eq!(Err(ParseError::UnknownCommand("frobnicate")), parse("frobnicate 1"));
+        assert_eq!(Err(ParseError::MissingArgument), parse("set p"));
+        assert_eq!(Err(ParseError::InvalidArgument("q")), parse("set q 1"));
+        assert_eq!(Err(ParseError::InvalidArgument("-1")), parse("set d -1"));
+        assert_eq!(Err(ParseError::InvalidArgument("inf")), parse("set p inf"));
+        assert_eq!(Err(ParseError::InvalidArgument("0")), parse("set tau 0"));
+        assert_eq!(Err(ParseError::InvalidArgument("65536")), parse("dac 65536"));
+        assert_eq!(Err(ParseError::InvalidArgument("maybe")), parse("holdover maybe"));
+        assert_eq!(Err(ParseError::UnexpectedArgument("now")), parse("reboot now"));
+    }
+}
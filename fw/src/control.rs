@@ -1,4 +1,6 @@
-use crate::filter::ExponentialAverageFilter;
+use core::fmt;
+
+use crate::filter::{ExponentialAverageFilter, Filter};
 use crate::freq_counter::{FrequencyCounters, FrequencyCountersToleranceCheck};
 
 pub struct ControlLoop {
@@ -67,20 +69,21 @@ impl ControlLoop {
     }
 }
 
-pub struct FeedbackControl {
+pub struct FeedbackControl<F: Filter = ExponentialAverageFilter> {
     frequency: f64,
 
-    dac_code: u16,
+    dac_code: f64,
+    max_dac_code: u16,
+    fractional: bool,
 
     target_frequency: f64,
     control_sensitivity: f64,
     i_factor: f64,
 
-    frequency_filter: ExponentialAverageFilter,
+    frequency_filter: F,
     i_error: f64,
     p_factor: f64,
     p_error: f64,
-    #[cfg(test)]
     d_error: f64,
 
     d_factor: f64,
@@ -88,7 +91,7 @@ pub struct FeedbackControl {
     i_error_dead_zone: f64,
 }
 
-impl FeedbackControl {
+impl<F: Filter> FeedbackControl<F> {
     pub fn new(
         dac_code: u16,
         frequency: f64,
@@ -98,16 +101,19 @@ impl FeedbackControl {
         p_factor: f64,
         d_factor: f64,
         i_error_dead_zone: f64,
+        mut frequency_filter: F,
     ) -> Self {
+        frequency_filter.reset(frequency);
         Self {
             target_frequency,
             frequency,
-            dac_code,
+            dac_code: dac_code as f64,
+            max_dac_code: 0xffff,
+            fractional: false,
             control_sensitivity,
-            frequency_filter: ExponentialAverageFilter::new(600, frequency),
+            frequency_filter,
             i_error: Default::default(),
             p_error: Default::default(),
-            #[cfg(test)]
             d_error: Default::default(),
             i_factor,
             p_factor,
@@ -142,19 +148,17 @@ impl FeedbackControl {
         self.i_error += p_error;
         let d_error = p_error - self.p_error;
         self.p_error = p_error;
-
-        #[cfg(test)] {
-            self.d_error = d_error;
-        }
+        self.d_error = d_error;
 
         let p_term = self.get_p_term();
         let i_term = self.get_i_term();
-        let d_term = d_error * self.d_factor;
+        let d_term = self.get_d_term();
 
-        let adj = libm::round((p_term + i_term + d_term) / self.control_sensitivity);
+        let adj = (p_term + i_term + d_term) / self.control_sensitivity;
+        let adj = if self.fractional { adj } else { libm::round(adj) };
 
         if adj != 0.0 {
-            self.dac_code = (self.dac_code as i32 + adj as i32).clamp(0, 0xffff) as u16;
+            self.dac_code = (self.dac_code + adj).clamp(0.0, self.max_dac_code as f64);
 
             self.frequency_filter.apply_adjustment(
                 adj * self.control_sensitivity * set_point_correction
@@ -163,13 +167,49 @@ impl FeedbackControl {
     }
 
     pub fn get_dac_code(&self) -> u16 {
+        libm::round(self.dac_code) as u16
+    }
+
+    /// For a `dither::Modulator`
+    pub fn get_fractional_dac_code(&self) -> f64 {
         self.dac_code
     }
 
+    /// Adjust by fractions of an LSB instead of whole codes, see `dither`
+    pub fn set_fractional(&mut self, fractional: bool) {
+        self.fractional = fractional;
+    }
+
+    /// For DACs narrower than 16 bits
+    pub fn set_max_dac_code(&mut self, max: u16) {
+        self.max_dac_code = max;
+        self.dac_code = self.dac_code.min(max as f64);
+    }
+
+    /// Where the DAC actually got to when it couldn't follow, e.g. slew-rate limited, takes back
+    /// the filter compensation for the part of the adjustment that didn't happen
+    pub fn sync_dac_code(&mut self, code: f64) {
+        let missed = code - self.dac_code;
+        if missed != 0.0 {
+            self.frequency_filter.apply_adjustment(missed * self.control_sensitivity);
+            self.dac_code = code;
+        }
+    }
+
     pub fn get_filtered_frequency(&self) -> f64 {
         self.frequency_filter.get()
     }
 
+    pub fn set_p_factor(&mut self, factor: f64) { self.p_factor = factor; }
+
+    pub fn set_i_factor(&mut self, factor: f64) { self.i_factor = factor; }
+
+    pub fn set_d_factor(&mut self, factor: f64) { self.d_factor = factor; }
+
+    pub fn get_factors(&self) -> (f64, f64, f64) {
+        (self.p_factor, self.i_factor, self.d_factor)
+    }
+
     pub fn get_i_error(&self) -> f64 { self.i_error }
 
     pub fn get_i_term(&self) -> f64 {
@@ -182,15 +222,33 @@ impl FeedbackControl {
         self.p_error * self.p_factor
     }
 
-    #[cfg(test)]
     pub fn get_d_error(&self) -> f64 { self.d_error }
 
-    #[cfg(test)]
     pub fn get_d_term(&self) -> f64 {
         self.d_error * self.d_factor
     }
 }
 
+impl FeedbackControl<ExponentialAverageFilter> {
+    /// See `noise::NoiseIdentifier::filter_tau`
+    pub fn set_filter_tau(&mut self, tau: u32) {
+        self.frequency_filter.set_tau(tau);
+    }
+}
+
+impl<F: Filter> fmt::Display for FeedbackControl<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Target: {:.03}Hz, filtered: {:.04}Hz, last: {:.04}Hz",
+                 self.target_frequency, self.get_filtered_frequency(), self.frequency)?;
+        writeln!(f, "DAC code: {:.03} of {}, fractional: {}", self.dac_code, self.max_dac_code, self.fractional)?;
+        writeln!(f, "Sensitivity: {:e}Hz/LSB", self.control_sensitivity)?;
+        writeln!(f, "P: {:e}, error {:.04}, term {:.04}", self.p_factor, self.p_error, self.get_p_term())?;
+        writeln!(f, "I: {:e}, error {:.04}, term {:.04}, dead zone {}",
+                 self.i_factor, self.i_error, self.get_i_term(), self.i_error_dead_zone)?;
+        writeln!(f, "D: {:e}, error {:.04}, term {:.04}", self.d_factor, self.d_error, self.get_d_term())
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -205,6 +263,10 @@ mod tests {
     use assert_approx_eq::assert_approx_eq;
 
     use crate::control::FeedbackControl;
+    use crate::dither::{Modulator, SigmaDelta, TpdfDither};
+    use crate::filter::{ExponentialAverageFilter, Filter, RegressionFilter, UniformAverageFilter};
+    use crate::fixed::{Fixed, FixedFeedbackControl};
+    use crate::noise::NoiseIdentifier;
 
     const RNG_SEED: [u8; 32] = [1, 0, 0, 0, 23, 0, 0, 0, 200, 1, 0, 0, 210, 30, 0, 0,
         0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
@@ -307,12 +369,17 @@ mod tests {
                 self.v_in * (self.r_next_in / (self.r_out + self.r_next_in))
         }
 
-        pub fn tick(&mut self) {
+        /// Volts at the EFC per volt out of the DAC
+        pub fn gain(&self) -> f64 {
+            self.r_next_in / (self.r_out + self.r_next_in)
+        }
+
+        pub fn tick(&mut self, seconds: f64) {
             let tau = self.c_out * 1.0 /
                 (1.0 / self.r_out + 1.0 / self.r_next_in);
 
             self.v_out = self.v_out +
-                (self.target_voltage() - self.v_out) * (1.0 - (-1.0 / tau).exp());
+                (self.target_voltage() - self.v_out) * (1.0 - (-seconds / tau).exp());
         }
 
         pub fn get_v_out(&self) -> f64 {
@@ -352,6 +419,7 @@ mod tests {
         pps_seconds: f64,
         ocxo_frequency: f64,
         reported_frequency: f64,
+        reported_frequency_fixed: Fixed,
         clk_slack: f64,
         ocxo_slack: f64,
         ocxo_clk_slack: f64,
@@ -364,6 +432,7 @@ mod tests {
                 pps_seconds: Default::default(),
                 ocxo_frequency: Default::default(),
                 reported_frequency: Default::default(),
+                reported_frequency_fixed: Default::default(),
                 clk_slack: Default::default(),
                 ocxo_slack: Default::default(),
                 ocxo_clk_slack: Default::default(),
@@ -425,23 +494,152 @@ mod tests {
             self.reported_frequency = clk_cycles_seen as f64
                 * ocxo_cycles_seen as f64
                 / ocxo_clk_cycles_seen as f64;
+            self.reported_frequency_fixed = Fixed::from_ratio(
+                clk_cycles_seen as u64 * ocxo_cycles_seen as u64,
+                ocxo_clk_cycles_seen as u64,
+            );
         }
 
         pub fn get_reported_frequency(&self) -> f64 {
             self.reported_frequency
         }
+
+        pub fn get_reported_frequency_fixed(&self) -> Fixed {
+            self.reported_frequency_fixed
+        }
+    }
+
+    /// Lets the simulation run either the f64 or the fixed-point control path
+    trait Control {
+        fn set_frequency(&mut self, counter: &FrequencyCounter);
+        fn tick(&mut self);
+        fn get_dac_code(&self) -> u16;
+        fn get_fractional_dac_code(&self) -> f64;
+        fn get_filtered_frequency(&self) -> f64;
+        fn get_i_error(&self) -> f64;
+        fn get_p_term(&self) -> f64;
+        fn get_i_term(&self) -> f64;
+        fn get_d_term(&self) -> f64;
     }
 
-    struct System {
+    impl<F: Filter> Control for FeedbackControl<F> {
+        fn set_frequency(&mut self, counter: &FrequencyCounter) {
+            FeedbackControl::set_frequency(self, counter.get_reported_frequency());
+        }
+        fn tick(&mut self) { FeedbackControl::tick(self) }
+        fn get_dac_code(&self) -> u16 { FeedbackControl::get_dac_code(self) }
+        fn get_fractional_dac_code(&self) -> f64 { FeedbackControl::get_fractional_dac_code(self) }
+        fn get_filtered_frequency(&self) -> f64 { FeedbackControl::get_filtered_frequency(self) }
+        fn get_i_error(&self) -> f64 { FeedbackControl::get_i_error(self) }
+        fn get_p_term(&self) -> f64 { FeedbackControl::get_p_term(self) }
+        fn get_i_term(&self) -> f64 { FeedbackControl::get_i_term(self) }
+        fn get_d_term(&self) -> f64 { FeedbackControl::get_d_term(self) }
+    }
+
+    impl Control for FixedFeedbackControl {
+        fn set_frequency(&mut self, counter: &FrequencyCounter) {
+            FixedFeedbackControl::set_frequency(self, counter.get_reported_frequency_fixed());
+        }
+        fn tick(&mut self) { FixedFeedbackControl::tick(self) }
+        fn get_dac_code(&self) -> u16 { FixedFeedbackControl::get_dac_code(self) }
+        fn get_fractional_dac_code(&self) -> f64 { FixedFeedbackControl::get_fractional_dac_code(self).to_f64() }
+        fn get_filtered_frequency(&self) -> f64 { FixedFeedbackControl::get_filtered_frequency(self).to_f64() }
+        fn get_i_error(&self) -> f64 { FixedFeedbackControl::get_i_error(self).to_f64() }
+        fn get_p_term(&self) -> f64 { FixedFeedbackControl::get_p_term(self).to_f64() }
+        fn get_i_term(&self) -> f64 { FixedFeedbackControl::get_i_term(self).to_f64() }
+        fn get_d_term(&self) -> f64 { FixedFeedbackControl::get_d_term(self).to_f64() }
+    }
+
+    struct System<C: Control = FeedbackControl> {
         ocxo: OCXO,
         dac: DAC16,
         pps: PPS,
         frequency_counter: FrequencyCounter,
-        feedback_control: FeedbackControl,
+        feedback_control: C,
+        dithering: Option<Dithering>,
+        /// Averaged over the tick
+        ocxo_frequency: f64,
+    }
+
+    /// The DAC updated `DAC_UPDATES_PER_TICK` times a tick, through the EFC filter
+    struct Dithering {
+        modulator: Box<dyn Modulator>,
+        efc_filter: DACFilter,
+    }
+
+    const DAC_UPDATES_PER_TICK: usize = 8;
+
+    fn control_sensitivity() -> f64 {
+        OCXO::get_control_sensitivity_hz_per_v() / 65536.0 * 5.0
     }
 
     impl System {
         pub fn new() -> Self {
+            Self::with_filter(ExponentialAverageFilter::new(600, 10e6))
+        }
+    }
+
+    impl<F: Filter> System<FeedbackControl<F>> {
+        pub fn with_filter(frequency_filter: F) -> Self {
+            Self::with_control(FeedbackControl::new(
+                32768,
+                10e6,
+                10e6,
+                control_sensitivity(),
+                0.001,
+                0.1,
+                0.05,
+                0.01,
+                frequency_filter,
+            ))
+        }
+    }
+
+    impl System {
+        /// The same gains, through the EFC filter, the adjustments in whole LSBs unless `fractional`
+        pub fn with_dithering(fractional: bool, modulator: Box<dyn Modulator>) -> Self {
+            let mut efc_filter = DACFilter::new();
+            let mut feedback_control = FeedbackControl::new(
+                32768,
+                10e6,
+                10e6,
+                control_sensitivity() * efc_filter.gain(),
+                0.001,
+                0.1,
+                0.05,
+                0.01,
+                ExponentialAverageFilter::new(600, 10e6),
+            );
+            feedback_control.set_fractional(fractional);
+
+            let mut system = Self::with_control(feedback_control);
+            system.dac.set_code(32768);
+            system.dac.tick();
+            efc_filter.set_v_in(system.dac.get_v_out());
+            efc_filter.init_steady_state();
+            system.dithering = Some(Dithering { modulator, efc_filter });
+            system
+        }
+    }
+
+    impl System<FixedFeedbackControl> {
+        pub fn new_fixed() -> Self {
+            Self::with_control(FixedFeedbackControl::new(
+                32768,
+                Fixed::from_int(10_000_000),
+                Fixed::from_int(10_000_000),
+                Fixed::from_f64(control_sensitivity()),
+                Fixed::from_f64(0.001),
+                Fixed::from_f64(0.1),
+                Fixed::from_f64(0.05),
+                Fixed::from_f64(0.01),
+                600,
+            ))
+        }
+    }
+
+    impl<C: Control> System<C> {
+        pub fn with_control(feedback_control: C) -> Self {
             let mut dac = DAC16::new();
             dac.set_v_ref(5.0);
             Self {
@@ -449,33 +647,45 @@ mod tests {
                 dac,
                 pps: PPS::new(7.0e-9),
                 frequency_counter: FrequencyCounter::new(),
-                feedback_control: FeedbackControl::new(
-                    32768,
-                    10e6,
-                    10e6,
-                    OCXO::get_control_sensitivity_hz_per_v() / 65536.0 * 5.0,
-                    0.001,
-                    0.1,
-                    0.05,
-                    0.01,
-                ),
+                feedback_control,
+                dithering: None,
+                ocxo_frequency: 10e6,
             }
         }
 
         pub fn tick(&mut self) {
-            self.dac.set_code(self.feedback_control.get_dac_code());
-            self.dac.tick();
+            if let Some(dithering) = &mut self.dithering {
+                dithering.modulator.set_target(self.feedback_control.get_fractional_dac_code());
+
+                let mut frequency_sum = 0.0;
+                for _ in 0..DAC_UPDATES_PER_TICK {
+                    self.dac.set_code(dithering.modulator.next_code());
+                    self.dac.tick();
 
-            self.ocxo.set_v_control(self.dac.v_out);
-            self.ocxo.tick();
+                    dithering.efc_filter.set_v_in(self.dac.get_v_out());
+                    dithering.efc_filter.tick(1.0 / DAC_UPDATES_PER_TICK as f64);
+
+                    self.ocxo.set_v_control(dithering.efc_filter.get_v_out());
+                    self.ocxo.tick();
+                    frequency_sum += self.ocxo.get_frequency();
+                }
+                self.ocxo_frequency = frequency_sum / DAC_UPDATES_PER_TICK as f64;
+            } else {
+                self.dac.set_code(self.feedback_control.get_dac_code());
+                self.dac.tick();
+
+                self.ocxo.set_v_control(self.dac.v_out);
+                self.ocxo.tick();
+                self.ocxo_frequency = self.ocxo.get_frequency();
+            }
 
             self.pps.tick();
 
-            self.frequency_counter.set_ocxo_frequency(self.ocxo.get_frequency());
+            self.frequency_counter.set_ocxo_frequency(self.ocxo_frequency);
             self.frequency_counter.set_pps_seconds(self.pps.get_seconds());
             self.frequency_counter.tick();
 
-            self.feedback_control.set_frequency(self.frequency_counter.get_reported_frequency());
+            self.feedback_control.set_frequency(&self.frequency_counter);
             self.feedback_control.tick();
         }
 
@@ -613,4 +823,151 @@ mod tests {
             wtr.serialize(system.metrics()).unwrap();
         }
     }
-}
\ No newline at end of file
+
+    fn closed_loop_control_with_filter<F: Filter>(name: &str, frequency_filter: F) -> Vec<f64> {
+        let mut system = System::with_filter(frequency_filter);
+
+        let mut wtr = csv::WriterBuilder::new()
+            .from_path(format!("sim/data/closed_loop_control_filter_{}.csv", name)).unwrap();
+
+        let mut freq = vec![];
+        for _ in 0..20000 {
+            system.tick();
+            freq.push(system.ocxo.get_frequency());
+            wtr.serialize(system.metrics()).unwrap();
+        }
+        freq
+    }
+
+    #[test]
+    fn closed_loop_control_filter_comparison() {
+        use typenum::consts::{U600, U601};
+
+        let exponential = closed_loop_control_with_filter(
+            "exponential", ExponentialAverageFilter::new(600, 10e6));
+        let uniform = closed_loop_control_with_filter(
+            "uniform", UniformAverageFilter::<U600>::new());
+        let regression = closed_loop_control_with_filter(
+            "regression", RegressionFilter::<U601>::new());
+
+        for freq in [exponential, uniform, regression].iter() {
+            // the actual OCXO frequency, the reported one is dominated by the PPS jitter
+            let settled = freq[10000..].to_vec();
+            assert_approx_eq!(10e6, settled.clone().mean(), 0.001);
+            assert!(settled.std_dev() < 0.001);
+        }
+    }
+
+    /// Runs both control paths side by side with the given DAC reference and checks the fixed-point
+    /// OCXO frequency stays within `tolerance_hz` of the f64 one
+    fn closed_loop_control_fixed_point_tracks_f64(name: &str, v_ref: f64, ticks: usize, tolerance_hz: f64) {
+        let mut system = System::new();
+        let mut fixed = System::new_fixed();
+        system.set_v_ref(v_ref);
+        fixed.set_v_ref(v_ref);
+
+        let mut wtr = csv::WriterBuilder::new()
+            .from_path(format!("sim/data/closed_loop_control_fixed_point_{}.csv", name)).unwrap();
+
+        for _ in 0..ticks {
+            system.tick();
+            fixed.tick();
+            wtr.serialize(fixed.metrics()).unwrap();
+
+            assert_approx_eq!(system.ocxo.get_frequency(), fixed.ocxo.get_frequency(), tolerance_hz);
+        }
+    }
+
+    #[test]
+    fn closed_loop_control_fixed_point_steady_state() {
+        // about one DAC LSB
+        closed_loop_control_fixed_point_tracks_f64("steady_state", 5.0, 20000, 2e-4);
+    }
+
+    #[test]
+    fn closed_loop_control_fixed_point_v_ref_step() {
+        // about one DAC LSB
+        closed_loop_control_fixed_point_tracks_f64("v_ref_step", 4.9, 40000, 2e-4);
+    }
+
+    #[test]
+    fn closed_loop_control_adaptive_filter_tau() {
+        let mut system = System::new();
+        let mut noise = NoiseIdentifier::new(1.0);
+
+        let mut wtr = csv::WriterBuilder::new()
+            .from_path("sim/data/closed_loop_control_adaptive_tau.csv").unwrap();
+
+        let mut taus = vec![];
+        let mut freq = vec![];
+        for ix in 0..40000 {
+            system.tick();
+            noise.add(system.get_reported_frequency());
+            if ix % 600 == 0 {
+                if let Some(tau) = noise.filter_tau(150, 2400) {
+                    system.feedback_control.set_filter_tau(tau);
+                    taus.push(tau);
+                }
+            }
+            if ix >= 30000 {
+                freq.push(system.ocxo.get_frequency());
+            }
+            wtr.serialize(system.metrics()).unwrap();
+        }
+
+        assert!(!taus.is_empty());
+        assert!(taus.iter().all(|tau| (150..=2400).contains(tau)));
+        assert_approx_eq!(10e6, freq.clone().mean(), 0.001);
+        assert!(freq.std_dev() < 0.001);
+    }
+
+    /// Deviation of the per-second OCXO frequency from the target, after settling
+    fn closed_loop_control_dithered(name: &str, fractional: bool, modulator: Box<dyn Modulator>) -> f64 {
+        let mut system = System::with_dithering(fractional, modulator);
+
+        let mut wtr = csv::WriterBuilder::new()
+            .from_path(format!("sim/data/closed_loop_control_dither_{}.csv", name)).unwrap();
+
+        let mut freq = vec![];
+        for ix in 0..20000 {
+            system.tick();
+            if ix >= 10000 {
+                freq.push(system.ocxo_frequency);
+            }
+            wtr.serialize(system.metrics()).unwrap();
+        }
+
+        assert_approx_eq!(10e6, freq.clone().mean(), 0.001);
+        (freq.iter().map(|f| (f - 10e6).powi(2)).sum::<f64>() / freq.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn sync_dac_code() {
+        let mut control = FeedbackControl::new(
+            32768, 10e6 - 1.0, 10e6, 0.001, 0.001, 0.1, 0.05, 0.0,
+            ExponentialAverageFilter::new(600, 10e6 - 1.0),
+        );
+        control.tick();
+        let commanded = control.get_fractional_dac_code();
+        let filtered = control.get_filtered_frequency();
+        assert!(commanded > 32768.0 + 16.0);
+
+        control.sync_dac_code(32768.0 + 16.0);
+        assert_eq!(32784, control.get_dac_code());
+        assert_approx_eq!(filtered - (commanded - 32784.0) * 0.001, control.get_filtered_frequency(), 1e-9);
+    }
+
+    #[test]
+    fn closed_loop_control_dithering() {
+        let rounded = closed_loop_control_dithered(
+            "rounded", false, Box::new(SigmaDelta::new(0xffff, 32768.0)));
+        let sigma_delta = closed_loop_control_dithered(
+            "sigma_delta", true, Box::new(SigmaDelta::new(0xffff, 32768.0)));
+        let tpdf = closed_loop_control_dithered(
+            "tpdf", true, Box::new(TpdfDither::new(0xffff, 32768.0, 1)));
+
+        // about 4.7e-5, 2.5e-5 and 3.0e-5Hz
+        assert!(sigma_delta < rounded);
+        assert!(tpdf < rounded);
+    }
+}
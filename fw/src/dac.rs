@@ -0,0 +1,301 @@
+//! DACs setting the OCXO tuning voltage
+
+use core::convert::Infallible;
+use core::fmt::Debug;
+
+use volatile_register::RW;
+
+pub trait TuningDac {
+    type Error: Debug;
+
+    /// Codes go from 0 to `max_code()`
+    fn resolution_bits(&self) -> u32;
+
+    /// Volts at a code of `2^resolution_bits()`, the reference
+    fn full_scale(&self) -> f64;
+
+    fn set_code(&mut self, code: u16) -> Result<(), Self::Error>;
+
+    fn max_code(&self) -> u16 {
+        ((1u32 << self.resolution_bits()) - 1) as u16
+    }
+
+    fn to_volts(&self, code: u16) -> f64 {
+        code as f64 * self.full_scale() / (1u32 << self.resolution_bits()) as f64
+    }
+}
+
+/// Of the SPI DACs
+#[derive(Debug)]
+pub enum Error<SPI: Debug, CS: Debug> {
+    BusError {
+        error: SPI,
+    },
+    ChipSelectError {
+        error: CS,
+    },
+}
+
+/// Moves the wrapped DAC by at most `max_step` codes per write, large moves take several `step`s
+pub struct SlewLimiter<D: TuningDac> {
+    dac: D,
+    max_step: u16,
+    target: u16,
+    code: u16,
+}
+
+impl<D: TuningDac> SlewLimiter<D> {
+    /// Assumes the DAC is at `code`, e.g. zero scale after power-on, and doesn't write it
+    pub fn new(dac: D, max_step: u16, code: u16) -> Self {
+        Self {
+            dac,
+            max_step: max_step.max(1),
+            target: code,
+            code,
+        }
+    }
+
+    pub fn set_max_step(&mut self, max_step: u16) {
+        self.max_step = max_step.max(1);
+    }
+
+    /// Reached by calling `step`
+    pub fn set_target(&mut self, code: u16) {
+        self.target = code.min(self.dac.max_code());
+    }
+
+    pub fn target(&self) -> u16 {
+        self.target
+    }
+
+    /// Where the DAC actually is
+    pub fn code(&self) -> u16 {
+        self.code
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.code == self.target
+    }
+
+    /// Writes the next code towards the target, if not there yet
+    pub fn step(&mut self) -> Result<u16, D::Error> {
+        let next = if self.code < self.target {
+            self.code + (self.target - self.code).min(self.max_step)
+        } else if self.code > self.target {
+            self.code - (self.code - self.target).min(self.max_step)
+        } else {
+            return Ok(self.code);
+        };
+        self.dac.set_code(next)?;
+        self.code = next;
+        Ok(next)
+    }
+}
+
+/// `set_code` sets the target and takes one step
+impl<D: TuningDac> TuningDac for SlewLimiter<D> {
+    type Error = D::Error;
+
+    fn resolution_bits(&self) -> u32 {
+        self.dac.resolution_bits()
+    }
+
+    fn full_scale(&self) -> f64 {
+        self.dac.full_scale()
+    }
+
+    fn set_code(&mut self, code: u16) -> Result<(), D::Error> {
+        self.set_target(code);
+        self.step().map(|_| ())
+    }
+}
+
+#[repr(C)]
+struct SigmaDeltaDacRegisters {
+    code: RW<u32>,
+}
+
+/// `sigma_delta_dac` in the FPGA, a first-order sigma-delta modulator whose `dac_pwm` output is
+/// RC-filtered to the tuning voltage. Full scale is the I/O bank voltage.
+pub struct SigmaDeltaDac {
+    full_scale: f64,
+}
+
+impl SigmaDeltaDac {
+    /// The iomem slot after the frequency counter's
+    fn ptr() -> *const SigmaDeltaDacRegisters {
+        0x03000014 as *const _
+    }
+
+    pub fn new(full_scale: f64) -> Self {
+        Self { full_scale }
+    }
+}
+
+impl TuningDac for SigmaDeltaDac {
+    type Error = Infallible;
+
+    fn resolution_bits(&self) -> u32 {
+        16
+    }
+
+    fn full_scale(&self) -> f64 {
+        self.full_scale
+    }
+
+    fn set_code(&mut self, code: u16) -> Result<(), Infallible> {
+        unsafe { (*Self::ptr()).code.write(code as u32) };
+        Ok(())
+    }
+}
+
+/// Records what goes over the bus, for testing the SPI DACs
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::prelude::v1::*;
+
+    use core::cell::RefCell;
+    use std::rc::Rc;
+
+    use embedded_hal::digital::v2::OutputPin;
+
+    #[derive(Debug, PartialEq)]
+    pub enum Event {
+        CsLow,
+        CsHigh,
+        Write(Vec<u8>),
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct Fault;
+
+    #[derive(Default)]
+    pub struct Bus {
+        pub events: Vec<Event>,
+        pub spi_fault: bool,
+        pub cs_fault: bool,
+    }
+
+    pub type Shared = Rc<RefCell<Bus>>;
+
+    pub struct Spi(pub Shared);
+    pub struct Cs(pub Shared);
+
+    impl embedded_hal::blocking::spi::Write<u8> for Spi {
+        type Error = Fault;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Fault> {
+            let mut bus = self.0.borrow_mut();
+            if bus.spi_fault {
+                return Err(Fault);
+            }
+            bus.events.push(Event::Write(words.to_vec()));
+            Ok(())
+        }
+    }
+
+    impl OutputPin for Cs {
+        type Error = Fault;
+
+        fn set_low(&mut self) -> Result<(), Fault> {
+            let mut bus = self.0.borrow_mut();
+            if bus.cs_fault {
+                return Err(Fault);
+            }
+            bus.events.push(Event::CsLow);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Fault> {
+            self.0.borrow_mut().events.push(Event::CsHigh);
+            Ok(())
+        }
+    }
+
+    pub fn bus() -> (Spi, Cs, Shared) {
+        let bus = Shared::default();
+        (Spi(bus.clone()), Cs(bus.clone()), bus)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use core::convert::Infallible;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    use crate::dac::{SigmaDeltaDac, SlewLimiter, TuningDac};
+
+    /// 12 bits, to check the limiter honors the DAC's range
+    #[derive(Default)]
+    struct RecordingDac {
+        codes: Vec<u16>,
+    }
+
+    impl TuningDac for RecordingDac {
+        type Error = Infallible;
+
+        fn resolution_bits(&self) -> u32 {
+            12
+        }
+
+        fn full_scale(&self) -> f64 {
+            4.096
+        }
+
+        fn set_code(&mut self, code: u16) -> Result<(), Infallible> {
+            self.codes.push(code);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn code_range() {
+        let dac = SigmaDeltaDac::new(3.3);
+        assert_eq!(0xffff, dac.max_code());
+        assert_approx_eq!(1.65, dac.to_volts(0x8000), 1e-12);
+        assert_approx_eq!(0.0, dac.to_volts(0), 1e-12);
+    }
+
+    /// `sigma_delta_dac` in basics.v, cycle by cycle: high `code` cycles out of every 2^16
+    #[test]
+    fn sigma_delta_duty() {
+        for &code in [0u16, 1, 0x5555, 0x8000, 0xffff].iter() {
+            let mut acc = 0u16;
+            let mut high = 0u32;
+            for _ in 0..1 << 16 {
+                let (sum, carry) = acc.overflowing_add(code);
+                acc = sum;
+                high += carry as u32;
+            }
+            assert_eq!(code as u32, high);
+            assert_eq!(0, acc);
+        }
+    }
+
+    #[test]
+    fn slew_limiter_ramps() {
+        let mut dac = SlewLimiter::new(RecordingDac::default(), 100, 1000);
+        assert!(dac.is_settled());
+        assert_eq!(Ok(1000), dac.step());
+        assert!(dac.dac.codes.is_empty());
+
+        dac.set_target(0xffff);
+        assert_eq!(0xfff, dac.target());
+        while !dac.is_settled() {
+            dac.step().unwrap();
+        }
+        dac.set_code(3900).unwrap();
+        assert_eq!(3995, dac.code());
+        assert_eq!(Ok(3900), dac.step());
+        assert_eq!(Ok(3900), dac.step());
+
+        let codes = &dac.dac.codes;
+        assert_eq!(Some(&0xfff), codes.iter().max());
+        assert!(codes.windows(2).all(|w| (w[0] as i32 - w[1] as i32).abs() <= 100));
+        // no writes once there
+        assert_eq!(&[0xfff, 3995, 3900], &codes[codes.len() - 3..]);
+    }
+}
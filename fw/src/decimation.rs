@@ -0,0 +1,265 @@
+//! Averages the per-second frequency and temperature stream down to 10 s, 100 s and 1000 s,
+//! keeping a short history at each timescale
+
+use arraydeque::ArrayDeque;
+use generic_array::{ArrayLength, GenericArray};
+
+/// Each timescale averages this many records of the one below
+pub const RATIO: u32 = 10;
+
+const STAGES: usize = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Timescale {
+    Seconds10 = 0,
+    Seconds100 = 1,
+    Seconds1000 = 2,
+}
+
+impl Timescale {
+    pub const ALL: [Timescale; STAGES] = [Timescale::Seconds10, Timescale::Seconds100, Timescale::Seconds1000];
+
+    pub fn seconds(self) -> u32 {
+        RATIO.pow(self as u32 + 1)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Average {
+    pub frequency: f64,
+    /// `None` if no temperature reading made it into this interval
+    pub temperature: Option<f64>,
+    /// Samples were lost between the previous record and this one
+    pub after_gap: bool,
+}
+
+#[derive(Default)]
+struct Accumulator {
+    frequency: f64,
+    temperature: f64,
+    count: u32,
+    temperature_count: u32,
+}
+
+impl Accumulator {
+    fn add(&mut self, frequency: f64, temperature: Option<f64>) {
+        self.frequency += frequency;
+        self.count += 1;
+        if let Some(temperature) = temperature {
+            self.temperature += temperature;
+            self.temperature_count += 1;
+        }
+    }
+
+    fn take(&mut self) -> (f64, Option<f64>) {
+        let frequency = self.frequency / self.count as f64;
+        let temperature = if self.temperature_count > 0 {
+            Some(self.temperature / self.temperature_count as f64)
+        } else {
+            None
+        };
+        *self = Default::default();
+        (frequency, temperature)
+    }
+}
+
+struct Stage<L: ArrayLength<Average>> {
+    accumulator: Accumulator,
+    history: ArrayDeque<GenericArray<Average, L>, arraydeque::Wrapping>,
+    gap_pending: bool,
+}
+
+impl<L: ArrayLength<Average>> Stage<L> {
+    fn new() -> Self {
+        Self {
+            accumulator: Default::default(),
+            history: ArrayDeque::new(),
+            gap_pending: false,
+        }
+    }
+
+    /// Returns the new record once `RATIO` inputs have been averaged
+    fn add(&mut self, frequency: f64, temperature: Option<f64>) -> Option<Average> {
+        self.accumulator.add(frequency, temperature);
+        if self.accumulator.count < RATIO {
+            return None;
+        }
+
+        let (frequency, temperature) = self.accumulator.take();
+        let average = Average {
+            frequency,
+            temperature,
+            after_gap: self.gap_pending,
+        };
+        self.gap_pending = false;
+        self.history.push_back(average);
+        Some(average)
+    }
+
+    fn gap(&mut self) {
+        self.accumulator = Default::default();
+        self.gap_pending = !self.history.is_empty();
+    }
+}
+
+/// A cascade of averaging-then-decimating stages, `L` records of history at each timescale.
+/// A gap in the input flushes the partially accumulated intervals, so no average ever spans one.
+pub struct DecimationChain<L: ArrayLength<Average>> {
+    stages: [Stage<L>; STAGES],
+}
+
+impl<L: ArrayLength<Average>> DecimationChain<L> {
+    pub fn new() -> Self {
+        Self {
+            stages: [Stage::new(), Stage::new(), Stage::new()],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// One sample per second
+    pub fn add(&mut self, frequency: f64, temperature: Option<f64>) {
+        let mut input = Some((frequency, temperature));
+        for stage in self.stages.iter_mut() {
+            input = match input {
+                Some((frequency, temperature)) => stage.add(frequency, temperature)
+                    .map(|average| (average.frequency, average.temperature)),
+                None => break,
+            };
+        }
+    }
+
+    /// Call instead of `add` when a second's sample is missing
+    pub fn gap(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.gap();
+        }
+    }
+
+    pub fn latest(&self, timescale: Timescale) -> Option<&Average> {
+        self.stages[timescale as usize].history.back()
+    }
+
+    /// Oldest first
+    pub fn history(&self, timescale: Timescale) -> impl Iterator<Item = &Average> {
+        self.stages[timescale as usize].history.iter()
+    }
+
+    /// The trailing records not interrupted by a gap, oldest first
+    pub fn contiguous(&self, timescale: Timescale) -> impl Iterator<Item = &Average> {
+        let history = &self.stages[timescale as usize].history;
+        let start = history.iter().rposition(|average| average.after_gap).unwrap_or(0);
+        history.iter().skip(start)
+    }
+
+    pub fn len(&self, timescale: Timescale) -> usize {
+        self.stages[timescale as usize].history.len()
+    }
+
+    /// Inputs accumulated towards the next record
+    pub fn pending(&self, timescale: Timescale) -> u32 {
+        self.stages[timescale as usize].accumulator.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use assert_approx_eq::assert_approx_eq;
+    use typenum::consts::U4;
+
+    use crate::decimation::{DecimationChain, Timescale};
+
+    #[test]
+    fn averages_at_each_timescale() {
+        let mut chain = DecimationChain::<U4>::new();
+
+        for t in 0..999 {
+            chain.add(10e6 + t as f64 * 1e-3, Some(40.0));
+        }
+        assert_eq!(None, chain.latest(Timescale::Seconds1000));
+        assert_eq!(9, chain.pending(Timescale::Seconds10));
+        assert_eq!(9, chain.pending(Timescale::Seconds100));
+        assert_eq!(9, chain.pending(Timescale::Seconds1000));
+
+        chain.add(10e6 + 0.999, Some(40.0));
+        for timescale in Timescale::ALL.iter() {
+            let latest = chain.latest(*timescale).unwrap();
+            let seconds = timescale.seconds() as f64;
+            // the mean of the last `seconds` samples
+            assert_approx_eq!(10e6 + (999.0 - (seconds - 1.0) / 2.0) * 1e-3, latest.frequency, 1e-8);
+            assert_eq!(Some(40.0), latest.temperature);
+            assert!(!latest.after_gap);
+        }
+
+        assert_eq!(4, chain.len(Timescale::Seconds10));
+        assert_eq!(4, chain.len(Timescale::Seconds100));
+        assert_eq!(1, chain.len(Timescale::Seconds1000));
+
+        let history: Vec<f64> = chain.history(Timescale::Seconds100).map(|a| a.frequency).collect();
+        assert_eq!(4, history.len());
+        assert!(history.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn missing_temperature() {
+        let mut chain = DecimationChain::<U4>::new();
+
+        for t in 0..10 {
+            chain.add(10e6, if t < 4 { Some(t as f64) } else { None });
+        }
+        assert_eq!(Some(1.5), chain.latest(Timescale::Seconds10).unwrap().temperature);
+
+        for _ in 0..10 {
+            chain.add(10e6, None);
+        }
+        assert_eq!(None, chain.latest(Timescale::Seconds10).unwrap().temperature);
+    }
+
+    #[test]
+    fn gap_flushes_partial_intervals() {
+        let mut chain = DecimationChain::<U4>::new();
+
+        for _ in 0..25 {
+            chain.add(1.0, None);
+        }
+        assert_eq!(2, chain.len(Timescale::Seconds10));
+        assert_eq!(5, chain.pending(Timescale::Seconds10));
+        assert_eq!(2, chain.pending(Timescale::Seconds100));
+
+        chain.gap();
+        assert_eq!(0, chain.pending(Timescale::Seconds10));
+        assert_eq!(0, chain.pending(Timescale::Seconds100));
+
+        for _ in 0..10 {
+            chain.add(2.0, None);
+        }
+        // none of the samples from before the gap made it into the new record
+        let latest = chain.latest(Timescale::Seconds10).unwrap();
+        assert_eq!(2.0, latest.frequency);
+        assert!(latest.after_gap);
+        assert_eq!(1, chain.pending(Timescale::Seconds100));
+
+        let contiguous: Vec<f64> = chain.contiguous(Timescale::Seconds10).map(|a| a.frequency).collect();
+        assert_eq!(vec![2.0], contiguous);
+
+        for _ in 0..10 {
+            chain.add(3.0, None);
+        }
+        assert!(!chain.latest(Timescale::Seconds10).unwrap().after_gap);
+        let contiguous: Vec<f64> = chain.contiguous(Timescale::Seconds10).map(|a| a.frequency).collect();
+        assert_eq!(vec![2.0, 3.0], contiguous);
+        assert_eq!(4, chain.history(Timescale::Seconds10).count());
+
+        // a gap before anything was recorded is not flagged
+        let mut chain = DecimationChain::<U4>::new();
+        chain.gap();
+        for _ in 0..10 {
+            chain.add(1.0, None);
+        }
+        assert!(!chain.latest(Timescale::Seconds10).unwrap().after_gap);
+    }
+}
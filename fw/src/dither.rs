@@ -0,0 +1,124 @@
+//! Sub-LSB tuning resolution: the DAC gets several codes per control loop tick and the EFC RC filter
+//! and the frequency counter's gate average them
+
+pub trait Modulator {
+    /// Fractional DAC code, clamped to the DAC's range
+    fn set_target(&mut self, code: f64);
+
+    /// To write to the DAC, at a constant rate
+    fn next_code(&mut self) -> u16;
+}
+
+/// First-order error feedback, the average of the codes converges on the target and the
+/// quantization noise is pushed to high frequencies
+pub struct SigmaDelta {
+    max_code: u16,
+    target: f64,
+    error: f64,
+}
+
+impl SigmaDelta {
+    pub fn new(max_code: u16, target: f64) -> Self {
+        let mut modulator = Self {
+            max_code,
+            target: 0.0,
+            error: 0.0,
+        };
+        modulator.set_target(target);
+        modulator
+    }
+}
+
+impl Modulator for SigmaDelta {
+    fn set_target(&mut self, code: f64) {
+        self.target = code.clamp(0.0, self.max_code as f64);
+    }
+
+    fn next_code(&mut self) -> u16 {
+        let wanted = self.target + self.error;
+        let code = libm::floor(wanted + 0.5).clamp(0.0, self.max_code as f64);
+        self.error = wanted - code;
+        code as u16
+    }
+}
+
+/// Triangular PDF dither, unbiased but with white quantization noise
+pub struct TpdfDither {
+    max_code: u16,
+    target: f64,
+    state: u32,
+}
+
+impl TpdfDither {
+    pub fn new(max_code: u16, target: f64, seed: u32) -> Self {
+        let mut modulator = Self {
+            max_code,
+            target: 0.0,
+            // xorshift gets stuck on 0
+            state: seed.max(1),
+        };
+        modulator.set_target(target);
+        modulator
+    }
+
+    /// Uniform in [0, 1), xorshift32
+    fn uniform(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f64 / 4294967296.0
+    }
+}
+
+impl Modulator for TpdfDither {
+    fn set_target(&mut self, code: f64) {
+        self.target = code.clamp(0.0, self.max_code as f64);
+    }
+
+    fn next_code(&mut self) -> u16 {
+        let dither = self.uniform() - self.uniform();
+        libm::floor(self.target + dither + 0.5).clamp(0.0, self.max_code as f64) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    use crate::dither::{Modulator, SigmaDelta, TpdfDither};
+
+    fn average<M: Modulator>(modulator: &mut M, n: usize) -> f64 {
+        (0..n).map(|_| modulator.next_code() as f64).sum::<f64>() / n as f64
+    }
+
+    #[test]
+    fn sigma_delta_average() {
+        let mut modulator = SigmaDelta::new(0xffff, 1000.25);
+        let codes: Vec<u16> = (0..8).map(|_| modulator.next_code()).collect();
+        assert!(codes.iter().all(|&c| c == 1000 || c == 1001));
+        assert_eq!(2, codes.iter().filter(|&&c| c == 1001).count());
+
+        modulator.set_target(1000.3);
+        assert_approx_eq!(1000.3, average(&mut modulator, 1000), 1e-3);
+    }
+
+    #[test]
+    fn sigma_delta_limits() {
+        let mut modulator = SigmaDelta::new(0x3fff, 0x3fff as f64 + 10.0);
+        assert_eq!(0x3fff, modulator.next_code());
+        modulator.set_target(-3.0);
+        assert_eq!(0, modulator.next_code());
+        modulator.set_target(0.5);
+        assert_approx_eq!(0.5, average(&mut modulator, 1000), 1e-3);
+    }
+
+    #[test]
+    fn tpdf_average() {
+        let mut modulator = TpdfDither::new(0xffff, 1000.25, 1);
+        let codes: Vec<u16> = (0..100_000).map(|_| modulator.next_code()).collect();
+        assert!(codes.iter().all(|&c| (999..=1002).contains(&c)));
+        assert_approx_eq!(1000.25, codes.iter().map(|&c| c as f64).sum::<f64>() / codes.len() as f64, 0.01);
+    }
+}
@@ -1,10 +1,44 @@
 use arraydeque::ArrayDeque;
 use generic_array::GenericArray;
 use typenum::Unsigned;
+use core::cmp::Ordering;
 use core::fmt;
 
+pub trait Filter {
+    fn add(&mut self, process_variable: f64);
+
+    fn get(&self) -> f64;
+
+    /// Shifts the filter state as if every sample seen so far had been `adjustment` higher
+    fn apply_adjustment(&mut self, adjustment: f64);
+
+    /// Discards the history, as if the filter had settled at `process_variable`
+    fn reset(&mut self, process_variable: f64);
+
+    /// Whether enough samples have been seen for `get` to be meaningful
+    fn is_ready(&self) -> bool;
+
+    /// Group delay at DC, in samples
+    fn group_delay(&self) -> f64;
+
+    /// Of a constant input, which `apply_adjustment` scales the same way
+    fn dc_gain(&self) -> f64 {
+        1.0
+    }
+}
+
+/// Keeps a running sum, so that both `get` and `apply_adjustment` are O(1)
 pub struct UniformAverageFilter<L: generic_array::ArrayLength<f64>> {
+    /// Samples minus the `offset` at the time they were added
     points: ArrayDeque<GenericArray<f64, L>, arraydeque::Wrapping>,
+    /// Applied to all samples seen so far
+    offset: f64,
+    /// Subtracted from `points` before they enter `sum`
+    base: f64,
+    /// Kahan-compensated `Σ (points - base)`
+    sum: f64,
+    compensation: f64,
+    since_renormalization: usize,
 }
 
 impl <L: generic_array::ArrayLength<f64>> fmt::Display for UniformAverageFilter<L> {
@@ -14,7 +48,7 @@ impl <L: generic_array::ArrayLength<f64>> fmt::Display for UniformAverageFilter<
             if ix != 0 {
                 write!(f, ", ")?;
             }
-            write!(f, "{}", p)?;
+            write!(f, "{}", p + self.offset)?;
         }
         write!(f, "])")?;
         Ok(())
@@ -25,27 +59,75 @@ impl<L: generic_array::ArrayLength<f64>> UniformAverageFilter<L> {
     pub fn new() -> Self {
         Self {
             points: ArrayDeque::new(),
+            offset: 0.0,
+            base: 0.0,
+            sum: 0.0,
+            compensation: 0.0,
+            since_renormalization: 0,
         }
     }
 
-    pub fn add(&mut self, process_variable: f64) -> Option<f64> {
-        self.points.push_back(process_variable)
+    pub fn is_full(&self) -> bool {
+        self.points.len() == self.points.capacity()
     }
 
-    pub fn get(&self) -> f64 {
-        self.points.iter().fold(0.0, |acc, point| {
-            acc + *point
-        }) / (self.points.len() as f64)
+    fn accumulate(&mut self, x: f64) {
+        let y = x - self.compensation;
+        let t = self.sum + y;
+        self.compensation = (t - self.sum) - y;
+        self.sum = t;
     }
 
-    pub fn apply_adjustment(&mut self, adjustment: f64) {
-        for p in self.points.iter_mut() {
-            *p += adjustment;
+    /// Recomputes the sum from scratch to stop rounding errors from piling up, once per window
+    fn renormalize(&mut self) {
+        self.base = *self.points.front().unwrap_or(&0.0);
+        self.sum = 0.0;
+        self.compensation = 0.0;
+        for ix in 0..self.points.len() {
+            self.accumulate(self.points[ix] - self.base);
         }
+        self.since_renormalization = 0;
     }
+}
 
-    pub fn is_full(&self) -> bool {
-        self.points.len() == self.points.capacity()
+impl<L: generic_array::ArrayLength<f64>> Filter for UniformAverageFilter<L> {
+    fn add(&mut self, process_variable: f64) {
+        let x = process_variable - self.offset;
+        if self.points.is_empty() {
+            self.base = x;
+        }
+        if let Some(evicted) = self.points.push_back(x) {
+            self.accumulate(self.base - evicted);
+        }
+        self.accumulate(x - self.base);
+
+        self.since_renormalization += 1;
+        if self.since_renormalization >= self.points.capacity() {
+            self.renormalize();
+        }
+    }
+
+    fn get(&self) -> f64 {
+        self.base + self.offset + self.sum / (self.points.len() as f64)
+    }
+
+    fn apply_adjustment(&mut self, adjustment: f64) {
+        self.offset += adjustment;
+    }
+
+    fn reset(&mut self, process_variable: f64) {
+        *self = Self::new();
+        while !self.is_full() {
+            self.add(process_variable);
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.is_full()
+    }
+
+    fn group_delay(&self) -> f64 {
+        (self.points.len().max(1) - 1) as f64 / 2.0
     }
 }
 
@@ -82,24 +164,161 @@ impl<L: generic_array::ArrayLength<f64>> ConvolutionFilter<L> {
         Self::new(filter_response.unwrap())
     }
 
-    pub fn add(&mut self, process_variable: f64) -> Option<f64> {
-        self.points.push_front(process_variable)
+    /// Blackman-windowed sinc, the cutoff being a period in samples like in `BiquadFilter`
+    pub fn new_windowed_sinc_low_pass(cutoff_period: f64) -> Self {
+        let l = <L as Unsigned>::to_usize();
+        let m = (l - 1) as f64;
+        let fc = 1.0 / cutoff_period;
+        let filter_response = GenericArray::from_exact_iter((0..l).map(|ix| {
+            let n = ix as f64;
+            let x = n - m / 2.0;
+            let sinc = if x == 0.0 {
+                2.0 * fc
+            } else {
+                libm::sin(2.0 * core::f64::consts::PI * fc * x) / (core::f64::consts::PI * x)
+            };
+            let window = if l == 1 {
+                1.0
+            } else {
+                0.42 - 0.5 * libm::cos(2.0 * core::f64::consts::PI * n / m)
+                    + 0.08 * libm::cos(4.0 * core::f64::consts::PI * n / m)
+            };
+            sinc * window
+        }));
+
+        Self::new_normalized(filter_response.unwrap())
+    }
+
+    /// Least-squares fit of a polynomial of `order` over the window, evaluated at its center.
+    /// With `derivative > 0` this estimates the derivative per sample instead, and the response
+    /// sums to zero rather than to one.
+    pub fn new_savitzky_golay(order: usize, derivative: usize) -> Self {
+        const MAX_ORDER: usize = 4;
+        let l = <L as Unsigned>::to_usize();
+        assert!(order <= MAX_ORDER && order < l && derivative <= order);
+
+        // u runs from 1 at the newest sample to -1 at the oldest, to keep the Gram matrix well
+        // conditioned
+        let half = ((l - 1) as f64 / 2.0).max(1.0);
+        let u = |ix: usize| ((l - 1) as f64 / 2.0 - ix as f64) / half;
+
+        // [G | e_derivative], G[j][k] = Σ u^(j + k)
+        let n = order + 1;
+        let mut m = [[0.0; MAX_ORDER + 2]; MAX_ORDER + 1];
+        for ix in 0..l {
+            for j in 0..n {
+                for k in 0..n {
+                    m[j][k] += libm::pow(u(ix), (j + k) as f64);
+                }
+            }
+        }
+        m[derivative][n] = 1.0;
+
+        for c in 0..n {
+            let pivot = (c..n)
+                .max_by(|a, b| libm::fabs(m[*a][c]).total_cmp(&libm::fabs(m[*b][c])))
+                .unwrap();
+            m.swap(c, pivot);
+            for r in 0..n {
+                if r != c {
+                    let factor = m[r][c] / m[c][c];
+                    for k in c..=n {
+                        m[r][k] -= factor * m[c][k];
+                    }
+                }
+            }
+        }
+
+        // d^k/dt^k = k! · a_k / half^k
+        let scale = (1..=derivative).fold(1.0, |acc, k| acc * k as f64 / half);
+        let filter_response = GenericArray::from_exact_iter((0..l).map(|ix| {
+            scale * (0..n).map(|k| m[k][n] / m[k][k] * libm::pow(u(ix), k as f64)).sum::<f64>()
+        }));
+
+        Self::new(filter_response.unwrap())
+    }
+
+    /// Λ weighting, as used by frequency counters to average phase over overlapping intervals
+    pub fn new_triangular() -> Self {
+        let l = <L as Unsigned>::to_usize();
+        let filter_response = GenericArray::from_exact_iter((0..l).map(|ix| {
+            (ix + 1).min(l - ix) as f64
+        }));
+
+        Self::new_normalized(filter_response.unwrap())
     }
 
-    pub fn get(&self) -> f64 {
+    /// Ω weighting, the frequency estimate of a least-squares fit to phase
+    pub fn new_parabolic() -> Self {
+        let l = <L as Unsigned>::to_usize();
+        let filter_response = GenericArray::from_exact_iter((0..l).map(|ix| {
+            ((ix + 1) * (l - ix)) as f64
+        }));
+
+        Self::new_normalized(filter_response.unwrap())
+    }
+
+    fn new_normalized(mut filter_response: GenericArray<f64, L>) -> Self {
+        let sum: f64 = filter_response.iter().sum();
+        for h in filter_response.iter_mut() {
+            *h /= sum;
+        }
+        Self::new(filter_response)
+    }
+
+    /// `|H|` for a sine with the given period in samples
+    pub fn magnitude_response(&self, period: f64) -> f64 {
+        let w = 2.0 * core::f64::consts::PI / period;
+        let (re, im) = self.filter_response.iter().enumerate()
+            .fold((0.0, 0.0), |(re, im), (ix, h)| {
+                (re + h * libm::cos(w * ix as f64), im - h * libm::sin(w * ix as f64))
+            });
+        libm::sqrt(re * re + im * im)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.points.len() == self.points.capacity()
+    }
+}
+
+impl<L: generic_array::ArrayLength<f64>> Filter for ConvolutionFilter<L> {
+    fn add(&mut self, process_variable: f64) {
+        self.points.push_front(process_variable);
+    }
+
+    fn get(&self) -> f64 {
         self.points.iter().zip(self.filter_response.iter()).fold(0.0, |acc, (point, filter)| {
             acc + (*point * *filter)
         })
     }
 
-    pub fn apply_adjustment(&mut self, adjustment: f64) {
+    fn apply_adjustment(&mut self, adjustment: f64) {
         for p in self.points.iter_mut() {
             *p += adjustment;
         }
     }
 
-    pub fn is_full(&self) -> bool {
-        self.points.len() == self.points.capacity()
+    fn reset(&mut self, process_variable: f64) {
+        self.points.clear();
+        while !self.is_full() {
+            self.points.push_front(process_variable);
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.is_full()
+    }
+
+    fn group_delay(&self) -> f64 {
+        let (moment, sum) = self.filter_response.iter().enumerate()
+            .fold((0.0, 0.0), |(moment, sum), (ix, filter)| {
+                (moment + ix as f64 * *filter, sum + *filter)
+            });
+        moment / sum
+    }
+
+    fn dc_gain(&self) -> f64 {
+        self.filter_response.iter().sum()
     }
 }
 
@@ -132,31 +351,617 @@ pub struct ExponentialAverageFilter {
 
 impl ExponentialAverageFilter {
     pub fn new(tau: u32, initial_process_variable: f64) -> Self {
-        let alpha = 1.0 - libm::expf(-1.0f32 / tau as f32) as f64;
-        Self {
-            alpha,
-            beta: 1.0 - alpha,
+        let mut filter = Self {
+            alpha: 1.0,
+            beta: 0.0,
             process_variable: initial_process_variable,
-        }
+        };
+        filter.set_tau(tau);
+        filter
     }
 
-    pub fn add(&mut self, process_variable: f64) {
+    /// Retunes the time constant, keeping the current estimate
+    pub fn set_tau(&mut self, tau: u32) {
+        self.alpha = 1.0 - libm::expf(-1.0f32 / tau as f32) as f64;
+        self.beta = 1.0 - self.alpha;
+    }
+}
+
+impl Filter for ExponentialAverageFilter {
+    fn add(&mut self, process_variable: f64) {
         self.process_variable = (self.process_variable * self.beta) + (process_variable * self.alpha);
     }
 
-    pub fn get(&self) -> f64 {
+    fn get(&self) -> f64 {
         self.process_variable
     }
 
-    pub fn apply_adjustment(&mut self, adjustment: f64) {
+    fn apply_adjustment(&mut self, adjustment: f64) {
         self.process_variable += adjustment;
     }
+
+    fn reset(&mut self, process_variable: f64) {
+        self.process_variable = process_variable;
+    }
+
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    fn group_delay(&self) -> f64 {
+        self.beta / self.alpha
+    }
+}
+
+/// Running median over the last `L` samples, kept sorted alongside the insertion order
+pub struct MedianFilter<L: generic_array::ArrayLength<f64>> {
+    points: ArrayDeque<GenericArray<f64, L>, arraydeque::Wrapping>,
+    sorted: GenericArray<f64, L>,
+}
+
+impl<L: generic_array::ArrayLength<f64>> MedianFilter<L> {
+    pub fn new() -> Self {
+        Self {
+            points: ArrayDeque::new(),
+            sorted: GenericArray::default(),
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.points.len() == self.points.capacity()
+    }
+
+    fn sorted(&self) -> &[f64] {
+        &self.sorted[..self.points.len()]
+    }
+
+    fn position(sorted: &[f64], process_variable: f64) -> usize {
+        sorted.partition_point(|p| p.total_cmp(&process_variable) == Ordering::Less)
+    }
+
+    fn insert_sorted(&mut self, len: usize, process_variable: f64) {
+        let ix = Self::position(&self.sorted[..len], process_variable);
+        self.sorted.copy_within(ix..len, ix + 1);
+        self.sorted[ix] = process_variable;
+    }
+
+    fn remove_sorted(&mut self, len: usize, process_variable: f64) {
+        let ix = Self::position(&self.sorted[..len], process_variable);
+        self.sorted.copy_within(ix + 1..len, ix);
+    }
+
+    /// Median of the absolute deviations from the median, O(L). NaN when empty, like `get`.
+    pub fn median_absolute_deviation(&self) -> f64 {
+        let sorted = self.sorted();
+        let n = sorted.len();
+        if n == 0 {
+            return f64::NAN;
+        }
+        let median = self.get();
+
+        // deviations grow leftwards from `split` on one side and rightwards on the other,
+        // merging the two gives them in order
+        let split = sorted.partition_point(|p| *p < median);
+        let (mut left, mut right) = (split, split);
+        let (mut lower, mut upper) = (f64::NAN, f64::NAN);
+        for k in 0..=n / 2 {
+            let deviation = if left > 0 && (right == n || median - sorted[left - 1] <= sorted[right] - median) {
+                left -= 1;
+                median - sorted[left]
+            } else {
+                right += 1;
+                sorted[right - 1] - median
+            };
+            if k == (n - 1) / 2 {
+                lower = deviation;
+            }
+            if k == n / 2 {
+                upper = deviation;
+            }
+        }
+        (lower + upper) / 2.0
+    }
+}
+
+impl<L: generic_array::ArrayLength<f64>> Filter for MedianFilter<L> {
+    fn add(&mut self, process_variable: f64) {
+        let len = self.points.len();
+        if let Some(evicted) = self.points.push_back(process_variable) {
+            self.remove_sorted(len, evicted);
+            self.insert_sorted(len - 1, process_variable);
+        } else {
+            self.insert_sorted(len, process_variable);
+        }
+    }
+
+    fn get(&self) -> f64 {
+        let sorted = self.sorted();
+        let n = sorted.len();
+        if n == 0 {
+            f64::NAN
+        } else if n % 2 == 1 {
+            sorted[n / 2]
+        } else {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+        }
+    }
+
+    fn apply_adjustment(&mut self, adjustment: f64) {
+        let len = self.points.len();
+        for p in self.points.iter_mut() {
+            *p += adjustment;
+        }
+        for p in self.sorted[..len].iter_mut() {
+            *p += adjustment;
+        }
+    }
+
+    fn reset(&mut self, process_variable: f64) {
+        self.points.clear();
+        while !self.is_full() {
+            self.add(process_variable);
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.is_full()
+    }
+
+    fn group_delay(&self) -> f64 {
+        (self.points.len().max(1) - 1) as f64 / 2.0
+    }
+}
+
+impl <L: generic_array::ArrayLength<f64>> fmt::Display for MedianFilter<L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MedianFilter<{}>([", <L as Unsigned>::to_u32())?;
+        for (ix, p) in self.points.iter().enumerate() {
+            if ix != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", p)?;
+        }
+        write!(f, "])")?;
+        Ok(())
+    }
+}
+
+/// Replaces samples further than `threshold` standard deviations from the median of the last `L`
+/// samples with that median. The standard deviation is estimated from the median absolute deviation.
+pub struct HampelFilter<L: generic_array::ArrayLength<f64>> {
+    window: MedianFilter<L>,
+    threshold: f64,
+    min_deviation: f64,
+    process_variable: f64,
+    outliers: u32,
+}
+
+impl<L: generic_array::ArrayLength<f64>> HampelFilter<L> {
+    /// Standard deviation over median absolute deviation for normally distributed samples
+    const MAD_SCALE: f64 = 1.4826;
+
+    /// Deviations up to `min_deviation` are always accepted, otherwise quantized readings that
+    /// mostly repeat the same value would have every change reported as an outlier
+    pub fn new(threshold: f64, min_deviation: f64) -> Self {
+        Self {
+            window: MedianFilter::new(),
+            threshold,
+            min_deviation,
+            process_variable: f64::NAN,
+            outliers: 0,
+        }
+    }
+
+    pub fn is_outlier(&self, process_variable: f64) -> bool {
+        if !self.window.is_full() {
+            return false;
+        }
+        let limit = (self.threshold * Self::MAD_SCALE * self.window.median_absolute_deviation())
+            .max(self.min_deviation);
+        libm::fabs(process_variable - self.window.get()) > limit
+    }
+
+    /// Number of samples replaced so far
+    pub fn outliers(&self) -> u32 {
+        self.outliers
+    }
+}
+
+impl<L: generic_array::ArrayLength<f64>> Filter for HampelFilter<L> {
+    fn add(&mut self, process_variable: f64) {
+        if self.is_outlier(process_variable) {
+            self.outliers += 1;
+            self.process_variable = self.window.get();
+        } else {
+            self.process_variable = process_variable;
+        }
+        self.window.add(process_variable);
+    }
+
+    fn get(&self) -> f64 {
+        self.process_variable
+    }
+
+    fn apply_adjustment(&mut self, adjustment: f64) {
+        self.window.apply_adjustment(adjustment);
+        self.process_variable += adjustment;
+    }
+
+    fn reset(&mut self, process_variable: f64) {
+        self.window.reset(process_variable);
+        self.process_variable = process_variable;
+    }
+
+    fn is_ready(&self) -> bool {
+        self.window.is_ready()
+    }
+
+    fn group_delay(&self) -> f64 {
+        0.0
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct RegressionEstimate {
+    /// Average frequency over the latest sample
+    pub frequency: f64,
+    pub frequency_error: f64,
+    /// Frequency change per sample
+    pub drift: f64,
+    pub drift_error: f64,
+}
+
+/// Least-squares fit of `x(t) = a + b·t + c·t²` to the phase accumulated from the last `L - 1`
+/// frequency samples. Unlike averaging frequency, this makes use of every phase sample, which is
+/// what matters when the white phase noise of the PPS dominates.
+pub struct RegressionFilter<L: generic_array::ArrayLength<f64>> {
+    /// Phase, in cycles of `reference`
+    points: ArrayDeque<GenericArray<f64, L>, arraydeque::Wrapping>,
+    reference: f64,
+    /// Applied to all samples added later rather than to the ones already seen, which is
+    /// the same thing as far as a least-squares fit is concerned
+    offset: f64,
+    phase: f64,
+    /// Subtracted from `points` before they enter the sums below
+    base: f64,
+    /// `Σ t^k·x` with `t = 0` at the oldest point
+    sums: [f64; 3],
+    sum_squares: f64,
+    last: f64,
+    since_renormalization: usize,
+}
+
+impl<L: generic_array::ArrayLength<f64>> RegressionFilter<L> {
+    pub fn new() -> Self {
+        Self {
+            points: ArrayDeque::new(),
+            reference: 0.0,
+            offset: 0.0,
+            phase: 0.0,
+            base: 0.0,
+            sums: [0.0; 3],
+            sum_squares: 0.0,
+            last: f64::NAN,
+            since_renormalization: 0,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.points.len() == self.points.capacity()
+    }
+
+    fn add_phase(&mut self, phase: f64) {
+        if let Some(evicted) = self.points.push_back(phase) {
+            let x = evicted - self.base;
+            self.sums[0] -= x;
+            self.sum_squares -= x * x;
+            // the evicted point was at t = 0, the rest move one sample closer
+            self.sums[2] += self.sums[0] - 2.0 * self.sums[1];
+            self.sums[1] -= self.sums[0];
+        }
+
+        let t = (self.points.len() - 1) as f64;
+        let x = phase - self.base;
+        self.sums[0] += x;
+        self.sums[1] += t * x;
+        self.sums[2] += t * t * x;
+        self.sum_squares += x * x;
+
+        self.since_renormalization += 1;
+        if self.since_renormalization >= self.points.capacity() {
+            self.renormalize();
+        }
+    }
+
+    /// Recomputes the sums from scratch to stop rounding errors from piling up, once per window
+    fn renormalize(&mut self) {
+        self.base = *self.points.front().unwrap_or(&0.0);
+        self.sums = [0.0; 3];
+        self.sum_squares = 0.0;
+        for (t, p) in self.points.iter().enumerate() {
+            let t = t as f64;
+            let x = *p - self.base;
+            self.sums[0] += x;
+            self.sums[1] += t * x;
+            self.sums[2] += t * t * x;
+            self.sum_squares += x * x;
+        }
+        self.since_renormalization = 0;
+    }
+
+    /// `Σ t^k` for `t = 0..n`, `k = 0..=4`
+    fn power_sums(n: f64) -> [f64; 5] {
+        let m = n - 1.0;
+        let t1 = m * n / 2.0;
+        [
+            n,
+            t1,
+            m * n * (2.0 * m + 1.0) / 6.0,
+            t1 * t1,
+            m * n * (2.0 * m + 1.0) * (3.0 * m * m + 3.0 * m - 1.0) / 30.0,
+        ]
+    }
+
+    pub fn estimate(&self) -> Option<RegressionEstimate> {
+        let n = self.points.len();
+        if n < 3 {
+            return None;
+        }
+
+        // the normal equations are solved for u = t / scale to keep them well conditioned
+        let scale = (n - 1) as f64;
+        let t = Self::power_sums(n as f64);
+        let u = [
+            t[0],
+            t[1] / scale,
+            t[2] / (scale * scale),
+            t[3] / (scale * scale * scale),
+            t[4] / (scale * scale * scale * scale),
+        ];
+        let y = [self.sums[0], self.sums[1] / scale, self.sums[2] / (scale * scale)];
+
+        let m = [[u[0], u[1], u[2]], [u[1], u[2], u[3]], [u[2], u[3], u[4]]];
+        let cofactor = |r: usize, c: usize| {
+            let (r0, r1) = ((r + 1) % 3, (r + 2) % 3);
+            let (c0, c1) = ((c + 1) % 3, (c + 2) % 3);
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let det = m[0][0] * cofactor(0, 0) + m[0][1] * cofactor(0, 1) + m[0][2] * cofactor(0, 2);
+        let mut inverse = [[0.0; 3]; 3];
+        for r in 0..3 {
+            for c in 0..3 {
+                inverse[r][c] = cofactor(c, r) / det;
+            }
+        }
+
+        let mut beta = [0.0; 3];
+        for r in 0..3 {
+            beta[r] = (0..3).map(|c| inverse[r][c] * y[c]).sum();
+        }
+
+        let residual = (self.sum_squares - (0..3).map(|k| beta[k] * y[k]).sum::<f64>()).max(0.0);
+        let variance = if n > 3 { residual / (n - 3) as f64 } else { 0.0 };
+
+        // back from u to t
+        let (b, c) = (beta[1] / scale, beta[2] / (scale * scale));
+        let var_b = variance * inverse[1][1] / (scale * scale);
+        let var_c = variance * inverse[2][2] / (scale * scale * scale * scale);
+        let cov_bc = variance * inverse[1][2] / (scale * scale * scale);
+
+        // x[t] - x[t - 1] at the newest point
+        let k = 2.0 * scale - 1.0;
+        Some(RegressionEstimate {
+            frequency: self.reference + self.offset + b + c * k,
+            frequency_error: libm::sqrt((var_b + k * k * var_c + 2.0 * k * cov_bc).max(0.0)),
+            drift: 2.0 * c,
+            drift_error: 2.0 * libm::sqrt(var_c),
+        })
+    }
+}
+
+impl<L: generic_array::ArrayLength<f64>> Filter for RegressionFilter<L> {
+    fn add(&mut self, process_variable: f64) {
+        if self.points.is_empty() {
+            self.reset(process_variable);
+        }
+        self.last = process_variable;
+        self.phase += process_variable - self.offset - self.reference;
+        self.add_phase(self.phase);
+    }
+
+    fn get(&self) -> f64 {
+        self.estimate().map(|e| e.frequency).unwrap_or(self.last)
+    }
+
+    fn apply_adjustment(&mut self, adjustment: f64) {
+        self.offset += adjustment;
+        self.last += adjustment;
+    }
+
+    fn reset(&mut self, process_variable: f64) {
+        *self = Self::new();
+        self.reference = process_variable;
+        self.last = process_variable;
+        self.add_phase(0.0);
+    }
+
+    fn is_ready(&self) -> bool {
+        self.is_full()
+    }
+
+    /// The fit is evaluated at the newest sample, with drift taken into account
+    fn group_delay(&self) -> f64 {
+        0.0
+    }
+}
+
+/// Second-order IIR section in direct form I, normalized so that `a0 = 1`
+pub struct BiquadFilter {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl BiquadFilter {
+    pub fn new(b: [f64; 3], a: [f64; 3], initial_process_variable: f64) -> Self {
+        let mut filter = Self {
+            b0: b[0] / a[0],
+            b1: b[1] / a[0],
+            b2: b[2] / a[0],
+            a1: a[1] / a[0],
+            a2: a[2] / a[0],
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        };
+        filter.reset(initial_process_variable);
+        filter
+    }
+
+    /// Bilinear transform low-pass with the cutoff given as a period, in samples
+    pub fn new_low_pass(cutoff: f64, q: f64, initial_process_variable: f64) -> Self {
+        let w0 = 2.0 * core::f64::consts::PI / cutoff;
+        let cos_w0 = libm::cos(w0);
+        let alpha = libm::sin(w0) / (2.0 * q);
+
+        Self::new(
+            [(1.0 - cos_w0) / 2.0, 1.0 - cos_w0, (1.0 - cos_w0) / 2.0],
+            [1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha],
+            initial_process_variable,
+        )
+    }
+
+    pub fn new_butterworth_low_pass(cutoff: f64, initial_process_variable: f64) -> Self {
+        Self::new_butterworth_low_pass_section(cutoff, 2, 0, initial_process_variable)
+    }
+
+    /// Section `section` out of `order / 2` of an even order Butterworth low-pass
+    pub fn new_butterworth_low_pass_section(cutoff: f64, order: u32, section: u32, initial_process_variable: f64) -> Self {
+        let q = 1.0 / (2.0 * libm::sin(
+            core::f64::consts::PI * (2 * section + 1) as f64 / (2 * order) as f64
+        ));
+        Self::new_low_pass(cutoff, q, initial_process_variable)
+    }
+
+    /// No overshoot in the step response
+    pub fn new_critically_damped_low_pass(cutoff: f64, initial_process_variable: f64) -> Self {
+        Self::new_low_pass(cutoff, 0.5, initial_process_variable)
+    }
+
+}
+
+impl Filter for BiquadFilter {
+    fn add(&mut self, process_variable: f64) {
+        let y = self.b0 * process_variable + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = process_variable;
+        self.y2 = self.y1;
+        self.y1 = y;
+    }
+
+    fn get(&self) -> f64 {
+        self.y1
+    }
+
+    fn apply_adjustment(&mut self, adjustment: f64) {
+        let output_adjustment = adjustment * self.dc_gain();
+        self.x1 += adjustment;
+        self.x2 += adjustment;
+        self.y1 += output_adjustment;
+        self.y2 += output_adjustment;
+    }
+
+    fn reset(&mut self, process_variable: f64) {
+        let output = process_variable * self.dc_gain();
+        self.x1 = process_variable;
+        self.x2 = process_variable;
+        self.y1 = output;
+        self.y2 = output;
+    }
+
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    fn group_delay(&self) -> f64 {
+        (self.b1 + 2.0 * self.b2) / (self.b0 + self.b1 + self.b2)
+            - (self.a1 + 2.0 * self.a2) / (1.0 + self.a1 + self.a2)
+    }
+
+    fn dc_gain(&self) -> f64 {
+        (self.b0 + self.b1 + self.b2) / (1.0 + self.a1 + self.a2)
+    }
+}
+
+/// Feeds the output of `first` into `second`
+pub struct CascadeFilter<A: Filter, B: Filter> {
+    first: A,
+    second: B,
+}
+
+impl<A: Filter, B: Filter> CascadeFilter<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl CascadeFilter<BiquadFilter, BiquadFilter> {
+    /// 4th order Butterworth low-pass
+    pub fn new_butterworth_low_pass(cutoff: f64, initial_process_variable: f64) -> Self {
+        Self::new(
+            BiquadFilter::new_butterworth_low_pass_section(cutoff, 4, 0, initial_process_variable),
+            BiquadFilter::new_butterworth_low_pass_section(cutoff, 4, 1, initial_process_variable),
+        )
+    }
+}
+
+impl<A: Filter, B: Filter> Filter for CascadeFilter<A, B> {
+    fn add(&mut self, process_variable: f64) {
+        self.first.add(process_variable);
+        self.second.add(self.first.get());
+    }
+
+    fn get(&self) -> f64 {
+        self.second.get()
+    }
+
+    fn apply_adjustment(&mut self, adjustment: f64) {
+        self.first.apply_adjustment(adjustment);
+        // it sees the first stage's output
+        self.second.apply_adjustment(adjustment * self.first.dc_gain());
+    }
+
+    fn reset(&mut self, process_variable: f64) {
+        self.first.reset(process_variable);
+        self.second.reset(self.first.get());
+    }
+
+    fn is_ready(&self) -> bool {
+        self.first.is_ready() && self.second.is_ready()
+    }
+
+    fn group_delay(&self) -> f64 {
+        self.first.group_delay() + self.second.group_delay()
+    }
+
+    fn dc_gain(&self) -> f64 {
+        self.first.dc_gain() * self.second.dc_gain()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::filter::{ConvolutionFilter, ExponentialAverageFilter};
-    use typenum::consts::U4;
+    use crate::filter::{BiquadFilter, CascadeFilter, ConvolutionFilter, ExponentialAverageFilter, Filter,
+                        HampelFilter, MedianFilter, RegressionFilter, UniformAverageFilter};
+    use typenum::consts::{U4, U5, U9, U61};
     use assert_approx_eq::assert_approx_eq;
 
     #[test]
@@ -229,4 +1034,458 @@ mod tests {
         filter.add(2.0);
         assert_approx_eq!(1.86, filter.get(), 0.01);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn exponential_filter_set_tau() {
+        let mut filter = ExponentialAverageFilter::new(600, 1.0);
+        assert_approx_eq!(599.5, filter.group_delay(), 0.01);
+
+        filter.set_tau(60);
+        assert_eq!(1.0, filter.get());
+        assert_approx_eq!(59.5, filter.group_delay(), 0.01);
+    }
+
+    #[test]
+    fn uniform_filter() {
+        let mut filter = UniformAverageFilter::<U4>::new();
+
+        filter.add(1.0);
+        assert!(!filter.is_ready());
+        assert_eq!(1.0, filter.get());
+
+        filter.add(2.0);
+        filter.add(3.0);
+        filter.add(4.0);
+        assert!(filter.is_ready());
+        assert_eq!(2.5, filter.get());
+        assert_eq!(1.5, filter.group_delay());
+
+        filter.add(5.0);
+        assert_eq!(3.5, filter.get());
+
+        filter.apply_adjustment(1.0);
+        assert_eq!(4.5, filter.get());
+
+        filter.reset(2.0);
+        assert!(filter.is_ready());
+        assert_eq!(2.0, filter.get());
+    }
+
+    #[test]
+    fn uniform_filter_running_sum_does_not_drift() {
+        let mut filter = UniformAverageFilter::<U61>::new();
+        let mut points = vec![];
+
+        for ix in 0..100_000 {
+            let f = 10e6 + 0.1 * ((ix * 7919) % 17) as f64 - 0.8 + 1e-5 * ix as f64;
+            filter.add(f);
+            points.push(f);
+            if ix % 7 == 0 {
+                filter.apply_adjustment(0.003);
+                for p in points.iter_mut() {
+                    *p += 0.003;
+                }
+            }
+        }
+
+        let window = &points[points.len() - 61..];
+        let mean = window.iter().sum::<f64>() / window.len() as f64;
+        assert_approx_eq!(mean, filter.get(), 1e-8);
+    }
+
+    #[test]
+    fn ramp_filter_group_delay() {
+        assert_eq!(1.0, ConvolutionFilter::<U4>::new_linear_ramp_down().group_delay());
+        assert_eq!(2.0, ConvolutionFilter::<U4>::new_linear_ramp_up().group_delay());
+    }
+
+    #[test]
+    fn windowed_sinc_low_pass_response() {
+        let filter = ConvolutionFilter::<U61>::new_windowed_sinc_low_pass(20.0);
+
+        assert_approx_eq!(1.0, filter.dc_gain(), 1e-12);
+        assert_approx_eq!(30.0, filter.group_delay(), 1e-9);
+        assert_approx_eq!(1.0, filter.magnitude_response(100.0), 0.01);
+        assert_approx_eq!(0.5, filter.magnitude_response(20.0), 0.05);
+        assert!(filter.magnitude_response(6.0) < 1e-3);
+        assert!(filter.magnitude_response(2.0) < 1e-3);
+    }
+
+    #[test]
+    fn savitzky_golay_smoothing() {
+        let mut filter = ConvolutionFilter::<U9>::new_savitzky_golay(2, 0);
+
+        assert_approx_eq!(1.0, filter.dc_gain(), 1e-12);
+        assert_approx_eq!(4.0, filter.group_delay(), 1e-9);
+        assert_approx_eq!(1.0, filter.magnitude_response(50.0), 1e-3);
+        assert_approx_eq!(41.0 / 231.0, filter.magnitude_response(2.0), 1e-12);
+
+        // a quadratic passes through unchanged, delayed to the center of the window
+        let x = |t: f64| 3.0 - 0.5 * t + 0.25 * t * t;
+        for t in 0..9 {
+            filter.add(x(t as f64));
+        }
+        assert_approx_eq!(x(4.0), filter.get(), 1e-9);
+    }
+
+    #[test]
+    fn savitzky_golay_derivative() {
+        let mut filter = ConvolutionFilter::<U9>::new_savitzky_golay(2, 1);
+
+        assert_approx_eq!(0.0, filter.dc_gain(), 1e-12);
+        // an ideal differentiator has |H| = ω at low frequencies
+        let period = 100.0;
+        assert_approx_eq!(2.0 * core::f64::consts::PI / period, filter.magnitude_response(period), 1e-3);
+
+        let x = |t: f64| 3.0 - 0.5 * t + 0.25 * t * t;
+        for t in 0..9 {
+            filter.add(x(t as f64));
+        }
+        assert_approx_eq!(-0.5 + 0.5 * 4.0, filter.get(), 1e-9);
+    }
+
+    #[test]
+    fn triangular_and_parabolic_weighting() {
+        let triangular = ConvolutionFilter::<U9>::new_triangular();
+        assert_approx_eq!(1.0, triangular.dc_gain(), 1e-12);
+        assert_approx_eq!(4.0, triangular.group_delay(), 1e-12);
+        // two cascaded 5 sample boxcars
+        assert_approx_eq!(0.0, triangular.magnitude_response(5.0), 1e-12);
+        assert_approx_eq!(1.0 / 25.0, triangular.magnitude_response(2.0), 1e-12);
+
+        let parabolic = ConvolutionFilter::<U9>::new_parabolic();
+        assert_approx_eq!(1.0, parabolic.dc_gain(), 1e-12);
+        assert_approx_eq!(4.0, parabolic.group_delay(), 1e-12);
+        assert!(parabolic.magnitude_response(2.0) < triangular.magnitude_response(2.0));
+        assert!(parabolic.magnitude_response(20.0) < 1.0);
+    }
+
+    #[test]
+    fn exponential_filter_reset() {
+        let mut filter = ExponentialAverageFilter::new(4, 1.0);
+        assert!(filter.is_ready());
+
+        filter.add(2.0);
+        filter.reset(3.0);
+        assert_eq!(3.0, filter.get());
+        assert_approx_eq!(3.52, filter.group_delay(), 0.01);
+    }
+
+    fn step_response<F: Filter>(filter: &mut F, samples: usize) -> Vec<f64> {
+        (0..samples).map(|_| {
+            filter.add(1.0);
+            filter.get()
+        }).collect()
+    }
+
+    fn impulse_response<F: Filter>(filter: &mut F, samples: usize) -> Vec<f64> {
+        (0..samples).map(|ix| {
+            filter.add(if ix == 0 { 1.0 } else { 0.0 });
+            filter.get()
+        }).collect()
+    }
+
+    #[test]
+    fn biquad_impulse_response() {
+        let mut filter = BiquadFilter::new([0.5, 0.25, 0.0], [1.0, -0.5, 0.0], 0.0);
+        assert_eq!(1.5, filter.dc_gain());
+
+        let response = impulse_response(&mut filter, 4);
+        assert_eq!(vec![0.5, 0.5, 0.25, 0.125], response);
+    }
+
+    #[test]
+    fn butterworth_step_response() {
+        let mut filter = BiquadFilter::new_butterworth_low_pass(20.0, 0.0);
+        assert_approx_eq!(1.0, filter.dc_gain(), 1e-12);
+
+        let response = step_response(&mut filter, 200);
+        let overshoot = response.iter().cloned().fold(0.0, f64::max) - 1.0;
+        // 4.3% for the analog prototype
+        assert_approx_eq!(0.043, overshoot, 0.005);
+        assert_approx_eq!(1.0, *response.last().unwrap(), 1e-6);
+    }
+
+    #[test]
+    fn critically_damped_step_response() {
+        let mut filter = BiquadFilter::new_critically_damped_low_pass(20.0, 0.0);
+
+        let response = step_response(&mut filter, 200);
+        for (prev, next) in response.iter().zip(response.iter().skip(1)) {
+            assert!(next >= prev);
+            assert!(*next <= 1.0);
+        }
+        assert_approx_eq!(1.0, *response.last().unwrap(), 1e-6);
+    }
+
+    #[test]
+    fn biquad_group_delay_matches_impulse_response() {
+        for filter in [
+            BiquadFilter::new_butterworth_low_pass(20.0, 0.0),
+            BiquadFilter::new_critically_damped_low_pass(50.0, 0.0),
+        ].iter_mut() {
+            let group_delay = filter.group_delay();
+            let response = impulse_response(filter, 2000);
+            let centroid = response.iter().enumerate().map(|(ix, h)| ix as f64 * h).sum::<f64>()
+                / response.iter().sum::<f64>();
+            assert_approx_eq!(centroid, group_delay, 1e-6);
+        }
+    }
+
+    #[test]
+    fn biquad_adjustment_and_reset() {
+        let mut filter = BiquadFilter::new_butterworth_low_pass(20.0, 1.0);
+        filter.add(1.0);
+        assert_approx_eq!(1.0, filter.get(), 1e-12);
+
+        filter.apply_adjustment(1.0);
+        assert_approx_eq!(2.0, filter.get(), 1e-12);
+        filter.add(2.0);
+        assert_approx_eq!(2.0, filter.get(), 1e-12);
+
+        filter.reset(3.0);
+        filter.add(3.0);
+        assert_approx_eq!(3.0, filter.get(), 1e-12);
+    }
+
+    #[test]
+    fn cascaded_butterworth_step_response() {
+        let mut filter = CascadeFilter::new_butterworth_low_pass(20.0, 0.0);
+
+        let group_delay = filter.group_delay();
+        assert_approx_eq!(
+            BiquadFilter::new_butterworth_low_pass_section(20.0, 4, 0, 0.0).group_delay()
+                + BiquadFilter::new_butterworth_low_pass_section(20.0, 4, 1, 0.0).group_delay(),
+            group_delay,
+            1e-12
+        );
+
+        let response = step_response(&mut filter, 400);
+        let overshoot = response.iter().cloned().fold(0.0, f64::max) - 1.0;
+        // 10.8% for the analog prototype
+        assert_approx_eq!(0.108, overshoot, 0.01);
+        assert_approx_eq!(1.0, *response.last().unwrap(), 1e-6);
+
+        filter.apply_adjustment(-1.0);
+        filter.add(0.0);
+        assert_approx_eq!(0.0, filter.get(), 1e-6);
+    }
+
+    #[test]
+    fn cascade_adjustment_through_gain() {
+        let mut filter = CascadeFilter::new(
+            BiquadFilter::new([0.5, 0.25, 0.0], [1.0, -0.5, 0.0], 1.0),
+            BiquadFilter::new_butterworth_low_pass(20.0, 1.5),
+        );
+        assert_approx_eq!(1.5, filter.dc_gain(), 1e-12);
+        filter.add(1.0);
+        assert_approx_eq!(1.5, filter.get(), 1e-12);
+
+        filter.apply_adjustment(1.0);
+        assert_approx_eq!(3.0, filter.get(), 1e-12);
+        filter.add(2.0);
+        assert_approx_eq!(3.0, filter.get(), 1e-12);
+    }
+
+    #[test]
+    fn median_filter() {
+        let mut filter = MedianFilter::<U4>::new();
+        assert!(filter.get().is_nan());
+
+        filter.add(3.0);
+        assert_eq!(3.0, filter.get());
+
+        filter.add(1.0);
+        assert_eq!(2.0, filter.get());
+
+        filter.add(10.0);
+        assert_eq!(3.0, filter.get());
+
+        filter.add(2.0);
+        assert!(filter.is_ready());
+        assert_eq!(2.5, filter.get());
+
+        // evicts the oldest (3.0), not the smallest
+        filter.add(1.0);
+        assert_eq!(1.5, filter.get());
+
+        filter.add(100.0);
+        filter.add(100.0);
+        assert_eq!(51.0, filter.get());
+
+        filter.apply_adjustment(-1.0);
+        assert_eq!(50.0, filter.get());
+        filter.add(99.0);
+        assert_eq!(99.0, filter.get());
+
+        filter.reset(5.0);
+        assert!(filter.is_ready());
+        assert_eq!(5.0, filter.get());
+    }
+
+    #[test]
+    fn median_absolute_deviation() {
+        let mut filter = MedianFilter::<U9>::new();
+        assert!(filter.median_absolute_deviation().is_nan());
+        filter.add(5.0);
+        assert_eq!(0.0, filter.median_absolute_deviation());
+
+        let mut filter = MedianFilter::<U9>::new();
+        for p in [1.0, 1.0, 2.0, 2.0, 4.0, 6.0, 9.0].iter() {
+            filter.add(*p);
+        }
+        // deviations from 2.0: 1, 1, 0, 0, 2, 4, 7
+        assert_eq!(2.0, filter.get());
+        assert_eq!(1.0, filter.median_absolute_deviation());
+
+        filter.add(-3.0);
+        // deviations from 2.0: 5, 1, 1, 0, 0, 2, 4, 7
+        assert_eq!(2.0, filter.get());
+        assert_eq!(1.5, filter.median_absolute_deviation());
+    }
+
+    #[test]
+    fn hampel_filter_replaces_spikes() {
+        let mut filter = HampelFilter::<U5>::new(3.0, 0.0);
+
+        for p in [10.0, 10.2, 9.9, 10.1, 9.8].iter() {
+            filter.add(*p);
+            assert_eq!(*p, filter.get());
+        }
+        assert!(filter.is_ready());
+
+        filter.add(50.0);
+        assert_eq!(10.0, filter.get());
+        assert_eq!(1, filter.outliers());
+
+        filter.add(10.05);
+        assert_eq!(10.05, filter.get());
+        assert_eq!(1, filter.outliers());
+    }
+
+    #[test]
+    fn hampel_filter_follows_steps() {
+        let mut filter = HampelFilter::<U5>::new(3.0, 0.5);
+        filter.reset(0.0);
+
+        // within the minimum deviation despite the zero MAD
+        filter.add(0.25);
+        assert_eq!(0.25, filter.get());
+
+        // replaced by the median until the step is the majority of the window
+        for median in [0.0, 0.0, 0.25].iter() {
+            filter.add(5.0);
+            assert_eq!(*median, filter.get());
+        }
+        filter.add(5.0);
+        assert_eq!(5.0, filter.get());
+        assert_eq!(3, filter.outliers());
+    }
+
+    /// Plain normal equations over the whole window
+    fn reference_regression(frequencies: &[f64]) -> (f64, f64) {
+        let mut x = vec![0.0];
+        for f in frequencies {
+            x.push(x.last().unwrap() + f - frequencies[0]);
+        }
+        let mut a = [[0.0; 4]; 3];
+        for (t, x) in x.iter().enumerate() {
+            let t = t as f64;
+            let row = [1.0, t, t * t];
+            for r in 0..3 {
+                for c in 0..3 {
+                    a[r][c] += row[r] * row[c];
+                }
+                a[r][3] += row[r] * x;
+            }
+        }
+        for p in 0..3 {
+            for r in 0..3 {
+                if r != p {
+                    let k = a[r][p] / a[p][p];
+                    for c in 0..4 {
+                        a[r][c] -= k * a[p][c];
+                    }
+                }
+            }
+        }
+        let (b, c) = (a[1][3] / a[1][1], a[2][3] / a[2][2]);
+        let t = (x.len() - 1) as f64;
+        (frequencies[0] + b + c * (2.0 * t - 1.0), 2.0 * c)
+    }
+
+    #[test]
+    fn regression_filter_exact_for_drifting_frequency() {
+        let mut filter = RegressionFilter::<U61>::new();
+
+        for ix in 0..200 {
+            filter.add(10e6 + 0.5 + 0.001 * ix as f64);
+            if ix >= 2 {
+                let estimate = filter.estimate().unwrap();
+                assert_approx_eq!(10e6 + 0.5 + 0.001 * ix as f64, estimate.frequency, 1e-6);
+                assert_approx_eq!(0.001, estimate.drift, 1e-8);
+                assert!(estimate.frequency_error < 1e-6);
+            }
+        }
+        assert!(filter.is_ready());
+        assert_eq!(0.0, filter.group_delay());
+    }
+
+    #[test]
+    fn regression_filter_matches_reference() {
+        let mut filter = RegressionFilter::<U61>::new();
+        let frequencies: Vec<f64> = (0..500).map(|ix| {
+            10e6 + 0.01 * ((ix * 7919) % 13) as f64 + 1e-5 * ix as f64
+        }).collect();
+
+        for (ix, f) in frequencies.iter().enumerate() {
+            filter.add(*f);
+            if ix >= 60 {
+                let (frequency, drift) = reference_regression(&frequencies[ix - 59..=ix]);
+                let estimate = filter.estimate().unwrap();
+                assert_approx_eq!(frequency, estimate.frequency, 1e-6);
+                assert_approx_eq!(drift, estimate.drift, 1e-8);
+            }
+        }
+    }
+
+    #[test]
+    fn regression_filter_white_phase_noise() {
+        // phase noise telescopes in the per-sample frequency, alternating ±0.1 cycles
+        let mut filter = RegressionFilter::<U61>::new();
+        let mut boxcar = UniformAverageFilter::<U61>::new();
+        let phase_noise = |ix: usize| if ix % 2 == 0 { 0.1 } else { -0.1 };
+        for ix in 1..200 {
+            let f = 10e6 + phase_noise(ix) - phase_noise(ix - 1);
+            filter.add(f);
+            boxcar.add(f);
+        }
+
+        let estimate = filter.estimate().unwrap();
+        assert_approx_eq!(10e6, estimate.frequency, 0.01);
+        assert!(estimate.frequency_error > 0.0);
+        assert!(libm::fabs(estimate.frequency - 10e6) < 3.0 * estimate.frequency_error);
+        assert!(libm::fabs(estimate.frequency - 10e6) < libm::fabs(boxcar.get() - 10e6));
+    }
+
+    #[test]
+    fn regression_filter_adjustment() {
+        let mut filter = RegressionFilter::<U61>::new();
+        for ix in 0..100 {
+            filter.add(10e6 + 0.002 * ix as f64);
+        }
+        let before = filter.estimate().unwrap();
+
+        filter.apply_adjustment(-0.5);
+        let after = filter.estimate().unwrap();
+        assert_approx_eq!(before.frequency - 0.5, after.frequency, 1e-6);
+        assert_approx_eq!(before.drift, after.drift, 1e-8);
+
+        filter.add(10e6 + 0.002 * 100.0 - 0.5);
+        assert_approx_eq!(10e6 + 0.002 * 100.0 - 0.5, filter.get(), 1e-6);
+
+        filter.reset(5.0);
+        assert_eq!(5.0, filter.get());
+        assert!(!filter.is_ready());
+    }
+}
@@ -0,0 +1,430 @@
+//! Q32.32 fixed-point versions of the per-second control path, the PicoRV32 has no FPU.
+//!
+//! With the `fixed-point` feature these cover the frequency from the counters, the loop filter and
+//! the PID. The rest is still soft-float: the start-up estimate, the DAC calibration and
+//! modulator, and the stability, decimation and noise analysis, which get the frequency converted
+//! once a second.
+//!
+//! Of the `filter` module, only `ExponentialAverageFilter` has a fixed-point version, being the
+//! only one the control loop runs. The others are out of scope for this feature:
+//! `RegressionFilter` only runs for the start-up estimate, and the FIR, biquad, median and Hampel
+//! filters are for `FeedbackControl`, which `FixedFeedbackControl` doesn't replace generically.
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fixed(i64);
+
+impl Fixed {
+    pub const FRACTIONAL_BITS: u32 = 32;
+    pub const ZERO: Fixed = Fixed(0);
+    pub const ONE: Fixed = Fixed(1 << Self::FRACTIONAL_BITS);
+    const HALF: i64 = 1 << (Self::FRACTIONAL_BITS - 1);
+
+    pub const fn from_bits(bits: i64) -> Self {
+        Fixed(bits)
+    }
+
+    pub const fn to_bits(self) -> i64 {
+        self.0
+    }
+
+    pub const fn from_int(v: i32) -> Self {
+        Fixed((v as i64) << Self::FRACTIONAL_BITS)
+    }
+
+    /// Soft-float on the MCU, meant for configuration values
+    pub fn from_f64(v: f64) -> Self {
+        Fixed(libm::round(v * Self::ONE.0 as f64) as i64)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / Self::ONE.0 as f64
+    }
+
+    /// `numerator / denominator` rounded to nearest, saturating
+    pub fn from_ratio(numerator: u64, denominator: u64) -> Self {
+        if denominator == 0 {
+            return Fixed::ZERO;
+        }
+        let numerator = (numerator as u128) << Self::FRACTIONAL_BITS;
+        let denominator = denominator as u128;
+        Fixed(((numerator + denominator / 2) / denominator).min(i64::MAX as u128) as i64)
+    }
+
+    /// Rounds half away from zero, like `libm::round`
+    pub fn round(self) -> i64 {
+        if self.0 >= 0 {
+            (self.0 + Self::HALF) >> Self::FRACTIONAL_BITS
+        } else {
+            -((-self.0 + Self::HALF) >> Self::FRACTIONAL_BITS)
+        }
+    }
+
+    pub fn abs(self) -> Self {
+        Fixed(self.0.abs())
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, rhs: Fixed) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0 - rhs.0)
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, rhs: Fixed) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed(-self.0)
+    }
+}
+
+/// Saturating, like `Div`
+impl Mul for Fixed {
+    type Output = Fixed;
+
+    fn mul(self, rhs: Fixed) -> Fixed {
+        let product = (self.0 as i128 * rhs.0 as i128 + Self::HALF as i128) >> Self::FRACTIONAL_BITS;
+        Fixed(product.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+    }
+}
+
+/// Saturating, and zero for a zero divisor like `from_ratio`. No trap on the MCU either way.
+impl Div for Fixed {
+    type Output = Fixed;
+
+    fn div(self, rhs: Fixed) -> Fixed {
+        if rhs.0 == 0 {
+            return Fixed::ZERO;
+        }
+        let quotient = ((self.0 as i128) << Self::FRACTIONAL_BITS) / rhs.0 as i128;
+        Fixed(quotient.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+    }
+}
+
+/// Honors the precision, `{:.03}` etc., defaults to 6 digits
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let precision = f.precision().unwrap_or(6).min(9);
+        let scale = 10u64.pow(precision as u32);
+        let scaled = ((self.0.unsigned_abs() as u128 * scale as u128 + Self::HALF as u128)
+            >> Self::FRACTIONAL_BITS) as u64;
+        let sign = if self.0 < 0 && scaled != 0 { "-" } else { "" };
+        if precision == 0 {
+            write!(f, "{}{}", sign, scaled)
+        } else {
+            write!(f, "{}{}.{:0width$}", sign, scaled / scale, scaled % scale, width = precision)
+        }
+    }
+}
+
+/// Same as `filter::ExponentialAverageFilter`
+pub struct FixedExponentialAverageFilter {
+    process_variable: Fixed,
+    alpha: Fixed,
+}
+
+impl FixedExponentialAverageFilter {
+    pub fn new(tau: u32, initial_process_variable: Fixed) -> Self {
+        Self {
+            alpha: Self::alpha(tau),
+            process_variable: initial_process_variable,
+        }
+    }
+
+    /// Retunes the time constant, keeping the current estimate
+    pub fn set_tau(&mut self, tau: u32) {
+        self.alpha = Self::alpha(tau);
+    }
+
+    /// `1 - exp(-1 / tau)` from its Taylor series, until the terms drop below the resolution
+    fn alpha(tau: u32) -> Fixed {
+        let tau = tau.max(1) as i64;
+        let mut term = Fixed::from_bits(Fixed::ONE.to_bits() / tau);
+        let mut alpha = Fixed::ZERO;
+        let mut k = 1;
+        while term != Fixed::ZERO {
+            if k % 2 == 1 {
+                alpha += term;
+            } else {
+                alpha -= term;
+            }
+            k += 1;
+            term = Fixed::from_bits(term.to_bits() / (k * tau));
+        }
+        alpha
+    }
+
+    pub fn add(&mut self, process_variable: Fixed) {
+        self.process_variable += (process_variable - self.process_variable) * self.alpha;
+    }
+
+    pub fn get(&self) -> Fixed {
+        self.process_variable
+    }
+
+    pub fn apply_adjustment(&mut self, adjustment: Fixed) {
+        self.process_variable += adjustment;
+    }
+}
+
+/// Same as `control::FeedbackControl` with an exponential filter
+pub struct FixedFeedbackControl {
+    frequency: Fixed,
+
+    dac_code: Fixed,
+    max_dac_code: u16,
+    fractional: bool,
+
+    target_frequency: Fixed,
+    control_sensitivity: Fixed,
+    i_factor: Fixed,
+
+    frequency_filter: FixedExponentialAverageFilter,
+    i_error: Fixed,
+    p_factor: Fixed,
+    p_error: Fixed,
+    d_error: Fixed,
+
+    d_factor: Fixed,
+
+    i_error_dead_zone: Fixed,
+}
+
+impl FixedFeedbackControl {
+    pub fn new(
+        dac_code: u16,
+        frequency: Fixed,
+        target_frequency: Fixed,
+        control_sensitivity: Fixed,
+        i_factor: Fixed,
+        p_factor: Fixed,
+        d_factor: Fixed,
+        i_error_dead_zone: Fixed,
+        filter_tau: u32,
+    ) -> Self {
+        Self {
+            target_frequency,
+            frequency,
+            dac_code: Fixed::from_int(dac_code as i32),
+            max_dac_code: 0xffff,
+            fractional: false,
+            control_sensitivity,
+            frequency_filter: FixedExponentialAverageFilter::new(filter_tau, frequency),
+            i_error: Fixed::ZERO,
+            p_error: Fixed::ZERO,
+            d_error: Fixed::ZERO,
+            i_factor,
+            p_factor,
+            d_factor,
+            i_error_dead_zone,
+        }
+    }
+
+    pub fn set_frequency(&mut self, frequency: Fixed) {
+        self.frequency = frequency;
+    }
+
+    pub fn set_filter_tau(&mut self, tau: u32) {
+        self.frequency_filter.set_tau(tau);
+    }
+
+    fn nullify_dead_zone(adj: Fixed, dead_zone: Fixed) -> Fixed {
+        let mag = adj.abs() - dead_zone;
+        if mag.is_negative() {
+            Fixed::ZERO
+        } else if adj.is_negative() {
+            -mag
+        } else {
+            mag
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.frequency_filter.add(self.frequency);
+
+        let p_error = self.target_frequency - self.get_filtered_frequency();
+        self.i_error += p_error;
+        let d_error = p_error - self.p_error;
+        self.p_error = p_error;
+        self.d_error = d_error;
+
+        let p_term = self.get_p_term();
+        let i_term = self.get_i_term();
+        let d_term = self.get_d_term();
+
+        let adj = (p_term + i_term + d_term) / self.control_sensitivity;
+        let adj = if self.fractional { adj } else { Fixed::from_int(adj.round() as i32) };
+
+        if adj != Fixed::ZERO {
+            self.dac_code = (self.dac_code + adj).clamp(Fixed::ZERO, Fixed::from_int(self.max_dac_code as i32));
+
+            self.frequency_filter.apply_adjustment(adj * self.control_sensitivity);
+        }
+    }
+
+    pub fn get_dac_code(&self) -> u16 {
+        self.dac_code.round() as u16
+    }
+
+    pub fn get_fractional_dac_code(&self) -> Fixed {
+        self.dac_code
+    }
+
+    pub fn set_fractional(&mut self, fractional: bool) {
+        self.fractional = fractional;
+    }
+
+    /// For DACs narrower than 16 bits
+    pub fn set_max_dac_code(&mut self, max: u16) {
+        self.max_dac_code = max;
+        self.dac_code = self.dac_code.min(Fixed::from_int(max as i32));
+    }
+
+    pub fn sync_dac_code(&mut self, code: Fixed) {
+        let missed = code - self.dac_code;
+        if missed != Fixed::ZERO {
+            self.frequency_filter.apply_adjustment(missed * self.control_sensitivity);
+            self.dac_code = code;
+        }
+    }
+
+    pub fn get_filtered_frequency(&self) -> Fixed {
+        self.frequency_filter.get()
+    }
+
+    pub fn set_p_factor(&mut self, factor: Fixed) { self.p_factor = factor; }
+
+    pub fn set_i_factor(&mut self, factor: Fixed) { self.i_factor = factor; }
+
+    pub fn set_d_factor(&mut self, factor: Fixed) { self.d_factor = factor; }
+
+    pub fn get_factors(&self) -> (Fixed, Fixed, Fixed) {
+        (self.p_factor, self.i_factor, self.d_factor)
+    }
+
+    pub fn get_i_error(&self) -> Fixed { self.i_error }
+
+    pub fn get_i_term(&self) -> Fixed {
+        Self::nullify_dead_zone(self.i_error, self.i_error_dead_zone) * self.i_factor
+    }
+
+    pub fn get_p_error(&self) -> Fixed { self.p_error }
+
+    pub fn get_p_term(&self) -> Fixed {
+        self.p_error * self.p_factor
+    }
+
+    pub fn get_d_error(&self) -> Fixed { self.d_error }
+
+    pub fn get_d_term(&self) -> Fixed {
+        self.d_error * self.d_factor
+    }
+}
+
+impl fmt::Display for FixedFeedbackControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Target: {:.03}Hz, filtered: {:.04}Hz, last: {:.04}Hz",
+                 self.target_frequency, self.get_filtered_frequency(), self.frequency)?;
+        writeln!(f, "DAC code: {:.03} of {}, fractional: {}", self.dac_code, self.max_dac_code, self.fractional)?;
+        writeln!(f, "Sensitivity: {:.09}Hz/LSB", self.control_sensitivity)?;
+        writeln!(f, "P: {:.09}, error {:.04}, term {:.04}", self.p_factor, self.p_error, self.get_p_term())?;
+        writeln!(f, "I: {:.09}, error {:.04}, term {:.04}, dead zone {}",
+                 self.i_factor, self.i_error, self.get_i_term(), self.i_error_dead_zone)?;
+        writeln!(f, "D: {:.09}, error {:.04}, term {:.04}", self.d_factor, self.d_error, self.get_d_term())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    use crate::filter::{ExponentialAverageFilter, Filter};
+    use crate::fixed::{Fixed, FixedExponentialAverageFilter};
+
+    #[test]
+    fn arithmetic() {
+        let a = Fixed::from_f64(2.5);
+        let b = Fixed::from_int(-4);
+
+        assert_eq!(Fixed::from_f64(-1.5), a + b);
+        assert_eq!(Fixed::from_f64(6.5), a - b);
+        assert_eq!(Fixed::from_int(-10), a * b);
+        assert_eq!(Fixed::from_f64(-0.625), a / b);
+        assert_eq!(Fixed::from_f64(0.1), Fixed::from_ratio(1, 10));
+        assert_eq!(Fixed::ZERO, Fixed::from_ratio(1, 0));
+        assert_eq!(Fixed::ZERO, a / Fixed::ZERO);
+        assert_eq!(Fixed::from_bits(i64::MAX), Fixed::from_int(1 << 20) / Fixed::from_bits(1));
+        assert_eq!(Fixed::from_bits(i64::MIN), Fixed::from_int(1 << 20) / Fixed::from_bits(-1));
+        assert_eq!(Fixed::from_bits(i64::MAX), Fixed::from_int(1 << 20) * Fixed::from_int(1 << 20));
+        assert_eq!(Fixed::from_bits(i64::MIN), Fixed::from_int(1 << 20) * Fixed::from_int(-(1 << 20)));
+
+        assert_eq!(3, a.round());
+        assert_eq!(-3, (-a).round());
+        assert_eq!(2, Fixed::from_f64(2.4999).round());
+        assert_eq!(-4, b.round());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!("10000000.000", format!("{:.03}", Fixed::from_f64(9_999_999.9996)));
+        assert_eq!("-0.125000", format!("{}", Fixed::from_f64(-0.125)));
+        assert_eq!("0.00", format!("{:.02}", Fixed::from_f64(-0.001)));
+        assert_eq!("-3", format!("{:.0}", Fixed::from_f64(-2.7)));
+    }
+
+    #[test]
+    fn alpha_matches_exp() {
+        for tau in [1, 2, 4, 60, 600, 10_000].iter() {
+            let alpha = FixedExponentialAverageFilter::alpha(*tau).to_f64();
+            assert_approx_eq!(1.0 - (-1.0 / *tau as f64).exp(), alpha, 1e-9);
+        }
+    }
+
+    #[test]
+    fn exponential_filter_tracks_f64() {
+        let mut filter = ExponentialAverageFilter::new(600, 10e6);
+        let mut fixed_filter = FixedExponentialAverageFilter::new(600, Fixed::from_f64(10e6));
+
+        for ix in 0..10_000 {
+            let f = 10e6 + 0.1 * ((ix * 7919) % 17) as f64 - 0.8;
+            filter.add(f);
+            fixed_filter.add(Fixed::from_f64(f));
+            if ix % 1000 == 0 {
+                filter.apply_adjustment(0.01);
+                fixed_filter.apply_adjustment(Fixed::from_f64(0.01));
+            }
+            assert_approx_eq!(filter.get(), fixed_filter.get().to_f64(), 1e-6);
+        }
+    }
+}
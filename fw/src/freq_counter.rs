@@ -1,3 +1,4 @@
+use crate::fixed::Fixed;
 use crate::lfsr::{reverse_clk, reverse_sig};
 use volatile_register::RO;
 use core::future::Future;
@@ -26,6 +27,23 @@ pub struct FrequencyCounters {
 }
 
 impl FrequencyCounters {
+    /// Decodes raw LFSR register values as latched by the FPGA `FrequencyCounter`
+    pub fn from_registers(ref_sys: u32, ref_sig: u32, sig_sys: u32, epoch: u8) -> Result<Self, ()> {
+        match (
+            reverse_clk(LFSR32::new(ref_sys)),
+            reverse_sig(LFSR32::new(ref_sig)),
+            reverse_clk(LFSR32::new(sig_sys)),
+        ) {
+            (Some(ref_sys), Some(ref_sig), Some(sig_sys)) => Ok(FrequencyCounters {
+                ref_sys,
+                ref_sig,
+                sig_sys,
+                epoch,
+            }),
+            _ => Err(()),
+        }
+    }
+
     pub fn get_frequency(&self, ref_hz: f64) -> f64 {
         if self.sig_sys == 0 {
             return 0.0;
@@ -33,6 +51,16 @@ impl FrequencyCounters {
 
         ref_hz * (((self.ref_sys as u64) * (self.ref_sig as u64)) as f64) / (self.sig_sys as f64)
     }
+
+    /// `ref_sys`, `ref_sig` and `sig_sys`, decoded
+    pub fn counts(&self) -> (u32, u32, u32) {
+        (self.ref_sys, self.ref_sig, self.sig_sys)
+    }
+
+    /// Same as `get_frequency(1.0)` without going through soft-float
+    pub fn get_frequency_fixed(&self) -> Fixed {
+        Fixed::from_ratio((self.ref_sys as u64) * (self.ref_sig as u64), self.sig_sys as u64)
+    }
 }
 
 pub struct FrequencyCountersToleranceCheck {
@@ -67,19 +95,7 @@ impl FrequencyCounter {
             let epoch2 = unsafe { (*Self::ptr()).epoch.read() } as u8;
 
             if epoch == epoch2 {
-                return match (
-                    reverse_clk(LFSR32::new(ref_sys)),
-                    reverse_sig(LFSR32::new(ref_sig)),
-                    reverse_clk(LFSR32::new(sig_sys)),
-                ) {
-                    (Some(ref_sys), Some(ref_sig), Some(sig_sys)) => Ok(FrequencyCounters {
-                        ref_sys,
-                        ref_sig,
-                        sig_sys,
-                        epoch,
-                    }),
-                    _ => Err(()),
-                };
+                return FrequencyCounters::from_registers(ref_sys, ref_sig, sig_sys, epoch);
             }
         }
     }
@@ -91,6 +107,8 @@ pub struct FrequencyCountersFuture {
 
 struct FrequencyCountersFutureState {
     ready: bool,
+    /// Polled often, the interrupt handler's queue only takes 16
+    registered: bool,
     waker: Option<Waker>,
 }
 
@@ -99,6 +117,7 @@ impl FrequencyCountersFuture {
         FrequencyCountersFuture {
             state: Rc::new(UnsafeCell::new(FrequencyCountersFutureState {
                 ready: false,
+                registered: false,
                 waker: None,
             }))
         }
@@ -116,9 +135,12 @@ impl Future for FrequencyCountersFuture {
                 if state.waker.as_ref().filter(|w| w.will_wake(cx.waker())).is_none() {
                     state.waker = Some(cx.waker().clone());
                 }
-                unsafe {
-                    // requires critical section
-                    FrequencyCounterInterruptHandler::register(Rc::clone(&self.state));
+                if !state.registered {
+                    state.registered = true;
+                    unsafe {
+                        // requires critical section
+                        FrequencyCounterInterruptHandler::register(Rc::clone(&self.state));
+                    }
                 }
             }
             state.ready
@@ -155,3 +177,250 @@ impl FrequencyCounterInterruptHandler {
 static mut FREQUENCY_COUNTER_INTERRUPT_HANDLER: FrequencyCounterInterruptHandler = FrequencyCounterInterruptHandler {
     queue: heapless::spsc::Queue::<Rc<UnsafeCell<FrequencyCountersFutureState>>, 16>::new(),
 };
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use crate::freq_counter::FrequencyCounters;
+
+    const SYS_CLK_HZ: u64 = if cfg!(feature = "hx8k") {
+        201_000_000
+    } else {
+        100_500_000
+    };
+
+    /// `pos_edge_det` from `fpga/src/basics.v`
+    #[derive(Default)]
+    struct PosEdgeDetector {
+        sig_dly: [bool; 3],
+        pos_edge: bool,
+    }
+
+    impl PosEdgeDetector {
+        fn tick(&mut self, sig: bool) {
+            self.pos_edge = self.sig_dly[1] && !self.sig_dly[2];
+            self.sig_dly = [sig, self.sig_dly[0], self.sig_dly[1]];
+        }
+    }
+
+    /// `lfsr32` from `fpga/src/lfsr_32.v`
+    #[derive(Default)]
+    struct Lfsr32 {
+        count: u32,
+    }
+
+    impl Lfsr32 {
+        const SEED0: u32 = 0b00000000000000000000000000000001;
+        const SEED1: u32 = 0b10100011000000000000000000000000;
+
+        fn step(count: u32) -> u32 {
+            // taps 32, 30, 26, 25 (1 based)
+            if count & 1 != 0 {
+                (count >> 1) ^ 0b10100011000000000000000000000000
+            } else {
+                count >> 1
+            }
+        }
+
+        fn tick(&mut self, sig: bool, cutoff: bool) {
+            if cutoff {
+                self.count = if sig { Self::SEED1 } else { Self::SEED0 };
+            } else if sig {
+                self.count = Self::step(self.count);
+            }
+        }
+    }
+
+    /// `FrequencyCounter` from `fpga/src/frequency_counter.sv` together with the epoch counter
+    /// from `fpga/src/gpsdo.v`, clocked by `sys_clk`
+    #[derive(Default)]
+    struct FrequencyCounterModel {
+        ref_edge_det: PosEdgeDetector,
+        sig_edge_det: PosEdgeDetector,
+        state_edge_det: PosEdgeDetector,
+        state: bool,
+
+        sig_sys_counter: Lfsr32,
+        sig_counter: Lfsr32,
+        ref_sys_counter: Lfsr32,
+
+        sig_sys_cnt: u32,
+        sig_cnt: u32,
+        ref_sys_cnt: u32,
+        sample_signal_buf: bool,
+        ref_clk_buf_buf: bool,
+        ready: bool,
+
+        epoch: u8,
+    }
+
+    impl FrequencyCounterModel {
+        /// A single `sys_clk` rising edge, all registers are updated from their previous values
+        fn tick(&mut self, ref_clk: bool, sig_clk: bool) {
+            let ref_clk_buf = self.ref_edge_det.pos_edge;
+            let sig_clk_buf = self.sig_edge_det.pos_edge;
+            let sample_signal = self.state_edge_det.pos_edge;
+            let state = self.state;
+            let sig_sys_cnt_unbuf = self.sig_sys_counter.count;
+            let sig_cnt_unbuf = self.sig_counter.count;
+            let ref_sys_cnt_unbuf = self.ref_sys_counter.count;
+            let sample_signal_buf = self.sample_signal_buf;
+            let ref_clk_buf_buf = self.ref_clk_buf_buf;
+            let ready = self.ready;
+
+            self.ref_edge_det.tick(ref_clk);
+            self.sig_edge_det.tick(sig_clk);
+            self.state_edge_det.tick(state);
+
+            if ref_clk_buf {
+                self.state = false;
+            } else if sig_clk_buf {
+                self.state = true;
+            }
+
+            self.sig_sys_counter.tick(true, sample_signal);
+            self.sig_counter.tick(sig_clk_buf, sample_signal);
+            self.ref_sys_counter.tick(true, ref_clk_buf);
+
+            if sample_signal && !sample_signal_buf {
+                self.sig_sys_cnt = sig_sys_cnt_unbuf;
+                self.sig_cnt = sig_cnt_unbuf;
+                self.sample_signal_buf = true;
+            }
+            if ref_clk_buf && !ref_clk_buf_buf {
+                self.ref_sys_cnt = ref_sys_cnt_unbuf;
+                self.ref_clk_buf_buf = true;
+            }
+            if sample_signal_buf && ref_clk_buf_buf {
+                self.ready = true;
+                self.sample_signal_buf = false;
+                self.ref_clk_buf_buf = false;
+            }
+            if ready {
+                self.ready = false;
+                self.epoch = (self.epoch + 1) & 0b11;
+            }
+        }
+
+        /// What the firmware reads at `0x03000004..0x03000014`
+        fn counters(&self) -> Result<FrequencyCounters, ()> {
+            FrequencyCounters::from_registers(self.ref_sys_cnt, self.sig_cnt, self.sig_sys_cnt, self.epoch)
+        }
+    }
+
+    /// Phase accumulator based square wave, exact for frequencies given in mHz
+    struct ClockSource {
+        phase: u64,
+        step: u64,
+        high: u64,
+    }
+
+    impl ClockSource {
+        const MODULUS: u64 = SYS_CLK_HZ * 1000;
+
+        fn new(frequency_mhz: u64, duty: f64, phase: f64) -> Self {
+            Self {
+                phase: (phase * Self::MODULUS as f64) as u64 % Self::MODULUS,
+                step: frequency_mhz,
+                high: (duty * Self::MODULUS as f64) as u64,
+            }
+        }
+
+        fn tick(&mut self) -> bool {
+            let level = self.phase < self.high;
+            self.phase += self.step;
+            if self.phase >= Self::MODULUS {
+                self.phase -= Self::MODULUS;
+            }
+            level
+        }
+    }
+
+    struct Testbench {
+        pps: ClockSource,
+        ocxo: ClockSource,
+        counter: FrequencyCounterModel,
+    }
+
+    impl Testbench {
+        fn new(pps: ClockSource, ocxo: ClockSource) -> Self {
+            Self {
+                pps,
+                ocxo,
+                counter: Default::default(),
+            }
+        }
+
+        /// Runs until `ready` has been asserted `n` times and returns the counters read afterwards
+        fn run_until_ready(&mut self, n: usize) -> Result<FrequencyCounters, ()> {
+            let mut seen = 0;
+            while seen < n {
+                let was_ready = self.counter.ready;
+                self.counter.tick(self.pps.tick(), self.ocxo.tick());
+                if self.counter.ready && !was_ready {
+                    seen += 1;
+                }
+            }
+            self.counter.counters()
+        }
+    }
+
+    #[test]
+    fn lfsr_seeds_match_fpga() {
+        assert_eq!(Lfsr32::SEED1, Lfsr32::step(Lfsr32::SEED0));
+        assert_eq!(2734686208, Lfsr32::SEED1);
+    }
+
+    #[test]
+    fn partial_first_interval_is_rejected() {
+        let mut tb = Testbench::new(
+            ClockSource::new(1_000, 0.1, 0.9),
+            ClockSource::new(10_000_000_000, 0.5, 0.0),
+        );
+
+        assert!(tb.run_until_ready(1).is_err());
+    }
+
+    #[test]
+    fn exact_frequency_with_simultaneous_pps_and_ocxo_edges() {
+        // both PPS and OCXO rising edges land on the same sys_clk cycle every second,
+        // the PPS wins and sampling is deferred until the next OCXO edge
+        let mut tb = Testbench::new(
+            ClockSource::new(1_000, 0.1, 0.0),
+            ClockSource::new(10_000_000_000, 0.5, 0.0),
+        );
+
+        let counters = tb.run_until_ready(2).unwrap();
+        assert_eq!(SYS_CLK_HZ as u32, counters.ref_sys);
+        assert_eq!(10_000_000, counters.ref_sig);
+        assert_eq!(SYS_CLK_HZ as u32, counters.sig_sys);
+        assert_eq!(10e6, counters.get_frequency(1.0));
+
+        let next = tb.run_until_ready(1).unwrap();
+        assert_eq!((counters.epoch + 1) & 0b11, next.epoch);
+        assert_eq!(10e6, next.get_frequency(1.0));
+    }
+
+    #[test]
+    fn offset_frequency_with_unaligned_edges() {
+        let ocxo_mhz = 10_000_000_370;
+        let mut tb = Testbench::new(
+            ClockSource::new(1_000, 0.1, 0.3),
+            ClockSource::new(ocxo_mhz, 0.5, 0.123),
+        );
+
+        // the first OCXO edge after power-up already samples the signal counters, so the
+        // second reading still spans the interval between that and the first PPS
+        assert!(tb.run_until_ready(1).is_err());
+        assert!(tb.run_until_ready(1).is_err());
+        for _ in 0..2 {
+            let counters = tb.run_until_ready(1).unwrap();
+            assert_eq!(SYS_CLK_HZ as u32, counters.ref_sys);
+            assert!(counters.sig_sys.max(SYS_CLK_HZ as u32) - counters.sig_sys.min(SYS_CLK_HZ as u32) <= 21);
+            // one sys_clk period of sampling uncertainty over a second
+            assert_approx_eq!(ocxo_mhz as f64 / 1000.0, counters.get_frequency(1.0), 0.1);
+            assert_approx_eq!(counters.get_frequency(1.0), counters.get_frequency_fixed().to_f64(), 1e-8);
+        }
+    }
+}
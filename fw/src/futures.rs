@@ -15,4 +15,18 @@ pub fn block_on<F: Future>(f: F) -> F::Output {
             }
         }
     }
+}
+
+/// `block_on`, calling `idle` between polls instead of waiting for an interrupt
+pub fn block_on_with_idle<F: Future, I: FnMut()>(f: F, mut idle: I) -> F::Output {
+    pin_mut!(f);
+
+    let waker = futures::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(t) = f.as_mut().poll(&mut cx) {
+            return t;
+        }
+        idle();
+    }
 }
\ No newline at end of file
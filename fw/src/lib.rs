@@ -16,18 +16,31 @@ extern crate nb;
 #[macro_use]
 pub mod util;
 
+pub mod ad5541;
 pub mod ads1018;
 pub mod allocator;
 pub mod bus;
+pub mod calibration;
+pub mod console;
 pub mod control;
+pub mod dac;
+pub mod decimation;
+pub mod dither;
 pub mod filter;
+pub mod fixed;
 pub mod freq_counter;
 pub mod futures;
 pub mod hal;
 pub mod lfsr;
+pub mod line;
 pub mod max5216;
+pub mod noise;
 pub mod picosoc;
 pub mod reactor;
+pub mod record;
+pub mod scanner;
+pub mod scpi;
+pub mod stability;
 
 #[cfg(test)]
 #[macro_use]
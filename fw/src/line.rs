@@ -0,0 +1,97 @@
+//! Received bytes assembled into lines
+
+use heapless::{String, Vec};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LineError {
+    /// The rest of the line is discarded
+    TooLong,
+    NotUtf8,
+}
+
+/// Takes `\r`, `\n` or both as the line end, skips empty lines and handles backspace
+pub struct LineBuffer<const N: usize> {
+    line: Vec<u8, N>,
+    overflowed: bool,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            overflowed: false,
+        }
+    }
+
+    /// Returns the line once `byte` ends it
+    pub fn push(&mut self, byte: u8) -> Option<Result<String<N>, LineError>> {
+        match byte {
+            b'\r' | b'\n' => {
+                if self.overflowed {
+                    self.overflowed = false;
+                    self.line.clear();
+                    Some(Err(LineError::TooLong))
+                } else if self.line.is_empty() {
+                    None
+                } else {
+                    let result = core::str::from_utf8(&self.line)
+                        .map(|s| {
+                            let mut line = String::new();
+                            // same capacity
+                            line.push_str(s).ok();
+                            line
+                        })
+                        .map_err(|_| LineError::NotUtf8);
+                    self.line.clear();
+                    Some(result)
+                }
+            }
+            0x08 | 0x7f => {
+                self.line.pop();
+                None
+            }
+            _ => {
+                if self.line.push(byte).is_err() {
+                    self.overflowed = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use crate::line::{LineBuffer, LineError};
+
+    fn lines<const N: usize>(buffer: &mut LineBuffer<N>, bytes: &[u8]) -> Vec<Result<String, LineError>> {
+        bytes.iter()
+            .filter_map(|&b| buffer.push(b))
+            .map(|r| r.map(|s| s.as_str().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn line_ends() {
+        let mut buffer = LineBuffer::<16>::new();
+        assert_eq!(vec![
+            Ok("a".to_string()), Ok("bc".to_string()), Ok("d".to_string()),
+        ], lines(&mut buffer, b"a\r\nbc\n\n\rd\r"));
+    }
+
+    #[test]
+    fn backspace() {
+        let mut buffer = LineBuffer::<16>::new();
+        assert_eq!(vec![Ok("ac".to_string())], lines(&mut buffer, b"ab\x7fc\x08\x08\x08ac\n"));
+    }
+
+    #[test]
+    fn errors() {
+        let mut buffer = LineBuffer::<4>::new();
+        assert_eq!(vec![
+            Err(LineError::TooLong), Ok("abcd".to_string()), Err(LineError::NotUtf8),
+        ], lines(&mut buffer, b"abcdefgh\nabcd\n\xff\xfe\n"));
+    }
+}
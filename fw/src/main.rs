@@ -22,27 +22,51 @@ extern crate alloc;
 #[cfg(test)]
 extern crate std;
 
+use core::arch::asm;
 use core::fmt::Write;
 use embedded_hal::digital::v1_compat::{OldOutputPin, OldInputPin};
-use embedded_hal::digital::v2::OutputPin;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
 use embedded_hal::spi::MODE_1;
 use embedded_hal::timer::CountDown;
 use ks_gpsdo::freq_counter::{FrequencyCounters, FrequencyCountersToleranceCheck, FrequencyCountersFuture};
+use ks_gpsdo::dac::{SlewLimiter, TuningDac};
+use ks_gpsdo::dither::Modulator;
+#[cfg(not(feature = "tpdf-dither"))]
+use ks_gpsdo::dither::SigmaDelta;
+#[cfg(feature = "tpdf-dither")]
+use ks_gpsdo::dither::TpdfDither;
 use ks_gpsdo::max5216::MAX5216;
 use ks_gpsdo::picosoc::*;
 use picorv32_rt::entry;
 use typenum::consts::*;
 use ufmt::uWrite;
-use ks_gpsdo::filter::UniformAverageFilter;
+use ks_gpsdo::filter::{Filter, RegressionFilter};
 use ks_gpsdo::bus::SharedBusManager;
 use core::sync::atomic;
 use core::sync::atomic::Ordering;
+#[cfg(not(feature = "fixed-point"))]
 use ks_gpsdo::control::FeedbackControl;
+#[cfg(not(feature = "fixed-point"))]
+use ks_gpsdo::filter::ExponentialAverageFilter;
+#[cfg(feature = "fixed-point")]
+use ks_gpsdo::fixed::{Fixed, FixedFeedbackControl};
 #[cfg(not(test))]
 use ks_gpsdo::allocator::RISCVHeap;
 use ks_gpsdo::ads1018::ADS1018;
-use ks_gpsdo::ads1018;
+#[cfg(feature = "efc-loopback")]
+use ks_gpsdo::ads1018::{DataRate, ExternalChannel, Gain};
+use ks_gpsdo::calibration::{calibrate, DacCalibration, LoopbackInput};
+use ks_gpsdo::console::{Command, HELP, parse, ParseError, Term};
+use ks_gpsdo::line::LineBuffer;
 use ks_gpsdo::hal::BusyWaitTimer;
+use ks_gpsdo::stability::StabilityAnalysis;
+use ks_gpsdo::decimation::{DecimationChain, Timescale};
+use ks_gpsdo::noise::NoiseIdentifier;
+use ks_gpsdo::record::Record;
+use ks_gpsdo::scanner::{DEFAULT_SCAN_TABLE, Quantity, Scanner, Telemetry};
+use ks_gpsdo::scpi::{self, Instrument, Scpi};
+#[cfg(feature = "efc-loopback")]
+use ks_gpsdo::scanner::ScanEntry;
 
 #[cfg(not(test))]
 #[allow(non_upper_case_globals)]
@@ -61,17 +85,259 @@ fn alloc_error(_: core::alloc::Layout) -> ! {
     panic!("Allocation failure");
 }
 
-struct ControlLoop<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin, CONSOLE: uWrite + Write> {
+/// Only this runs in fixed point with `fixed-point`, see `ks_gpsdo::fixed`
+#[cfg(not(feature = "fixed-point"))]
+type Control = FeedbackControl;
+#[cfg(feature = "fixed-point")]
+type Control = FixedFeedbackControl;
+
+/// Control loop defaults, `*RST` goes back to these
+const P_FACTOR: f64 = 0.1;
+const I_FACTOR: f64 = 0.001;
+const D_FACTOR: f64 = 0.05;
+const I_ERROR_DEAD_ZONE: f64 = 0.01;
+const FILTER_TAU: u32 = 600;
+/// The gains were tuned around a 600 s filter, keep within a factor of 4 of that
+const MIN_FILTER_TAU: u32 = 150;
+const MAX_FILTER_TAU: u32 = 2400;
+
+/// Busy-wait between DAC updates while waiting for the counters, several a second
+const DAC_UPDATE_WAIT_CYCLES: u32 = 100_000;
+/// Under a byte time at 115200 Bd at the 12 MHz CPU clock
+const UART_POLL_CYCLES: u32 = 50;
+/// Per DAC update, about 0.1Hz on the test OCXO
+const MAX_DAC_STEP: u16 = 512;
+
+/// The EFC node on AIN3, behind the EFC divider. The calibration normalizes the divider's gain and
+/// any offset out, in volts as read.
+#[cfg(feature = "efc-loopback")]
+const EFC_LOOPBACK: LoopbackInput = LoopbackInput {
+    channel: ExternalChannel::Channel3,
+    gain: Gain::FSR_6_144V,
+    data_rate: DataRate::_128SPS,
+    scale: 1.0,
+    offset: 0.0,
+};
+/// Without the ambient temperature, AIN3 is taken by `EFC_LOOPBACK`
+#[cfg(feature = "efc-loopback")]
+const SCAN_TABLE: [ScanEntry; 4] = [
+    DEFAULT_SCAN_TABLE[0],
+    DEFAULT_SCAN_TABLE[1],
+    DEFAULT_SCAN_TABLE[2],
+    DEFAULT_SCAN_TABLE[4],
+];
+
+/// Between the fractional DAC code from the control loop and the DAC
+#[cfg(not(feature = "tpdf-dither"))]
+type DacModulator = SigmaDelta;
+#[cfg(feature = "tpdf-dither")]
+type DacModulator = TpdfDither;
+
+#[cfg(not(feature = "tpdf-dither"))]
+fn dac_modulator(max_code: u16, target: f64) -> DacModulator {
+    SigmaDelta::new(max_code, target)
+}
+#[cfg(feature = "tpdf-dither")]
+fn dac_modulator(max_code: u16, target: f64) -> DacModulator {
+    TpdfDither::new(max_code, target, 0x2545_f491)
+}
+
+/// Set from the console or over SCPI, kept across control loop restarts until `*RST`
+struct Settings {
+    holdover: bool,
+    manual_dac: Option<u16>,
+    p_factor: f64,
+    i_factor: f64,
+    d_factor: f64,
+    filter_tau: u32,
+    /// Picked by `noise` rather than set
+    auto_filter_tau: bool,
+    /// Instead of the per-second text log, not affected by `*RST`
+    records: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            holdover: false,
+            manual_dac: None,
+            p_factor: P_FACTOR,
+            i_factor: I_FACTOR,
+            d_factor: D_FACTOR,
+            filter_tau: FILTER_TAU,
+            auto_filter_tau: true,
+            records: false,
+        }
+    }
+}
+
+/// The servo loop state the console and SCPI commands act on
+struct Session<'a, DAC: TuningDac, M: Modulator> {
+    feedback_control: &'a mut Control,
+    modulator: &'a mut M,
+    dac: &'a SlewLimiter<DAC>,
+    calibration: &'a DacCalibration,
+    settings: &'a mut Settings,
+    stability: &'a StabilityAnalysis,
+    telemetry: Telemetry,
+    frequency: Option<f64>,
+}
+
+impl<'a, DAC: TuningDac, M: Modulator> Session<'a, DAC, M> {
+    fn mode(&self) -> &'static str {
+        match (self.settings.manual_dac, self.settings.holdover) {
+            (Some(_), _) => "manual DAC",
+            (None, true) => "holdover",
+            (None, false) => "steering",
+        }
+    }
+}
+
+impl<'a, DAC: TuningDac, M: Modulator> Instrument for Session<'a, DAC, M> {
+    fn reset(&mut self) {
+        self.set_dac_code(None).ok();
+        self.settings.holdover = false;
+        self.set_gain(Term::P, P_FACTOR).ok();
+        self.set_gain(Term::I, I_FACTOR).ok();
+        self.set_gain(Term::D, D_FACTOR).ok();
+        self.feedback_control.set_filter_tau(FILTER_TAU);
+        self.settings.filter_tau = FILTER_TAU;
+        self.settings.auto_filter_tau = true;
+    }
+
+    fn frequency(&self) -> Option<f64> {
+        self.frequency
+    }
+
+    fn filtered_frequency(&self) -> Option<f64> {
+        #[cfg(not(feature = "fixed-point"))]
+        let frequency = self.feedback_control.get_filtered_frequency();
+        #[cfg(feature = "fixed-point")]
+        let frequency = self.feedback_control.get_filtered_frequency().to_f64();
+        Some(frequency)
+    }
+
+    fn telemetry(&self, quantity: Quantity) -> Option<f64> {
+        self.telemetry.get(quantity).map(|reading| reading.latest)
+    }
+
+    fn is_locked(&self) -> bool {
+        self.frequency.is_some() && !self.settings.holdover && self.settings.manual_dac.is_none()
+    }
+
+    fn stability(&self) -> &StabilityAnalysis {
+        self.stability
+    }
+
+    fn dac_code(&self) -> u16 {
+        self.dac.code()
+    }
+
+    fn set_dac_code(&mut self, code: Option<u16>) -> Result<(), scpi::Error> {
+        match code {
+            Some(code) => {
+                if code > self.dac.max_code() {
+                    return Err(scpi::Error::DataOutOfRange);
+                }
+                self.settings.manual_dac = Some(code);
+                self.modulator.set_target(code as f64);
+            }
+            None => {
+                if self.settings.manual_dac.take().is_some() {
+                    // carry on from where it was left
+                    let code = self.calibration.ideal_code(self.dac.target() as f64);
+                    #[cfg(not(feature = "fixed-point"))]
+                    self.feedback_control.sync_dac_code(code);
+                    #[cfg(feature = "fixed-point")]
+                    self.feedback_control.sync_dac_code(Fixed::from_f64(code));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn time_constant(&self) -> u32 {
+        self.settings.filter_tau
+    }
+
+    fn set_time_constant(&mut self, seconds: u32) -> Result<(), scpi::Error> {
+        if !(MIN_FILTER_TAU..=MAX_FILTER_TAU).contains(&seconds) {
+            return Err(scpi::Error::DataOutOfRange);
+        }
+        self.feedback_control.set_filter_tau(seconds);
+        self.settings.filter_tau = seconds;
+        self.settings.auto_filter_tau = false;
+        Ok(())
+    }
+
+    fn gain(&self, term: Term) -> f64 {
+        #[cfg(not(feature = "fixed-point"))]
+        let (p, i, d) = self.feedback_control.get_factors();
+        #[cfg(feature = "fixed-point")]
+        let (p, i, d) = {
+            let (p, i, d) = self.feedback_control.get_factors();
+            (p.to_f64(), i.to_f64(), d.to_f64())
+        };
+        match term {
+            Term::P => p,
+            Term::I => i,
+            Term::D => d,
+        }
+    }
+
+    fn set_gain(&mut self, term: Term, factor: f64) -> Result<(), scpi::Error> {
+        match term {
+            Term::P => self.settings.p_factor = factor,
+            Term::I => self.settings.i_factor = factor,
+            Term::D => self.settings.d_factor = factor,
+        }
+        #[cfg(feature = "fixed-point")]
+        let factor = Fixed::from_f64(factor);
+        match term {
+            Term::P => self.feedback_control.set_p_factor(factor),
+            Term::I => self.feedback_control.set_i_factor(factor),
+            Term::D => self.feedback_control.set_d_factor(factor),
+        }
+        Ok(())
+    }
+
+    fn holdover(&self) -> bool {
+        self.settings.holdover
+    }
+
+    fn set_holdover(&mut self, holdover: bool) -> Result<(), scpi::Error> {
+        self.settings.holdover = holdover;
+        Ok(())
+    }
+
+    fn records(&self) -> bool {
+        self.settings.records
+    }
+
+    fn set_records(&mut self, records: bool) -> Result<(), scpi::Error> {
+        self.settings.records = records;
+        Ok(())
+    }
+}
+
+struct ControlLoop<DAC: TuningDac, CONSOLE: uWrite + Write> {
     console: CONSOLE,
     tolerance_check: FrequencyCountersToleranceCheck,
-    dac: MAX5216<SPI, CS>,
+    dac: SlewLimiter<DAC>,
+    calibration: DacCalibration,
+    stability: StabilityAnalysis,
+    history: DecimationChain<U16>,
+    noise: NoiseIdentifier,
     last_epoch: Option<u8>,
     error_flag: bool,
     output_flag: bool,
+    line: LineBuffer<64>,
+    scpi: Scpi,
+    settings: Settings,
 }
 
-impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin, CONSOLE: uWrite + Write> ControlLoop<SPI, CS, CONSOLE> {
-    pub fn new(spi: SPI, cs: CS, console: CONSOLE) -> Self {
+impl<DAC: TuningDac, CONSOLE: uWrite + Write> ControlLoop<DAC, CONSOLE> {
+    pub fn new(dac: SlewLimiter<DAC>, console: CONSOLE, settings: Settings, scpi: Scpi) -> Self {
         Self {
             console,
             tolerance_check: FrequencyCountersToleranceCheck {
@@ -86,17 +352,69 @@ impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin, CONSOLE: uWrite
                 },
                 clk_tolerance: 10_000,
             },
-            dac: MAX5216::new(spi, cs),
+            calibration: DacCalibration::ideal(dac.resolution_bits(), dac.full_scale()),
+            dac,
+            stability: StabilityAnalysis::new(10_000_000.0, 1.0),
+            history: DecimationChain::new(),
+            noise: NoiseIdentifier::new(1.0),
             last_epoch: None,
             error_flag: false,
             output_flag: false,
+            line: LineBuffer::new(),
+            scpi,
+            settings,
         }
     }
 
     pub fn get_counters(&mut self) -> Result<FrequencyCounters, ()> {
-        writeln!(self.console, "Getting counters").ok();
-        let r = ks_gpsdo::futures::block_on(FrequencyCountersFuture::new());
-        writeln!(self.console, "Counters: {:?}", r).ok();
+        self.wait_counters(None::<&mut DacModulator>)
+    }
+
+    /// Ramps the DAC towards its target while waiting, fed from the modulator if any
+    fn wait_counters(&mut self, mut modulator: Option<&mut impl Modulator>) -> Result<FrequencyCounters, ()> {
+        if self.verbose() {
+            writeln!(self.console, "Getting counters").ok();
+        }
+        let dac = &mut self.dac;
+        let mut dac_error = None;
+        let r = ks_gpsdo::futures::block_on_with_idle(FrequencyCountersFuture::new(), || {
+            if let Some(modulator) = modulator.as_mut() {
+                dac.set_target(modulator.next_code());
+            }
+            if let Err(e) = dac.step() {
+                dac_error = Some(e);
+            }
+            for _ in 0..DAC_UPDATE_WAIT_CYCLES / UART_POLL_CYCLES {
+                BusyWaitTimer::new(UART_POLL_CYCLES).wait().ok();
+                uart_poll();
+            }
+        });
+        let r = self.check_epoch(r);
+        if let Some(e) = dac_error {
+            writeln!(self.console, "DAC error: {:?}", e).ok();
+            self.error_flag = true;
+            return Err(());
+        }
+        r
+    }
+
+    /// The per-second log gets in the way of SCPI responses, and is replaced by binary records
+    fn verbose(&self) -> bool {
+        !self.scpi.is_remote() && !self.settings.records
+    }
+
+    /// Discards the counters until the DAC is there
+    fn settle_dac(&mut self) -> Result<(), ()> {
+        while !self.dac.is_settled() {
+            self.get_counters()?;
+        }
+        Ok(())
+    }
+
+    fn check_epoch(&mut self, r: Result<FrequencyCounters, ()>) -> Result<FrequencyCounters, ()> {
+        if self.verbose() {
+            writeln!(self.console, "Counters: {:?}", r).ok();
+        }
 
         if let (Ok(counters), Some(last_epoch)) = (r, self.last_epoch) {
             if (last_epoch + 1) & 0b11 != counters.epoch {
@@ -128,10 +446,16 @@ impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin, CONSOLE: uWrite
         }
     }
 
+    /// Ramped, see `MAX_DAC_STEP`
+    fn set_dac_code(&mut self, code: u16) -> Result<(), ()> {
+        self.dac.set_target(code);
+        self.settle_dac()
+    }
+
     fn frequency_at_v(&mut self, samples: u8, v: u16) -> Result<f64, ()> {
         let mut f_sum = 0.0;
         writeln!(self.console, "DAC code: {}", v).ok();
-        self.dac.set_v(v);
+        self.set_dac_code(v)?;
         self.get_counters()?;
 
         for _ in 0..samples {
@@ -144,7 +468,8 @@ impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin, CONSOLE: uWrite
 
     fn find_operating_point(&mut self) -> Result<u16, ()> {
         let mut min = 0u16;
-        let mut max = 0xffffu16;
+        let max_code = self.dac.max_code();
+        let mut max = max_code;
 
         let target_freq = self.tolerance_check.target_sig_cnt as f64;
 
@@ -166,7 +491,7 @@ impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin, CONSOLE: uWrite
 
             let max_freq = self.frequency_at_v(samples, max)?;
             if max_freq < target_freq {
-                max = max.saturating_add(1000);
+                max = max.saturating_add(1000).min(max_code);
                 v_limits_adjusted = true;
             }
 
@@ -196,23 +521,145 @@ impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin, CONSOLE: uWrite
 
     fn control_sensitivity(&mut self, op_point: u16) -> Result<f64, ()> {
         let min = op_point.saturating_sub(10000);
-        let max = op_point.saturating_add(10000);
+        let max = op_point.saturating_add(10000).min(self.dac.max_code());
 
         let min_f = self.frequency_at_v(5, min)?;
         let max_f = self.frequency_at_v(5, max)?;
 
-        Ok((max_f - min_f) / ((max - min) as f64))
+        let sensitivity = (max_f - min_f) / ((max - min) as f64);
+        // a dead EFC, the control loop divides by it
+        if sensitivity == 0.0 || !sensitivity.is_finite() {
+            writeln!(self.console, "No control response between DAC codes {} and {}", min, max).ok();
+            return Err(());
+        }
+        Ok(sensitivity)
     }
 
-    pub fn run_servo_loop(&mut self, init_op_point: u16, sensitivity: f64) -> Result<(), ()> {
-        self.dac.set_v(init_op_point);
+    /// Measures the DAC through the loopback input across the code range
+    pub fn calibrate_dac<ADC_SPI, ADC_CS: OutputPin, ADC_MISO: InputPin>(
+        &mut self,
+        adc: &mut ADS1018<ADC_SPI, ADC_CS, ADC_MISO>,
+        loopback: &LoopbackInput,
+    ) -> Result<(), ()>
+        where ADC_SPI: embedded_hal::blocking::spi::Transfer<u8>, ADC_SPI::Error: core::fmt::Debug
+    {
+        let (resolution_bits, full_scale) = (self.dac.resolution_bits(), self.dac.full_scale());
+        let mut adc_error = None;
+        let calibration = calibrate(resolution_bits, full_scale, |code| {
+            self.set_dac_code(code)?;
+            // for the EFC filter to settle
+            self.get_counters().map(|_| ())
+        }, || loopback.read(adc).map_err(|e| adc_error = Some(e)));
+        if let Some(e) = adc_error {
+            writeln!(self.console, "ADC error: {:?}", e).ok();
+        }
+        self.calibration = calibration?;
+        write!(self.console, "{}", self.calibration).ok();
+        Ok(())
+    }
+
+    /// Runs the commands received since the last call, `Err` restarts the control loop. Lines that
+    /// aren't console commands but look like SCPI go to SCPI.
+    fn handle_commands(
+        &mut self,
+        feedback_control: &mut Control,
+        modulator: &mut impl Modulator,
+        scanner: &Scanner,
+        frequency: Option<f64>,
+        can_calibrate: bool,
+    ) -> Result<(), ()> {
+        while let Some(line) = uart_read_line(&mut self.line) {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    writeln!(self.console, "Line error: {:?}", e).ok();
+                    continue;
+                }
+            };
+            let mut session = Session {
+                feedback_control: &mut *feedback_control,
+                modulator: &mut *modulator,
+                dac: &self.dac,
+                calibration: &self.calibration,
+                settings: &mut self.settings,
+                stability: &self.stability,
+                telemetry: scanner.telemetry(),
+                frequency,
+            };
+            let command = match parse(&line) {
+                Ok(command) => command,
+                Err(ParseError::UnknownCommand(_)) if scpi::is_scpi(&line) => {
+                    self.scpi.execute(&line, &mut session, &mut self.console);
+                    continue;
+                }
+                Err(e) => {
+                    writeln!(self.console, "{}", e).ok();
+                    continue;
+                }
+            };
+            let result = match command {
+                Command::Help => {
+                    write!(self.console, "{}", HELP).ok();
+                    Ok(())
+                }
+                Command::Status => {
+                    writeln!(self.console, "Mode: {}, frequency: {:.04?}Hz, filtered: {:.04?}Hz, samples: {}",
+                             session.mode(), frequency, session.filtered_frequency(),
+                             self.stability.samples()).ok();
+                    writeln!(self.console, "DAC code: {}, target: {}, reference gain: {:.06}",
+                             self.dac.code(), self.dac.target(), self.calibration.reference_gain()).ok();
+                    writeln!(self.console, "Filter tau: {}s, errors: {}, UART overruns: {}",
+                             session.time_constant(), self.error_flag, uart_rx_overruns()).ok();
+                    Ok(())
+                }
+                Command::Dump => {
+                    write!(self.console, "{}", session.feedback_control).ok();
+                    Ok(())
+                }
+                Command::Stability => {
+                    write!(self.console, "{}", self.stability).ok();
+                    Ok(())
+                }
+                Command::SetGain(term, factor) => session.set_gain(term, factor),
+                Command::SetFilterTau(tau) => session.set_time_constant(tau),
+                Command::Holdover(holdover) => session.set_holdover(holdover),
+                Command::ManualDac(code) => session.set_dac_code(code),
+                Command::Records(records) => session.set_records(records),
+                Command::Calibrate => {
+                    if can_calibrate {
+                        writeln!(self.console, "Restarting to calibrate").ok();
+                        return Err(());
+                    }
+                    writeln!(self.console, "No loopback input, see the efc-loopback feature").ok();
+                    Ok(())
+                }
+                Command::Reboot => reboot(),
+            };
+            if let Err(e) = result {
+                writeln!(self.console, "{}", e.message()).ok();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn run_servo_loop<ADC_SPI, ADC_CS: OutputPin, ADC_MISO: InputPin>(
+        &mut self,
+        init_op_point: u16,
+        sensitivity: f64,
+        scanner: &mut Scanner,
+        adc: &mut ADS1018<ADC_SPI, ADC_CS, ADC_MISO>,
+        loopback: Option<&LoopbackInput>,
+    ) -> Result<(), ()>
+        where ADC_SPI: embedded_hal::blocking::spi::Transfer<u8>, ADC_SPI::Error: core::fmt::Debug
+    {
+        self.set_dac_code(init_op_point)?;
         self.get_counters()?;
 
         let mut op_point = init_op_point;
 
         writeln!(self.console, "Establishing an initial filter value").ok();
         let initial_filter_value = loop {
-            let mut filter = UniformAverageFilter::<U60>::new();
+            let mut filter = RegressionFilter::<U61>::new();
             while !filter.is_full() {
                 let counters = self.get_counters()?;
                 let raw_freq = counters.get_frequency(1.0);
@@ -220,14 +667,19 @@ impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin, CONSOLE: uWrite
             }
 
             let freq = filter.get();
+            if let Some(estimate) = filter.estimate() {
+                writeln!(self.console, "Frequency: {:.04} ± {:.04}Hz, drift: {:.06} ± {:.06}Hz/s",
+                         estimate.frequency, estimate.frequency_error,
+                         estimate.drift, estimate.drift_error).ok();
+            }
             let p_error = self.tolerance_check.target_sig_cnt as f64 - freq;
 
             let new_op_point = (op_point as i32 + (p_error / sensitivity) as i32)
-                .clamp(0, 0xffff) as u16;
+                .clamp(0, self.dac.max_code() as i32) as u16;
             let adj = new_op_point as i32 - op_point as i32;
 
             if adj != 0 {
-                self.dac.set_v(new_op_point);
+                self.set_dac_code(new_op_point)?;
                 op_point = new_op_point;
             }
 
@@ -241,34 +693,176 @@ impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin, CONSOLE: uWrite
         self.output_flag = true;
         writeln!(self.console, "Frequency is in spec, running a slow control loop now").ok();
 
-        let mut feedback_control = FeedbackControl::new(
-            op_point,
+        // the control loop works in ideal codes, see `DacCalibration`
+        let ideal_op_point = libm::round(self.calibration.ideal_code(op_point as f64))
+            .clamp(0.0, self.dac.max_code() as f64) as u16;
+
+        #[cfg(not(feature = "fixed-point"))]
+        let mut feedback_control = Control::new(
+            ideal_op_point,
             initial_filter_value,
             self.tolerance_check.target_sig_cnt as f64,
             sensitivity,
-            0.001,
-            0.1,
-            0.05,
-            0.01,
+            self.settings.i_factor,
+            self.settings.p_factor,
+            self.settings.d_factor,
+            I_ERROR_DEAD_ZONE,
+            ExponentialAverageFilter::new(self.settings.filter_tau, initial_filter_value),
+        );
+        #[cfg(feature = "fixed-point")]
+        let mut feedback_control = Control::new(
+            ideal_op_point,
+            Fixed::from_f64(initial_filter_value),
+            Fixed::from_int(self.tolerance_check.target_sig_cnt as i32),
+            Fixed::from_f64(sensitivity),
+            Fixed::from_f64(self.settings.i_factor),
+            Fixed::from_f64(self.settings.p_factor),
+            Fixed::from_f64(self.settings.d_factor),
+            Fixed::from_f64(I_ERROR_DEAD_ZONE),
+            self.settings.filter_tau,
         );
+        feedback_control.set_max_dac_code(self.dac.max_code());
+        feedback_control.set_fractional(true);
+        let mut modulator = dac_modulator(self.dac.max_code(), op_point as f64);
+        // set before a restart
+        if let Some(code) = self.settings.manual_dac {
+            modulator.set_target(code as f64);
+        }
+
+        self.stability.reset();
+        self.history.reset();
+        self.noise.reset();
+        scanner.reset_statistics();
 
+        let mut frequency = None;
+        // held between readings, the history wants a temperature every second
+        let mut ocxo_temperature = None;
         loop {
-            if let Some(counters) = self.get_counters().ok() {
+            self.handle_commands(&mut feedback_control, &mut modulator, scanner, frequency, loopback.is_some())?;
+            frequency = None;
+            if let Some(counters) = self.wait_counters(Some(&mut modulator)).ok() {
+                #[cfg(not(feature = "fixed-point"))]
                 let raw_freq = counters.get_frequency(1.0);
-                feedback_control.set_frequency(raw_freq);
-                feedback_control.tick();
+                #[cfg(feature = "fixed-point")]
+                let raw_freq = counters.get_frequency_fixed();
+                if !self.dac.is_settled() {
+                    // still ramping, only part of the last adjustment happened
+                    let code = self.calibration.ideal_code(self.dac.code() as f64);
+                    #[cfg(not(feature = "fixed-point"))]
+                    feedback_control.sync_dac_code(code);
+                    #[cfg(feature = "fixed-point")]
+                    feedback_control.sync_dac_code(Fixed::from_f64(code));
+                }
+                let steering = !self.settings.holdover && self.settings.manual_dac.is_none();
+                if steering {
+                    feedback_control.set_frequency(raw_freq);
+                    feedback_control.tick();
+                }
 
-                let new_op_point = feedback_control.get_dac_code();
+                #[cfg(not(feature = "fixed-point"))]
+                let raw_freq_hz = raw_freq;
+                #[cfg(feature = "fixed-point")]
+                let raw_freq_hz = raw_freq.to_f64();
+                frequency = Some(raw_freq_hz);
+                self.stability.add(raw_freq_hz);
+                // one ADC channel a second, the OCXO temperature comes around every few
+                if let Ok((Quantity::OcxoTemperature, t)) = scanner.scan_next(adc) {
+                    ocxo_temperature = Some(t);
+                }
+                self.history.add(raw_freq_hz, ocxo_temperature);
+                self.noise.add(raw_freq_hz);
+
+                #[cfg(not(feature = "fixed-point"))]
+                let dac_code = feedback_control.get_fractional_dac_code();
+                #[cfg(feature = "fixed-point")]
+                let dac_code = feedback_control.get_fractional_dac_code().to_f64();
+                if steering {
+                    modulator.set_target(self.calibration.corrected_code(dac_code));
+                }
 
-                writeln!(self.console, "freq: {:.03},\traw_freq: {:.03},\terr_i: {:.03}cycles,\tadj: {}",
-                         feedback_control.get_filtered_frequency(), raw_freq,
-                         feedback_control.get_i_error(), new_op_point as i32 - op_point as i32).ok();
-                if new_op_point != op_point {
-                    self.dac.set_v(new_op_point);
-                    op_point = new_op_point;
+                let verbose = self.verbose();
+                if self.settings.records {
+                    let (ref_sys, ref_sig, sig_sys) = counters.counts();
+                    let mut flags = 0;
+                    if steering {
+                        flags |= Record::LOCKED;
+                    }
+                    if self.settings.holdover {
+                        flags |= Record::HOLDOVER;
+                    }
+                    if self.settings.manual_dac.is_some() {
+                        flags |= Record::MANUAL_DAC;
+                    }
+                    #[cfg(not(feature = "fixed-point"))]
+                    let (filtered_frequency, p_term, i_term, d_term) = (
+                        feedback_control.get_filtered_frequency(), feedback_control.get_p_term(),
+                        feedback_control.get_i_term(), feedback_control.get_d_term(),
+                    );
+                    #[cfg(feature = "fixed-point")]
+                    let (filtered_frequency, p_term, i_term, d_term) = (
+                        feedback_control.get_filtered_frequency().to_f64(), feedback_control.get_p_term().to_f64(),
+                        feedback_control.get_i_term().to_f64(), feedback_control.get_d_term().to_f64(),
+                    );
+                    let mut record = Record {
+                        flags,
+                        epoch: counters.epoch,
+                        sequence: self.stability.samples(),
+                        ref_sys,
+                        ref_sig,
+                        sig_sys,
+                        filtered_frequency,
+                        p_term: p_term as f32,
+                        i_term: i_term as f32,
+                        d_term: d_term as f32,
+                        dac_target: dac_code as f32,
+                        dac_code: self.dac.code(),
+                        telemetry: [f32::NAN; Quantity::COUNT],
+                    };
+                    record.set_telemetry(&scanner.telemetry());
+                    uart_write(&record.to_frame());
+                } else if verbose {
+                    writeln!(self.console, "freq: {:.03},\traw_freq: {:.03},\terr_i: {:.03}cycles,\tdac: {:.03}",
+                             feedback_control.get_filtered_frequency(), raw_freq,
+                             feedback_control.get_i_error(), dac_code).ok();
+                }
+                if self.stability.samples() % 600 == 0 {
+                    if verbose {
+                        write!(self.console, "{}", self.stability).ok();
+                        write!(self.console, "{}", scanner.telemetry()).ok();
+                    }
+                    scanner.reset_statistics();
+                    if let Some(loopback) = loopback {
+                        match loopback.read(adc) {
+                            Ok(v) => self.calibration.track_reference(self.dac.code() as f64, v),
+                            Err(e) => {
+                                writeln!(self.console, "ADC error: {:?}", e).ok();
+                            }
+                        }
+                        if verbose {
+                            writeln!(self.console, "DAC reference gain: {:.06}", self.calibration.reference_gain()).ok();
+                        }
+                    }
+                    let tau = self.noise.filter_tau(MIN_FILTER_TAU, MAX_FILTER_TAU);
+                    if let (true, Some(tau)) = (self.settings.auto_filter_tau, tau) {
+                        self.settings.filter_tau = tau;
+                        feedback_control.set_filter_tau(tau);
+                        if verbose {
+                            writeln!(self.console, "Filter tau: {}s", tau).ok();
+                        }
+                    }
+                    if verbose {
+                        for timescale in Timescale::ALL.iter() {
+                            if let Some(average) = self.history.latest(*timescale) {
+                                writeln!(self.console, "{}s average: {:.06}Hz, {:.02?}⁰C", timescale.seconds(),
+                                         average.frequency, average.temperature).ok();
+                            }
+                        }
+                    }
                 }
             } else {
                 self.error_flag = true;
+                self.history.gap();
+                self.noise.gap();
             }
         }
     }
@@ -320,70 +914,30 @@ fn main() -> ! {
 
     BusyWaitTimer::new(100000).wait().ok();
     let mut adc = ADS1018::new(spi.acquire(), adc_cs, GPIO4 {});
+    // a conversion takes longer than the UART holds a byte
+    adc.set_idle(uart_poll);
 
-    loop {
-        match adc.read_temperature(ads1018::DataRate::_128SPS) {
-            Ok(reading) => {
-                writeln!(console, "ADC temperature:           \t{:04x}\t{:.03}⁰C", reading,
-                         reading as f64 * 0.125).ok();
-            }
-            Err(e) => {
-                writeln!(console, "ADC temperature error: {:?}", e).ok();
-            }
-        }
+    #[cfg(not(feature = "efc-loopback"))]
+    let (mut scanner, loopback) = (Scanner::new(&DEFAULT_SCAN_TABLE), None);
+    #[cfg(feature = "efc-loopback")]
+    let (mut scanner, loopback) = (Scanner::new(&SCAN_TABLE), Some(&EFC_LOOPBACK));
+    scanner.scan_all(&mut adc);
+    write!(console, "{}", scanner.telemetry()).ok();
 
-        match adc.read_channel(ads1018::ExternalChannel::Channel0,
-                                                ads1018::Gain::FSR_2_048V,
-                                                ads1018::DataRate::_128SPS) {
-            Ok(reading) => {
-                writeln!(console, "ADC channel 0 (VCOCXO I):  \t{:04x}\t{:.03}A", reading,
-                        reading as f64 * 0.001 * 0.5).ok();
-            }
-            Err(e) => {
-                writeln!(console, "ADC channel 0 error: {:?}", e).ok();
-            }
-        }
-        match adc.read_channel(ads1018::ExternalChannel::Channel1,
-                                                ads1018::Gain::FSR_4_096V,
-                                                ads1018::DataRate::_128SPS) {
-            Ok(reading) => {
-                writeln!(console, "ADC channel 1 (VCOCXO Vcc):\t{:04x}\t{:.03}V", reading,
-                         reading as f64 * 0.002 * (12.0 / 5.0)).ok();
-            }
-            Err(e) => {
-                writeln!(console, "ADC channel 1 error: {:?}", e).ok();
-            }
-        }
-        match adc.read_channel(ads1018::ExternalChannel::Channel2,
-                                                ads1018::Gain::FSR_1_024V,
-                                                ads1018::DataRate::_128SPS) {
-            Ok(reading) => {
-                writeln!(console, "ADC channel 2 (VCOCXO t):  \t{:04x}\t{:.03}⁰C", reading,
-                         ((reading as f64 * 0.0005) - 0.5) * 100.0).ok();
-            }
-            Err(e) => {
-                writeln!(console, "ADC channel 2 error: {:?}", e).ok();
-            }
-        }
-        match adc.read_channel(ads1018::ExternalChannel::Channel3,
-                                                ads1018::Gain::FSR_1_024V,
-                                                ads1018::DataRate::_128SPS) {
-            Ok(reading) => {
-                writeln!(console, "ADC channel 3 (ambient t): \t{:04x}\t{:.03}⁰C", reading,
-                         ((reading as f64 * 0.0005) - 0.5) * 100.0).ok();
-            }
-            Err(e) => {
-                writeln!(console, "ADC channel 3 error: {:?}", e).ok();
-            }
-        }
-        break
-    }
+    // the MAX5216 powers up at zero scale
+    let mut dac = SlewLimiter::new(MAX5216::new(spi.acquire(), dac_cs, 5.0), MAX_DAC_STEP, 0);
 
+    let mut settings = Settings::default();
+    let mut scpi = Scpi::new();
     loop {
-        let mut control_loop = ControlLoop::new(spi.acquire(), dac_cs, console);
+        let mut control_loop = ControlLoop::new(dac, console, settings, scpi);
         let _result: Result<(), ()> = try {
             uwriteln!(&mut console, "Stabilizing").ok();
             control_loop.stabilize()?;
+            if let Some(loopback) = loopback {
+                uwriteln!(&mut console, "Calibrating the DAC").ok();
+                control_loop.calibrate_dac(&mut adc, loopback)?;
+            }
             uwriteln!(&mut console, "Finding operating point").ok();
             let v = control_loop.find_operating_point()?;
             uwriteln!(&mut console, "Estimating control response").ok();
@@ -391,13 +945,26 @@ fn main() -> ! {
             writeln!(&mut console, "Starting control loop with initial op {} and control response of {}Hz per 1 LSB code",
                      v, sensitivity).ok();
 
-            control_loop.run_servo_loop(v, sensitivity)?;
+            control_loop.run_servo_loop(v, sensitivity, &mut scanner, &mut adc, loopback)?;
         };
+        // keeps track of where it's at, and what it was told, across restarts
+        dac = control_loop.dac;
+        settings = control_loop.settings;
+        scpi = control_loop.scpi;
 
         uwriteln!(&mut console, "Restarting").ok();
     }
 }
 
+/// Jumps to the reset vector, the peripherals keep their state
+fn reboot() -> ! {
+    ConsoleDevice {}.flush();
+    unsafe {
+        picorv32::interrupt::disable();
+        asm!("jr {0}", in(reg) 0x0010_0000u32, options(noreturn));
+    }
+}
+
 pub fn timer(_regs: &picorv32_rt::PicoRV32StoredRegisters) {
     let mut console = ConsoleDevice {};
     uwriteln!(&mut console, "IRQ: Timer").ok();
@@ -407,6 +974,7 @@ pub fn illegal_instruction(regs: &picorv32_rt::PicoRV32StoredRegisters) {
     let mut console = ConsoleDevice {};
     uwriteln!(&mut console, "IRQ: Illegal instruction").ok();
     writeln!(&mut console, "{:?}", regs).ok();
+    console.flush();
     loop {
         atomic::compiler_fence(Ordering::SeqCst);
     }
@@ -416,6 +984,7 @@ pub fn bus_error(regs: &picorv32_rt::PicoRV32StoredRegisters) {
     let mut console = ConsoleDevice {};
     uwriteln!(&mut console, "IRQ: Bus error").ok();
     writeln!(&mut console, "{:?}", regs).ok();
+    console.flush();
     loop {
         atomic::compiler_fence(Ordering::SeqCst);
     }
@@ -1,24 +1,114 @@
 use embedded_hal::digital::v2::OutputPin;
 
+use crate::dac::{Error, TuningDac};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PowerDown {
+    HighImpedance,
+    Pulldown100k,
+    Pulldown1k,
+}
+
 pub struct MAX5216<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin> {
     spi: SPI,
     cs: CS,
+    v_ref: f64,
+}
+
+impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin> MAX5216<SPI, CS>
+    where SPI::Error: core::fmt::Debug, CS::Error: core::fmt::Debug
+{
+    pub fn new(spi: SPI, cs: CS, v_ref: f64) -> Self {
+        Self { spi, cs, v_ref }
+    }
+
+    /// Until the next `power_up` or `set_code`
+    pub fn power_down(&mut self, mode: PowerDown) -> Result<(), Error<SPI::Error, CS::Error>> {
+        let pd = match mode {
+            PowerDown::HighImpedance => 0b01u8,
+            PowerDown::Pulldown100k => 0b10u8,
+            PowerDown::Pulldown1k => 0b11u8,
+        };
+        self.write(&[0b1000_0000u8 | pd << 4, 0, 0])
+    }
+
+    /// Back to the last code
+    pub fn power_up(&mut self) -> Result<(), Error<SPI::Error, CS::Error>> {
+        self.write(&[0b1000_0000u8, 0, 0])
+    }
+
+    /// The CS is released even if the transfer fails
+    fn write(&mut self, frame: &[u8; 3]) -> Result<(), Error<SPI::Error, CS::Error>> {
+        self.cs.set_low().map_err(|error| Error::ChipSelectError { error })?;
+        let result = self.spi.write(frame).map_err(|error| Error::BusError { error });
+        let cs_result = self.cs.set_high().map_err(|error| Error::ChipSelectError { error });
+        result.and(cs_result)
+    }
+}
+
+impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin> TuningDac for MAX5216<SPI, CS>
+    where SPI::Error: core::fmt::Debug, CS::Error: core::fmt::Debug
+{
+    type Error = Error<SPI::Error, CS::Error>;
+
+    fn resolution_bits(&self) -> u32 {
+        16
+    }
+
+    fn full_scale(&self) -> f64 {
+        self.v_ref
+    }
+
+    fn set_code(&mut self, code: u16) -> Result<(), Self::Error> {
+        self.write(&[
+            0b0100_0000u8 | ((code & 0b1111_1100_0000_0000u16) >> 10) as u8,
+            (code >> 2) as u8,
+            (code << 6) as u8 & 0b1100_0000u8,
+        ])
+    }
 }
 
-impl<SPI: embedded_hal::blocking::spi::Write<u8>, CS: OutputPin> MAX5216<SPI, CS> {
-    pub fn new(spi: SPI, cs: CS) -> Self {
-        Self { spi, cs }
-    }
-
-    pub fn set_v(&mut self, v: u16) {
-        self.cs.set_low().ok();
-        self.spi
-            .write(&[
-                0b0100_0000u8 | ((v & 0b1111_1100_0000_0000u16) >> 10) as u8,
-                (v >> 2) as u8,
-                (v << 6) as u8 & 0b1100_0000u8,
-            ])
-            .ok();
-        self.cs.set_high().ok();
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use crate::dac::{Error, TuningDac};
+    use crate::dac::mock::{self, Event, Fault};
+    use crate::max5216::{MAX5216, PowerDown};
+
+    #[test]
+    fn frames() {
+        let (spi, cs, bus) = mock::bus();
+        let mut dac = MAX5216::new(spi, cs, 5.0);
+
+        dac.set_code(0xabcd).unwrap();
+        dac.power_down(PowerDown::Pulldown100k).unwrap();
+        dac.power_up().unwrap();
+
+        assert_eq!(vec![
+            Event::CsLow, Event::Write(vec![0b0110_1010, 0b1111_0011, 0b0100_0000]), Event::CsHigh,
+            Event::CsLow, Event::Write(vec![0b1010_0000, 0, 0]), Event::CsHigh,
+            Event::CsLow, Event::Write(vec![0b1000_0000, 0, 0]), Event::CsHigh,
+        ], bus.borrow().events);
+    }
+
+    #[test]
+    fn errors() {
+        let (spi, cs, bus) = mock::bus();
+        let mut dac = MAX5216::new(spi, cs, 5.0);
+
+        bus.borrow_mut().spi_fault = true;
+        match dac.set_code(1) {
+            Err(Error::BusError { error: Fault }) => {}
+            r => panic!("{:?}", r),
+        }
+        // released regardless
+        assert_eq!(vec![Event::CsLow, Event::CsHigh], bus.borrow().events);
+
+        bus.borrow_mut().cs_fault = true;
+        match dac.set_code(1) {
+            Err(Error::ChipSelectError { error: Fault }) => {}
+            r => panic!("{:?}", r),
+        }
     }
 }
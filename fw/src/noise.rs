@@ -0,0 +1,305 @@
+//! Online power-law noise identification from the lag-1 autocorrelation of frequency data
+//! (Riley & Greenhall), at averaging factors of 1, 2, 4 .. 1024 samples
+
+/// Octave-spaced averaging factors
+pub const FACTORS: usize = 11;
+/// Averaged samples the autocorrelation estimates are exponentially weighted over
+const WINDOW: f64 = 1024.0;
+const MIN_SAMPLES: u32 = 32;
+/// Enough to tell random walk FM apart, oscillators don't need more
+const MAX_DIFFERENCES: usize = 2;
+/// Halfway between flicker PM (1) and white FM (0)
+const FM_ALPHA: f64 = 0.5;
+
+/// `S_y(f) ∝ f^α`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NoiseType {
+    WhitePM,
+    FlickerPM,
+    WhiteFM,
+    FlickerFM,
+    RandomWalkFM,
+}
+
+impl NoiseType {
+    pub fn from_alpha(alpha: f64) -> Self {
+        match libm::round(alpha) as i32 {
+            a if a >= 2 => NoiseType::WhitePM,
+            1 => NoiseType::FlickerPM,
+            0 => NoiseType::WhiteFM,
+            -1 => NoiseType::FlickerFM,
+            _ => NoiseType::RandomWalkFM,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct Lag1 {
+    previous: Option<f64>,
+    mean: f64,
+    square: f64,
+    product: f64,
+    n: u32,
+}
+
+impl Lag1 {
+    fn add(&mut self, z: f64) {
+        if let Some(previous) = self.previous {
+            self.n += 1;
+            // a plain average until the window fills up
+            let w = (1.0 / self.n as f64).max(1.0 / WINDOW);
+            self.mean += w * (z - self.mean);
+            self.square += w * (z * z - self.square);
+            self.product += w * (z * previous - self.product);
+        }
+        self.previous = Some(z);
+    }
+
+    fn autocorrelation(&self) -> f64 {
+        let mean_square = self.mean * self.mean;
+        let variance = self.square - mean_square;
+        if variance <= 0.0 {
+            return 0.0;
+        }
+        ((self.product - mean_square) / variance).clamp(-0.99, 0.99)
+    }
+}
+
+/// The frequency data is differenced at least once, so that random walk FM is stationary over
+/// the window instead of passing for a slow drift
+#[derive(Copy, Clone, Default)]
+struct Factor {
+    /// Last value at each differencing order, to form the next one
+    last: [Option<f64>; MAX_DIFFERENCES],
+    /// `lag1[d - 1]` is for the data differenced `d` times
+    lag1: [Lag1; MAX_DIFFERENCES],
+}
+
+impl Factor {
+    fn add(&mut self, y: f64) {
+        let mut z = y;
+        for d in 0..MAX_DIFFERENCES {
+            match self.last[d].replace(z) {
+                Some(previous) => z -= previous,
+                None => break,
+            }
+            self.lag1[d].add(z);
+        }
+    }
+
+    fn gap(&mut self) {
+        self.last = Default::default();
+        for lag1 in self.lag1.iter_mut() {
+            lag1.previous = None;
+        }
+    }
+
+    /// Differences the data until it is stationary enough, `δ < 1/4`
+    fn alpha(&self) -> Option<f64> {
+        if self.lag1[0].n < MIN_SAMPLES {
+            return None;
+        }
+        for d in 1..=MAX_DIFFERENCES {
+            let r1 = self.lag1[d - 1].autocorrelation();
+            let delta = r1 / (1.0 + r1);
+            if delta < 0.25 || d == MAX_DIFFERENCES {
+                return Some((-2.0 * (delta + d as f64)).clamp(-2.0, 2.0));
+            }
+        }
+        None
+    }
+}
+
+pub struct NoiseIdentifier {
+    tau0: f64,
+    reference: Option<f64>,
+    factors: [Factor; FACTORS],
+    /// First halves of the pairs being averaged into each factor from the one below
+    partial: [Option<f64>; FACTORS],
+}
+
+impl NoiseIdentifier {
+    pub fn new(tau0: f64) -> Self {
+        Self {
+            tau0,
+            reference: None,
+            factors: [Factor::default(); FACTORS],
+            partial: [None; FACTORS],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.tau0);
+    }
+
+    pub fn add(&mut self, frequency: f64) {
+        let reference = *self.reference.get_or_insert(frequency);
+        let mut y = frequency - reference;
+        for k in 0..FACTORS {
+            self.factors[k].add(y);
+            if k + 1 == FACTORS {
+                break;
+            }
+            match self.partial[k + 1].take() {
+                Some(first) => y = (first + y) / 2.0,
+                None => {
+                    self.partial[k + 1] = Some(y);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Call instead of `add` when a sample is missing, keeps the statistics gathered so far
+    pub fn gap(&mut self) {
+        self.partial = [None; FACTORS];
+        for factor in self.factors.iter_mut() {
+            factor.gap();
+        }
+    }
+
+    pub fn tau(&self, factor: usize) -> f64 {
+        self.tau0 * (1u32 << factor) as f64
+    }
+
+    /// Estimated power-law exponent, `None` until enough averaged samples have been seen
+    pub fn alpha(&self, factor: usize) -> Option<f64> {
+        self.factors[factor].alpha()
+    }
+
+    pub fn noise_type(&self, factor: usize) -> Option<NoiseType> {
+        self.alpha(factor).map(NoiseType::from_alpha)
+    }
+
+    /// The shortest averaging time at which the oscillator's FM noise takes over from the PM
+    /// noise of the reference, beyond that averaging longer doesn't help. It takes two FM
+    /// factors in a row to tell, as single estimates are noisy. `None` while it can't be told yet.
+    pub fn optimal_tau(&self) -> Option<f64> {
+        let is_fm = |k: usize| self.alpha(k).map(|alpha| alpha < FM_ALPHA);
+        for k in 0..FACTORS {
+            match (is_fm(k), is_fm((k + 1).min(FACTORS - 1))) {
+                (Some(true), Some(true)) => return Some(self.tau(k)),
+                (Some(_), Some(_)) => {}
+                _ => return None,
+            }
+        }
+        Some(self.tau(FACTORS - 1))
+    }
+
+    /// `optimal_tau` for an exponential average, in samples and clamped to `[min_tau, max_tau]`.
+    /// An exponential average over `tau` samples has the white noise bandwidth of a `2·tau` boxcar.
+    pub fn filter_tau(&self, min_tau: u32, max_tau: u32) -> Option<u32> {
+        self.optimal_tau().map(|tau| {
+            (libm::round(tau / (2.0 * self.tau0)) as u32).clamp(min_tau, max_tau)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use rand_distr::StandardNormal;
+
+    use crate::noise::{FACTORS, NoiseIdentifier, NoiseType};
+
+    const RNG_SEED: [u8; 32] = [1, 0, 0, 0, 23, 0, 0, 0, 200, 1, 0, 0, 210, 30, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    /// Long enough for the 1024 sample factor to be estimated over a full window
+    const SAMPLES: usize = 1024 * 256;
+
+    fn identify(mut y: impl FnMut(&mut StdRng) -> f64) -> NoiseIdentifier {
+        let mut rng = StdRng::from_seed(RNG_SEED);
+        let mut noise = NoiseIdentifier::new(1.0);
+        for _ in 0..SAMPLES {
+            noise.add(10e6 + y(&mut rng));
+        }
+        noise
+    }
+
+    #[test]
+    fn not_ready_until_enough_samples() {
+        let mut noise = NoiseIdentifier::new(1.0);
+        let mut y = 1.0;
+        // differenced once, the first two samples only prime the lag
+        for _ in 0..33 {
+            noise.add(10e6 + y);
+            y = -y;
+        }
+        assert_eq!(None, noise.alpha(0));
+        assert_eq!(None, noise.optimal_tau());
+
+        noise.add(10e6 + y);
+        assert_eq!(Some(NoiseType::WhitePM), noise.noise_type(0));
+        assert_eq!(None, noise.alpha(1));
+        assert_eq!(None, noise.optimal_tau());
+    }
+
+    #[test]
+    fn white_pm() {
+        let mut x_previous = 0.0;
+        let noise = identify(|rng| {
+            let x: f64 = rng.sample(StandardNormal);
+            let y = x - x_previous;
+            x_previous = x;
+            y
+        });
+
+        for k in 0..FACTORS {
+            assert_eq!(Some(NoiseType::WhitePM), noise.noise_type(k), "factor {}", k);
+        }
+        // never crosses over, so average as long as allowed
+        assert_eq!(Some(1024.0), noise.optimal_tau());
+        assert_eq!(Some(512), noise.filter_tau(150, 600));
+        assert_eq!(Some(400), noise.filter_tau(150, 400));
+    }
+
+    #[test]
+    fn white_fm() {
+        let noise = identify(|rng| rng.sample(StandardNormal));
+
+        for k in 0..FACTORS {
+            assert_eq!(Some(NoiseType::WhiteFM), noise.noise_type(k), "factor {}", k);
+        }
+        assert_eq!(Some(1.0), noise.optimal_tau());
+        assert_eq!(Some(150), noise.filter_tau(150, 600));
+    }
+
+    #[test]
+    fn random_walk_fm() {
+        let mut y = 0.0;
+        let noise = identify(|rng| {
+            y += rng.sample::<f64, _>(StandardNormal);
+            y
+        });
+
+        for k in 0..FACTORS {
+            assert_eq!(Some(NoiseType::RandomWalkFM), noise.noise_type(k), "factor {}", k);
+        }
+    }
+
+    #[test]
+    fn crossover_from_white_pm_to_random_walk_fm() {
+        // Allan variances: white PM 3σx²/τ², random walk FM σw²·τ/3, equal at τ = (9σx²/σw²)^(1/3)
+        let (sigma_x, sigma_w) = (1.0, 0.01);
+        let crossover = libm::cbrt(9.0 * sigma_x * sigma_x / (sigma_w * sigma_w));
+
+        let mut x_previous = 0.0;
+        let mut walk = 0.0;
+        let noise = identify(|rng| {
+            let x = sigma_x * rng.sample::<f64, _>(StandardNormal);
+            walk += sigma_w * rng.sample::<f64, _>(StandardNormal);
+            let y = x - x_previous + walk;
+            x_previous = x;
+            y
+        });
+
+        let tau = noise.optimal_tau().unwrap();
+        assert!(tau >= crossover / 4.0 && tau <= crossover * 4.0, "{} vs {}", tau, crossover);
+        assert_eq!(Some(NoiseType::WhitePM), noise.noise_type(0));
+        assert_eq!(Some(NoiseType::RandomWalkFM), noise.noise_type(FACTORS - 1));
+    }
+}
@@ -1,15 +1,26 @@
 use core::convert::Infallible;
 use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
 use embedded_hal::digital::v2::{InputPin, OutputPin};
+use heapless::String;
+use heapless::spsc::Queue;
+use picorv32::interrupt;
 use ufmt::uWrite;
 use volatile_register::RW;
 
+use crate::line::{LineBuffer, LineError};
+
 #[repr(C)]
 pub struct UART {
     pub clkdiv: RW<u32>,
-    pub data: RW<u8>,
+    pub data: RW<u32>,
 }
 
+/// Read from `data` when nothing was received
+const UART_RX_EMPTY: u32 = !0;
+
 impl UART {
     fn ptr() -> *const Self {
         0x02000004 as *const _
@@ -24,29 +35,131 @@ impl UART {
             self.clkdiv.write(u32::from(12_000_000 / baud).max(1));
         }
     }
+
+    /// simpleuart holds a single received byte, a new one overwrites it
+    pub fn read(&self) -> Option<u8> {
+        let data = self.data.read();
+        if data == UART_RX_EMPTY {
+            None
+        } else {
+            Some(data as u8)
+        }
+    }
+
+    /// Stalls the bus until the previous byte is out
+    pub fn write(&self, byte: u8) {
+        unsafe { self.data.write(byte as u32) };
+    }
+}
+
+struct UartBuffers {
+    rx: Queue<u8, 64>,
+    tx: Queue<u8, 256>,
+    rx_overruns: u32,
+}
+
+static mut UART_BUFFERS: UartBuffers = UartBuffers {
+    rx: Queue::new(),
+    tx: Queue::new(),
+    rx_overruns: 0,
+};
+
+/// Moves a received byte to the RX buffer and sends one from the TX buffer. The gateware doesn't
+/// raise the UART interrupt, call it more often than once a byte time.
+pub fn uart_poll() {
+    interrupt::free(|_cs| unsafe {
+        let uart = &*UART::ptr();
+        let buffers = &mut UART_BUFFERS;
+        if let Some(byte) = uart.read() {
+            if buffers.rx.enqueue(byte).is_err() {
+                buffers.rx_overruns += 1;
+            }
+        }
+        if let Some(byte) = buffers.tx.dequeue() {
+            uart.write(byte);
+        }
+    })
+}
+
+pub fn uart_read() -> Option<u8> {
+    interrupt::free(|_cs| unsafe { UART_BUFFERS.rx.dequeue() })
+}
+
+/// Through the TX buffer, waits while it's full
+pub fn uart_write(bytes: &[u8]) {
+    for &byte in bytes {
+        while interrupt::free(|_cs| unsafe { UART_BUFFERS.tx.enqueue(byte).is_err() }) {
+            uart_poll();
+        }
+    }
+}
+
+/// Bytes dropped with the RX buffer full. simpleuart has no overrun flag, bytes overwritten in its
+/// holding register between two polls go unnoticed.
+pub fn uart_rx_overruns() -> u32 {
+    interrupt::free(|_cs| unsafe { UART_BUFFERS.rx_overruns })
+}
+
+/// The next complete line, if any, without waiting
+pub fn uart_read_line<const N: usize>(line: &mut LineBuffer<N>) -> Option<Result<String<N>, LineError>> {
+    uart_poll();
+    while let Some(byte) = uart_read() {
+        if let Some(result) = line.push(byte) {
+            return Some(result);
+        }
+    }
+    None
 }
 
+/// Wakes itself up, there's no UART interrupt, so drive it with `futures::block_on_with_idle`
+/// rather than `futures::block_on`
+pub fn read_line<const N: usize>(line: &mut LineBuffer<N>) -> ReadLine<'_, N> {
+    ReadLine { line }
+}
+
+pub struct ReadLine<'a, const N: usize> {
+    line: &'a mut LineBuffer<N>,
+}
+
+impl<'a, const N: usize> Future for ReadLine<'a, N> {
+    type Output = Result<String<N>, LineError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match uart_read_line(&mut self.line) {
+            Some(result) => Poll::Ready(result),
+            None => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Writes through the TX buffer, drained by `uart_poll`. Sends synchronously while it's full.
 #[derive(Clone, Copy)]
 pub struct ConsoleDevice {}
 
+impl ConsoleDevice {
+    /// Before halting, nothing drains the buffer then
+    pub fn flush(&self) {
+        while interrupt::free(|_cs| unsafe { !UART_BUFFERS.tx.is_empty() }) {
+            uart_poll();
+        }
+    }
+}
+
 impl uWrite for ConsoleDevice {
     type Error = Infallible;
 
     fn write_str(&mut self, s: &str) -> Result<(), Infallible> {
-        s.as_bytes().iter().for_each(|b| unsafe {
-            (*UART::ptr()).data.write(*b);
-        });
-
+        uart_write(s.as_bytes());
         Ok(())
     }
 }
 
 impl fmt::Write for ConsoleDevice {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        s.as_bytes().iter().for_each(|b| unsafe {
-            (*UART::ptr()).data.write(*b);
-        });
-
+        uart_write(s.as_bytes());
         Ok(())
     }
 }
@@ -0,0 +1,330 @@
+//! Per-second servo loop metrics as binary records, much cheaper than formatting floats on the
+//! soft-float core.
+//!
+//! A frame is the little-endian record, then its CRC-16/CCITT-FALSE, COBS-encoded and wrapped in
+//! zero bytes. Text on the same UART has no zeros, a decoder splits the stream on them and takes
+//! whatever decodes with a valid CRC.
+
+use byteorder::{ByteOrder, LE};
+use heapless::Vec;
+
+use crate::scanner::{Quantity, Telemetry};
+
+/// Bumped on any layout change, decoders reject other versions
+pub const VERSION: u8 = 1;
+pub const RECORD_LEN: usize = 65;
+const CRC_LEN: usize = 2;
+/// Delimiters on both sides and a COBS overhead byte, enough up to 254 bytes of record
+pub const MAX_FRAME_LEN: usize = RECORD_LEN + CRC_LEN + 3;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    /// Malformed, or not a frame at all
+    Cobs,
+    Length,
+    Crc,
+    UnsupportedVersion(u8),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Record {
+    pub flags: u8,
+    pub epoch: u8,
+    /// Seconds in the servo loop
+    pub sequence: u32,
+    /// Decoded counter values, see `FrequencyCounters::counts`
+    pub ref_sys: u32,
+    pub ref_sig: u32,
+    pub sig_sys: u32,
+    /// Hz
+    pub filtered_frequency: f64,
+    pub p_term: f32,
+    pub i_term: f32,
+    pub d_term: f32,
+    /// From the control loop, fractional and before calibration
+    pub dac_target: f32,
+    /// As written
+    pub dac_code: u16,
+    /// Latest readings in `Quantity` order, NaN when not read
+    pub telemetry: [f32; Quantity::COUNT],
+}
+
+impl Record {
+    /// Steering on valid counters
+    pub const LOCKED: u8 = 1 << 0;
+    pub const HOLDOVER: u8 = 1 << 1;
+    pub const MANUAL_DAC: u8 = 1 << 2;
+
+    /// Hz, same as `FrequencyCounters::get_frequency(1.0)`
+    pub fn frequency(&self) -> f64 {
+        if self.sig_sys == 0 {
+            return 0.0;
+        }
+        ((self.ref_sys as u64) * (self.ref_sig as u64)) as f64 / self.sig_sys as f64
+    }
+
+    /// A steering record measuring `frequency`, for the firmware's and the host tool's tests
+    #[cfg(any(test, feature = "test-fixtures"))]
+    pub fn fixture(sequence: u32, frequency: f64) -> Self {
+        Self {
+            flags: Self::LOCKED,
+            epoch: (sequence & 0b11) as u8,
+            sequence,
+            // as the counters would see it, see `frequency`
+            ref_sys: libm::round(frequency * 20.1) as u32,
+            ref_sig: 10_000_000,
+            sig_sys: 201_000_000,
+            filtered_frequency: 10e6,
+            p_term: 1e-3,
+            i_term: -2e-4,
+            d_term: 0.0,
+            dac_target: 32768.5,
+            dac_code: 32768,
+            telemetry: [0.2, 12.0, 65.0, 25.0, 40.0],
+        }
+    }
+
+    pub fn set_telemetry(&mut self, telemetry: &Telemetry) {
+        for quantity in Quantity::ALL.iter() {
+            self.telemetry[*quantity as usize] = telemetry.get(*quantity)
+                .map(|reading| reading.latest as f32)
+                .unwrap_or(f32::NAN);
+        }
+    }
+
+    pub fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut buf = [0; RECORD_LEN];
+        buf[0] = VERSION;
+        buf[1] = self.flags;
+        buf[2] = self.epoch;
+        LE::write_u32(&mut buf[3..7], self.sequence);
+        LE::write_u32(&mut buf[7..11], self.ref_sys);
+        LE::write_u32(&mut buf[11..15], self.ref_sig);
+        LE::write_u32(&mut buf[15..19], self.sig_sys);
+        LE::write_f64(&mut buf[19..27], self.filtered_frequency);
+        LE::write_f32(&mut buf[27..31], self.p_term);
+        LE::write_f32(&mut buf[31..35], self.i_term);
+        LE::write_f32(&mut buf[35..39], self.d_term);
+        LE::write_f32(&mut buf[39..43], self.dac_target);
+        LE::write_u16(&mut buf[43..45], self.dac_code);
+        LE::write_f32_into(&self.telemetry, &mut buf[45..RECORD_LEN]);
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        match buf.first() {
+            Some(&VERSION) => {}
+            Some(&version) => return Err(DecodeError::UnsupportedVersion(version)),
+            None => return Err(DecodeError::Length),
+        }
+        if buf.len() != RECORD_LEN {
+            return Err(DecodeError::Length);
+        }
+        let mut telemetry = [0.0; Quantity::COUNT];
+        LE::read_f32_into(&buf[45..RECORD_LEN], &mut telemetry);
+        Ok(Self {
+            flags: buf[1],
+            epoch: buf[2],
+            sequence: LE::read_u32(&buf[3..7]),
+            ref_sys: LE::read_u32(&buf[7..11]),
+            ref_sig: LE::read_u32(&buf[11..15]),
+            sig_sys: LE::read_u32(&buf[15..19]),
+            filtered_frequency: LE::read_f64(&buf[19..27]),
+            p_term: LE::read_f32(&buf[27..31]),
+            i_term: LE::read_f32(&buf[31..35]),
+            d_term: LE::read_f32(&buf[35..39]),
+            dac_target: LE::read_f32(&buf[39..43]),
+            dac_code: LE::read_u16(&buf[43..45]),
+            telemetry,
+        })
+    }
+
+    /// Ready to send, delimiters included
+    pub fn to_frame(&self) -> Vec<u8, MAX_FRAME_LEN> {
+        let mut payload = [0; RECORD_LEN + CRC_LEN];
+        payload[..RECORD_LEN].copy_from_slice(&self.encode());
+        let crc = crc16(&payload[..RECORD_LEN]);
+        LE::write_u16(&mut payload[RECORD_LEN..], crc);
+
+        let mut frame = Vec::new();
+        // sized for it
+        frame.push(0).ok();
+        cobs_encode(&payload, &mut frame);
+        frame.push(0).ok();
+        frame
+    }
+
+    /// From the bytes between two delimiters
+    pub fn from_frame(frame: &[u8]) -> Result<Self, DecodeError> {
+        let mut payload = [0; RECORD_LEN + CRC_LEN];
+        let len = cobs_decode(frame, &mut payload)?;
+        if len < CRC_LEN {
+            return Err(DecodeError::Length);
+        }
+        let (record, crc) = payload[..len].split_at(len - CRC_LEN);
+        if crc16(record) != LE::read_u16(crc) {
+            return Err(DecodeError::Crc);
+        }
+        Self::decode(record)
+    }
+}
+
+/// CRC-16/CCITT-FALSE, bit by bit, it's a few dozen bytes a second
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Appends to `out`, which has to have room for one byte more per 254
+fn cobs_encode<const N: usize>(data: &[u8], out: &mut Vec<u8, N>) {
+    let mut code_index = out.len();
+    let mut code = 1u8;
+    out.push(0).ok();
+    for &byte in data {
+        if byte != 0 {
+            out.push(byte).ok();
+            code += 1;
+        }
+        if byte == 0 || code == 0xff {
+            out[code_index] = code;
+            code_index = out.len();
+            code = 1;
+            out.push(0).ok();
+        }
+    }
+    out[code_index] = code;
+}
+
+/// Returns the decoded length
+fn cobs_decode(frame: &[u8], out: &mut [u8]) -> Result<usize, DecodeError> {
+    let mut len = 0;
+    let mut i = 0;
+    while i < frame.len() {
+        let code = frame[i] as usize;
+        if code == 0 || i + code > frame.len() {
+            return Err(DecodeError::Cobs);
+        }
+        for &byte in &frame[i + 1..i + code] {
+            if byte == 0 {
+                return Err(DecodeError::Cobs);
+            }
+            *out.get_mut(len).ok_or(DecodeError::Length)? = byte;
+            len += 1;
+        }
+        i += code;
+        if code < 0xff && i < frame.len() {
+            *out.get_mut(len).ok_or(DecodeError::Length)? = 0;
+            len += 1;
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use assert_approx_eq::assert_approx_eq;
+    use heapless::Vec;
+
+    use crate::record::{cobs_decode, cobs_encode, crc16, DecodeError, MAX_FRAME_LEN, Record, VERSION};
+    use crate::scanner::Quantity;
+
+    fn record(sequence: u32) -> Record {
+        Record {
+            filtered_frequency: 10_000_000.000_123,
+            telemetry: [0.21, 12.0, 65.5, f32::NAN, 41.0],
+            ..Record::fixture(sequence, 10_000_006.119)
+        }
+    }
+
+    /// What a host does with the UART stream
+    fn decode_stream(stream: &[u8]) -> Vec<Result<Record, DecodeError>, 8> {
+        stream.split(|&b| b == 0)
+            .filter(|frame| !frame.is_empty())
+            .map(Record::from_frame)
+            // text, or damaged
+            .filter(|r| !matches!(r, Err(DecodeError::Cobs | DecodeError::Crc | DecodeError::Length)))
+            .collect()
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(0x29b1, crc16(b"123456789"));
+    }
+
+    #[test]
+    fn cobs() {
+        let cases: [&[u8]; 5] = [&[], &[0], &[0, 0], &[1, 2, 0, 3], &[0x11, 0x22, 0x00, 0x33]];
+        for data in cases.iter() {
+            let mut encoded = Vec::<u8, 16>::new();
+            cobs_encode(data, &mut encoded);
+            assert!(!encoded.contains(&0));
+            let mut decoded = [0; 16];
+            let len = cobs_decode(&encoded, &mut decoded).unwrap();
+            assert_eq!(*data, &decoded[..len]);
+        }
+
+        let mut encoded = Vec::<u8, 16>::new();
+        cobs_encode(&[0x11, 0x22, 0x00, 0x33], &mut encoded);
+        assert_eq!(&[0x03, 0x11, 0x22, 0x02, 0x33], &encoded[..]);
+
+        let long: std::vec::Vec<u8> = (1..=255).collect();
+        let mut encoded = Vec::<u8, 260>::new();
+        cobs_encode(&long, &mut encoded);
+        assert_eq!(257, encoded.len());
+        let mut decoded = [0; 260];
+        assert_eq!(Ok(255), cobs_decode(&encoded, &mut decoded));
+        assert_eq!(&long[..], &decoded[..255]);
+
+        assert_eq!(Err(DecodeError::Cobs), cobs_decode(&[0x05, 0x11], &mut decoded));
+    }
+
+    #[test]
+    fn round_trip() {
+        let frame = record(42).to_frame();
+        assert!(frame.len() <= MAX_FRAME_LEN);
+        assert_eq!((Some(&0), Some(&0)), (frame.first(), frame.last()));
+        assert!(!frame[1..frame.len() - 1].contains(&0));
+
+        let decoded = Record::from_frame(&frame[1..frame.len() - 1]).unwrap();
+        assert!(decoded.telemetry[Quantity::AmbientTemperature as usize].is_nan());
+        assert_approx_eq!(10_000_006.119, decoded.frequency(), 1e-3);
+        assert_eq!(
+            format!("{:?}", record(42)),
+            format!("{:?}", decoded),
+        );
+    }
+
+    #[test]
+    fn stream() {
+        let mut stream = b"freq: 10000000.000\r\n".to_vec();
+        stream.extend_from_slice(&record(1).to_frame());
+        stream.extend_from_slice(b"Counters: Ok(..)\r\n");
+        let mut corrupted = record(2).to_frame();
+        corrupted[20] ^= 0x01;
+        stream.extend_from_slice(&corrupted);
+        stream.extend_from_slice(&record(3).to_frame());
+        let mut newer = record(4).encode();
+        newer[0] = VERSION + 1;
+        let mut frame = Vec::<u8, MAX_FRAME_LEN>::new();
+        let mut payload = newer.to_vec();
+        payload.extend_from_slice(&crc16(&newer).to_le_bytes());
+        cobs_encode(&payload, &mut frame);
+        stream.push(0);
+        stream.extend_from_slice(&frame);
+        stream.push(0);
+
+        let records = decode_stream(&stream);
+        let sequences: std::vec::Vec<_> = records.iter()
+            .map(|r| r.map(|r| r.sequence))
+            .collect();
+        assert_eq!(vec![Ok(1), Ok(3), Err(DecodeError::UnsupportedVersion(VERSION + 1))], sequences);
+    }
+}
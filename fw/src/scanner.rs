@@ -0,0 +1,389 @@
+//! Round-robin scanning of the ADS1x18 channels, converted to engineering units
+
+use core::fmt;
+use core::fmt::Debug;
+
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::ads1018::{ADS1018Variant, ADS1x18, Channel, ConversionResult, DataRate, Error, Gain, Variant};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Quantity {
+    OcxoCurrent = 0,
+    OcxoVcc = 1,
+    OcxoTemperature = 2,
+    AmbientTemperature = 3,
+    DieTemperature = 4,
+}
+
+impl Quantity {
+    pub const COUNT: usize = 5;
+    pub const ALL: [Quantity; Self::COUNT] = [
+        Quantity::OcxoCurrent,
+        Quantity::OcxoVcc,
+        Quantity::OcxoTemperature,
+        Quantity::AmbientTemperature,
+        Quantity::DieTemperature,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Quantity::OcxoCurrent => "VCOCXO I",
+            Quantity::OcxoVcc => "VCOCXO Vcc",
+            Quantity::OcxoTemperature => "VCOCXO t",
+            Quantity::AmbientTemperature => "ambient t",
+            Quantity::DieTemperature => "ADC t",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Unit {
+    Ampere,
+    Volt,
+    Celsius,
+}
+
+impl Unit {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::Ampere => "A",
+            Unit::Volt => "V",
+            Unit::Celsius => "⁰C",
+        }
+    }
+}
+
+/// One conversion of the scan, `volts * scale + offset` in `unit`. The temperature sensor is
+/// converted from ⁰C instead of volts.
+#[derive(Copy, Clone, Debug)]
+pub struct ScanEntry<V: Variant = ADS1018Variant> {
+    pub quantity: Quantity,
+    pub channel: Channel,
+    pub gain: Gain,
+    pub data_rate: V::DataRate,
+    pub scale: f64,
+    pub offset: f64,
+    pub unit: Unit,
+}
+
+impl<V: Variant> ScanEntry<V> {
+    pub fn convert(&self, raw: i16) -> f64 {
+        let value = if self.channel == Channel::Temperature {
+            V::to_celsius(raw)
+        } else {
+            V::to_volts(self.gain, raw)
+        };
+        value * self.scale + self.offset
+    }
+}
+
+/// The board's channel assignment
+pub const DEFAULT_SCAN_TABLE: [ScanEntry<ADS1018Variant>; Quantity::COUNT] = [
+    // 0.5 A/V current sense
+    ScanEntry {
+        quantity: Quantity::OcxoCurrent,
+        channel: Channel::Channel0,
+        gain: Gain::FSR_2_048V,
+        data_rate: DataRate::_128SPS,
+        scale: 0.5,
+        offset: 0.0,
+        unit: Unit::Ampere,
+    },
+    // behind a 12:5 divider
+    ScanEntry {
+        quantity: Quantity::OcxoVcc,
+        channel: Channel::Channel1,
+        gain: Gain::FSR_4_096V,
+        data_rate: DataRate::_128SPS,
+        scale: 12.0 / 5.0,
+        offset: 0.0,
+        unit: Unit::Volt,
+    },
+    // 10 mV/⁰C sensors with a 500 mV offset
+    ScanEntry {
+        quantity: Quantity::OcxoTemperature,
+        channel: Channel::Channel2,
+        gain: Gain::FSR_1_024V,
+        data_rate: DataRate::_128SPS,
+        scale: 100.0,
+        offset: -50.0,
+        unit: Unit::Celsius,
+    },
+    ScanEntry {
+        quantity: Quantity::AmbientTemperature,
+        channel: Channel::Channel3,
+        gain: Gain::FSR_1_024V,
+        data_rate: DataRate::_128SPS,
+        scale: 100.0,
+        offset: -50.0,
+        unit: Unit::Celsius,
+    },
+    // the internal sensor ignores the PGA
+    ScanEntry {
+        quantity: Quantity::DieTemperature,
+        channel: Channel::Temperature,
+        gain: Gain::FSR_2_048V,
+        data_rate: DataRate::_128SPS,
+        scale: 1.0,
+        offset: 0.0,
+        unit: Unit::Celsius,
+    },
+];
+
+/// Statistics of one quantity since the last `Scanner::reset_statistics`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Reading {
+    pub latest: f64,
+    pub average: f64,
+    pub min: f64,
+    pub max: f64,
+    pub samples: u32,
+    pub unit: Unit,
+}
+
+#[derive(Copy, Clone, Default)]
+struct Statistics {
+    latest: f64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    samples: u32,
+    errors: u32,
+}
+
+impl Statistics {
+    fn add(&mut self, value: f64) {
+        if self.samples == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.latest = value;
+        self.sum += value;
+        self.samples += 1;
+    }
+
+    fn reading(&self, unit: Unit) -> Option<Reading> {
+        if self.samples == 0 {
+            return None;
+        }
+        Some(Reading {
+            latest: self.latest,
+            average: self.sum / self.samples as f64,
+            min: self.min,
+            max: self.max,
+            samples: self.samples,
+            unit,
+        })
+    }
+}
+
+/// `None` for quantities not scanned, or not successfully read yet
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Telemetry {
+    pub ocxo_current: Option<Reading>,
+    pub ocxo_vcc: Option<Reading>,
+    pub ocxo_temperature: Option<Reading>,
+    pub ambient_temperature: Option<Reading>,
+    pub die_temperature: Option<Reading>,
+    /// Failed conversions, over all channels
+    pub errors: u32,
+}
+
+impl Telemetry {
+    pub fn get(&self, quantity: Quantity) -> Option<Reading> {
+        match quantity {
+            Quantity::OcxoCurrent => self.ocxo_current,
+            Quantity::OcxoVcc => self.ocxo_vcc,
+            Quantity::OcxoTemperature => self.ocxo_temperature,
+            Quantity::AmbientTemperature => self.ambient_temperature,
+            Quantity::DieTemperature => self.die_temperature,
+        }
+    }
+}
+
+impl fmt::Display for Telemetry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for quantity in Quantity::ALL.iter() {
+            if let Some(r) = self.get(*quantity) {
+                writeln!(f, "{}:\t{:.03}{}\tavg {:.03}, min {:.03}, max {:.03} over {}", quantity.name(),
+                         r.latest, r.unit.symbol(), r.average, r.min, r.max, r.samples)?;
+            }
+        }
+        if self.errors != 0 {
+            writeln!(f, "ADC errors: {}", self.errors)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts one scan table entry per `scan_next`, in order and wrapping around
+pub struct Scanner<'a, V: Variant = ADS1018Variant> {
+    table: &'a [ScanEntry<V>],
+    next: usize,
+    statistics: [Statistics; Quantity::COUNT],
+}
+
+impl<'a, V: Variant> Scanner<'a, V> {
+    pub fn new(table: &'a [ScanEntry<V>]) -> Self {
+        assert!(!table.is_empty());
+        Self {
+            table,
+            next: 0,
+            statistics: [Statistics::default(); Quantity::COUNT],
+        }
+    }
+
+    pub fn reset_statistics(&mut self) {
+        self.statistics = [Statistics::default(); Quantity::COUNT];
+    }
+
+    /// Returns the quantity read and its value
+    pub fn scan_next<SPI, CS: OutputPin, MISO: InputPin>(
+        &mut self,
+        adc: &mut ADS1x18<SPI, CS, MISO, V>,
+    ) -> Result<(Quantity, f64), Error<SPI::Error>>
+        where SPI: embedded_hal::blocking::spi::Transfer<u8>, SPI::Error: Debug
+    {
+        let entry = self.advance();
+        let result = adc.read_raw(entry.channel, entry.gain, entry.data_rate);
+        self.record(&entry, result)
+    }
+
+    /// Goes through the whole table once, the errors are only counted
+    pub fn scan_all<SPI, CS: OutputPin, MISO: InputPin>(&mut self, adc: &mut ADS1x18<SPI, CS, MISO, V>)
+        where SPI: embedded_hal::blocking::spi::Transfer<u8>, SPI::Error: Debug
+    {
+        for _ in 0..self.table.len() {
+            self.scan_next(adc).ok();
+        }
+    }
+
+    pub fn telemetry(&self) -> Telemetry {
+        let reading = |quantity: Quantity| {
+            self.table.iter()
+                .find(|entry| entry.quantity == quantity)
+                .and_then(|entry| self.statistics[quantity as usize].reading(entry.unit))
+        };
+        Telemetry {
+            ocxo_current: reading(Quantity::OcxoCurrent),
+            ocxo_vcc: reading(Quantity::OcxoVcc),
+            ocxo_temperature: reading(Quantity::OcxoTemperature),
+            ambient_temperature: reading(Quantity::AmbientTemperature),
+            die_temperature: reading(Quantity::DieTemperature),
+            errors: self.statistics.iter().map(|s| s.errors).sum(),
+        }
+    }
+
+    fn advance(&mut self) -> ScanEntry<V> {
+        let entry = self.table[self.next];
+        self.next = (self.next + 1) % self.table.len();
+        entry
+    }
+
+    fn record<E: Debug>(&mut self, entry: &ScanEntry<V>, result: ConversionResult<E>) -> Result<(Quantity, f64), Error<E>> {
+        let statistics = &mut self.statistics[entry.quantity as usize];
+        match result {
+            Ok(raw) => {
+                let value = entry.convert(raw);
+                statistics.add(value);
+                Ok((entry.quantity, value))
+            }
+            Err(e) => {
+                statistics.errors += 1;
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    use crate::ads1018::{ConversionResult, Error};
+    use crate::scanner::{DEFAULT_SCAN_TABLE, Quantity, Scanner, Unit};
+
+    fn ok(raw: i16) -> ConversionResult<()> {
+        Ok(raw)
+    }
+
+    #[test]
+    fn round_robin() {
+        let mut scanner = Scanner::new(&DEFAULT_SCAN_TABLE[..3]);
+        let order: Vec<Quantity> = (0..7).map(|_| scanner.advance().quantity).collect();
+        assert_eq!(vec![
+            Quantity::OcxoCurrent, Quantity::OcxoVcc, Quantity::OcxoTemperature,
+            Quantity::OcxoCurrent, Quantity::OcxoVcc, Quantity::OcxoTemperature,
+            Quantity::OcxoCurrent,
+        ], order);
+    }
+
+    #[test]
+    fn default_scale_factors() {
+        let raw = 1234;
+        let expected = [
+            raw as f64 * 0.001 * 0.5,
+            raw as f64 * 0.002 * (12.0 / 5.0),
+            ((raw as f64 * 0.0005) - 0.5) * 100.0,
+            ((raw as f64 * 0.0005) - 0.5) * 100.0,
+            raw as f64 * 0.125,
+        ];
+        for (entry, expected) in DEFAULT_SCAN_TABLE.iter().zip(expected.iter()) {
+            assert_approx_eq!(*expected, entry.convert(raw), 1e-9);
+        }
+    }
+
+    #[test]
+    fn statistics() {
+        let mut scanner = Scanner::new(&DEFAULT_SCAN_TABLE);
+        for raw in [1000, 1200, 1100].iter() {
+            for _ in 0..DEFAULT_SCAN_TABLE.len() {
+                let entry = scanner.advance();
+                scanner.record(&entry, ok(*raw)).unwrap();
+            }
+        }
+
+        let telemetry = scanner.telemetry();
+        let vcc = telemetry.ocxo_vcc.unwrap();
+        assert_approx_eq!(1100.0 * 0.0048, vcc.latest, 1e-9);
+        assert_approx_eq!(1100.0 * 0.0048, vcc.average, 1e-9);
+        assert_approx_eq!(1000.0 * 0.0048, vcc.min, 1e-9);
+        assert_approx_eq!(1200.0 * 0.0048, vcc.max, 1e-9);
+        assert_eq!(3, vcc.samples);
+        assert_eq!(Unit::Volt, vcc.unit);
+
+        let temperature = telemetry.get(Quantity::OcxoTemperature).unwrap();
+        assert_approx_eq!(0.0, temperature.min, 1e-9);
+        assert_approx_eq!(10.0, temperature.max, 1e-9);
+        assert_eq!(Unit::Celsius, temperature.unit);
+        assert_eq!(0, telemetry.errors);
+
+        scanner.reset_statistics();
+        assert_eq!(None, scanner.telemetry().ocxo_vcc);
+    }
+
+    #[test]
+    fn errors_and_unscanned_quantities() {
+        let mut scanner = Scanner::new(&DEFAULT_SCAN_TABLE[..2]);
+
+        let entry = scanner.advance();
+        assert_eq!((Quantity::OcxoCurrent, 0.5), scanner.record(&entry, ok(1000)).unwrap());
+        let entry = scanner.advance();
+        match scanner.record(&entry, Err::<i16, Error<()>>(Error::Timeout)) {
+            Err(Error::Timeout) => {}
+            r => panic!("{:?}", r),
+        }
+
+        let telemetry = scanner.telemetry();
+        assert!(telemetry.ocxo_current.is_some());
+        assert_eq!(None, telemetry.ocxo_vcc);
+        assert_eq!(None, telemetry.die_temperature);
+        assert_eq!(1, telemetry.errors);
+    }
+}
@@ -0,0 +1,689 @@
+//! SCPI remote control, sharing the UART lines with `console`.
+//!
+//! Headers take the short or the long form of each mnemonic, e.g. `MEAS:FREQ?` or
+//! `MEASURE:FREQUENCY?`, several commands go on a line separated by `;`, relative to the last
+//! header's node unless they start with `:`. Errors go to an IEEE 488.2 style queue read with
+//! `SYSTem:ERRor?`, and the rest of the line is discarded.
+
+use core::fmt;
+
+use heapless::{Deque, Vec};
+
+use crate::console::Term;
+use crate::scanner::Quantity;
+use crate::stability::StabilityAnalysis;
+
+pub const IDENTIFICATION: &str = concat!("ks,gpsdo,0,", env!("CARGO_PKG_VERSION"));
+/// The SCPI standard the commands follow
+const VERSION: &str = "1999.0";
+const MAX_DEPTH: usize = 4;
+/// Not-a-number, as SCPI has it
+const NAN: f64 = 9.91e37;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    CommandError,
+    SyntaxError,
+    ParameterNotAllowed,
+    MissingParameter,
+    UndefinedHeader,
+    NumericDataError,
+    ExecutionError,
+    SettingsConflict,
+    DataOutOfRange,
+    IllegalParameterValue,
+    DataStale,
+    QueueOverflow,
+}
+
+impl Error {
+    pub fn code(self) -> i16 {
+        match self {
+            Error::CommandError => -100,
+            Error::SyntaxError => -102,
+            Error::ParameterNotAllowed => -108,
+            Error::MissingParameter => -109,
+            Error::UndefinedHeader => -113,
+            Error::NumericDataError => -120,
+            Error::ExecutionError => -200,
+            Error::SettingsConflict => -221,
+            Error::DataOutOfRange => -222,
+            Error::IllegalParameterValue => -224,
+            Error::DataStale => -230,
+            Error::QueueOverflow => -350,
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            Error::CommandError => "Command error",
+            Error::SyntaxError => "Syntax error",
+            Error::ParameterNotAllowed => "Parameter not allowed",
+            Error::MissingParameter => "Missing parameter",
+            Error::UndefinedHeader => "Undefined header",
+            Error::NumericDataError => "Numeric data error",
+            Error::ExecutionError => "Execution error",
+            Error::SettingsConflict => "Settings conflict",
+            Error::DataOutOfRange => "Data out of range",
+            Error::IllegalParameterValue => "Illegal parameter value",
+            Error::DataStale => "Data corrupt or stale",
+            Error::QueueOverflow => "Queue overflow",
+        }
+    }
+}
+
+/// As returned by `SYSTem:ERRor?`
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},\"{}\"", self.code(), self.message())
+    }
+}
+
+/// What the commands act on
+pub trait Instrument {
+    /// `*RST`, back to steering with the default settings
+    fn reset(&mut self);
+
+    /// Hz, of the last counter reading
+    fn frequency(&self) -> Option<f64>;
+
+    /// Hz, as seen by the control loop
+    fn filtered_frequency(&self) -> Option<f64>;
+
+    /// The latest ADC reading
+    fn telemetry(&self, quantity: Quantity) -> Option<f64>;
+
+    /// Steering, with valid counter readings
+    fn is_locked(&self) -> bool;
+
+    /// Of the counter readings since the control loop started
+    fn stability(&self) -> &StabilityAnalysis;
+
+    /// As written
+    fn dac_code(&self) -> u16;
+
+    /// `None` gives the DAC back to the control loop
+    fn set_dac_code(&mut self, code: Option<u16>) -> Result<(), Error>;
+
+    /// Seconds, of the frequency filter
+    fn time_constant(&self) -> u32;
+
+    fn set_time_constant(&mut self, seconds: u32) -> Result<(), Error>;
+
+    fn gain(&self, term: Term) -> f64;
+
+    fn set_gain(&mut self, term: Term, factor: f64) -> Result<(), Error>;
+
+    fn holdover(&self) -> bool;
+
+    fn set_holdover(&mut self, holdover: bool) -> Result<(), Error>;
+
+    /// Binary per-second records, see `record`
+    fn records(&self) -> bool;
+
+    fn set_records(&mut self, records: bool) -> Result<(), Error>;
+}
+
+#[derive(Copy, Clone)]
+enum Header {
+    Frequency,
+    FilteredFrequency,
+    Telemetry(Quantity),
+    Stability,
+    Dac,
+    TimeConstant,
+    Gain(Term),
+    Holdover,
+    Lock,
+    NextError,
+    ErrorCount,
+    Version,
+    Local,
+    Records,
+}
+
+/// Optional nodes are listed both with and without
+const HEADERS: &[(&[&str], Header)] = &[
+    (&["MEASure", "FREQuency"], Header::Frequency),
+    (&["MEASure", "FREQuency", "FILTered"], Header::FilteredFrequency),
+    (&["MEASure", "TEMPerature"], Header::Telemetry(Quantity::OcxoTemperature)),
+    (&["MEASure", "TEMPerature", "OCXO"], Header::Telemetry(Quantity::OcxoTemperature)),
+    (&["MEASure", "TEMPerature", "AMBient"], Header::Telemetry(Quantity::AmbientTemperature)),
+    (&["MEASure", "TEMPerature", "DIE"], Header::Telemetry(Quantity::DieTemperature)),
+    (&["MEASure", "CURRent"], Header::Telemetry(Quantity::OcxoCurrent)),
+    (&["MEASure", "VOLTage"], Header::Telemetry(Quantity::OcxoVcc)),
+    (&["MEASure", "STABility"], Header::Stability),
+    (&["SOURce", "DAC"], Header::Dac),
+    (&["CONFigure", "LOOP", "TC"], Header::TimeConstant),
+    (&["CONFigure", "LOOP", "GAIN", "P"], Header::Gain(Term::P)),
+    (&["CONFigure", "LOOP", "GAIN", "I"], Header::Gain(Term::I)),
+    (&["CONFigure", "LOOP", "GAIN", "D"], Header::Gain(Term::D)),
+    (&["CONFigure", "LOOP", "HOLDover"], Header::Holdover),
+    (&["STATus", "LOCK"], Header::Lock),
+    (&["SYSTem", "ERRor"], Header::NextError),
+    (&["SYSTem", "ERRor", "NEXT"], Header::NextError),
+    (&["SYSTem", "ERRor", "COUNt"], Header::ErrorCount),
+    (&["SYSTem", "VERSion"], Header::Version),
+    (&["SYSTem", "LOCal"], Header::Local),
+    (&["SYSTem", "TELemetry"], Header::Records),
+    (&["SYSTem", "TELemetry", "STATe"], Header::Records),
+];
+
+/// The short form is the leading capitals of `mnemonic`
+fn mnemonic_matches(word: &str, mnemonic: &str) -> bool {
+    let short = mnemonic.bytes().take_while(|b| !b.is_ascii_lowercase()).count();
+    word.eq_ignore_ascii_case(mnemonic) || word.eq_ignore_ascii_case(&mnemonic[..short])
+}
+
+fn find_header(path: &[&str]) -> Option<Header> {
+    HEADERS.iter()
+        .find(|(mnemonics, _)| mnemonics.len() == path.len()
+            && mnemonics.iter().zip(path).all(|(m, w)| mnemonic_matches(w, m)))
+        .map(|&(_, header)| header)
+}
+
+fn number(parameter: &str) -> Result<f64, Error> {
+    match parameter.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(Error::NumericDataError),
+    }
+}
+
+fn integer(parameter: &str, max: u32) -> Result<u32, Error> {
+    let value = libm::round(number(parameter)?);
+    if value < 0.0 || value > max as f64 {
+        return Err(Error::DataOutOfRange);
+    }
+    Ok(value as u32)
+}
+
+fn boolean(parameter: &str) -> Result<bool, Error> {
+    if parameter.eq_ignore_ascii_case("ON") {
+        Ok(true)
+    } else if parameter.eq_ignore_ascii_case("OFF") {
+        Ok(false)
+    } else {
+        number(parameter).map(|value| libm::round(value) != 0.0)
+    }
+}
+
+/// Common commands, or a header with a path: what a mistyped console command isn't
+pub fn is_scpi(line: &str) -> bool {
+    let line = line.trim_start();
+    let header = line.split(|c: char| c.is_ascii_whitespace() || c == ';').next().unwrap_or("");
+    line.starts_with('*') || header.contains(':')
+}
+
+/// Query responses of one line, `;`-separated
+struct Responses<'w, W: fmt::Write> {
+    out: &'w mut W,
+    any: bool,
+}
+
+impl<'w, W: fmt::Write> Responses<'w, W> {
+    fn write(&mut self, response: fmt::Arguments) {
+        if self.any {
+            self.out.write_char(';').ok();
+        }
+        self.any = true;
+        self.out.write_fmt(response).ok();
+    }
+
+    /// More of the same response
+    fn continue_write(&mut self, response: fmt::Arguments) {
+        self.out.write_fmt(response).ok();
+    }
+}
+
+pub struct Scpi<const N: usize = 8> {
+    errors: Deque<Error, N>,
+    remote: bool,
+}
+
+impl<const N: usize> Scpi<N> {
+    pub fn new() -> Self {
+        Self {
+            errors: Deque::new(),
+            remote: false,
+        }
+    }
+
+    /// The newest error is replaced by a queue overflow once full
+    pub fn push_error(&mut self, error: Error) {
+        if self.errors.is_full() {
+            self.errors.pop_back();
+            self.errors.push_back(Error::QueueOverflow).ok();
+        } else {
+            self.errors.push_back(error).ok();
+        }
+    }
+
+    pub fn pop_error(&mut self) -> Option<Error> {
+        self.errors.pop_front()
+    }
+
+    /// Set by any command and cleared by `SYSTem:LOCal`, keep the log quiet while set
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    /// Runs the commands on one line, writes the query responses terminated by a newline
+    pub fn execute<I: Instrument, W: fmt::Write>(&mut self, line: &str, instrument: &mut I, out: &mut W) {
+        self.remote = true;
+        let mut responses = Responses { out, any: false };
+        let mut prefix: Vec<&str, MAX_DEPTH> = Vec::new();
+        for command in line.split(';').map(str::trim) {
+            if command.is_empty() {
+                continue;
+            }
+            if let Err(e) = self.execute_command(command, &mut prefix, instrument, &mut responses) {
+                self.push_error(e);
+                break;
+            }
+        }
+        if responses.any {
+            responses.out.write_char('\n').ok();
+        }
+    }
+
+    fn execute_command<'l, I: Instrument, W: fmt::Write>(
+        &mut self,
+        command: &'l str,
+        prefix: &mut Vec<&'l str, MAX_DEPTH>,
+        instrument: &mut I,
+        responses: &mut Responses<W>,
+    ) -> Result<(), Error> {
+        let (header, parameters) = match command.find(|c: char| c.is_ascii_whitespace()) {
+            Some(i) => (&command[..i], command[i..].trim()),
+            None => (command, ""),
+        };
+        let mut parameters = parameters.split(',').map(str::trim).filter(|p| !p.is_empty());
+        let (header, query) = match header.strip_suffix('?') {
+            Some(header) => (header, true),
+            None => (header, false),
+        };
+
+        if let Some(common) = header.strip_prefix('*') {
+            if parameters.next().is_some() {
+                return Err(Error::ParameterNotAllowed);
+            }
+            return self.execute_common(common, query, instrument, responses);
+        }
+
+        let mut path = Vec::<&str, MAX_DEPTH>::new();
+        let header = match header.strip_prefix(':') {
+            Some(absolute) => absolute,
+            None => {
+                path.clone_from(prefix);
+                header
+            }
+        };
+        for node in header.split(':') {
+            if node.is_empty() || !node.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                return Err(Error::SyntaxError);
+            }
+            path.push(node).map_err(|_| Error::UndefinedHeader)?;
+        }
+        let header = find_header(&path).ok_or(Error::UndefinedHeader)?;
+        prefix.clone_from(&path);
+        prefix.pop();
+
+        let parameter = parameters.next();
+        if parameters.next().is_some() {
+            return Err(Error::ParameterNotAllowed);
+        }
+        match (query, parameter) {
+            (true, Some(_)) => return Err(Error::ParameterNotAllowed),
+            (true, None) => self.query(header, instrument, responses),
+            (false, Some(parameter)) => self.set(header, parameter, instrument),
+            (false, None) => match header {
+                Header::Local => {
+                    self.remote = false;
+                    Ok(())
+                }
+                Header::Dac | Header::TimeConstant | Header::Gain(_) | Header::Holdover | Header::Records => {
+                    Err(Error::MissingParameter)
+                }
+                _ => Err(Error::UndefinedHeader),
+            },
+        }
+    }
+
+    fn execute_common<I: Instrument, W: fmt::Write>(
+        &mut self,
+        common: &str,
+        query: bool,
+        instrument: &mut I,
+        responses: &mut Responses<W>,
+    ) -> Result<(), Error> {
+        let is = |mnemonic: &str| common.eq_ignore_ascii_case(mnemonic);
+        match query {
+            true if is("IDN") => responses.write(format_args!("{}", IDENTIFICATION)),
+            // everything completes before the response
+            true if is("OPC") => responses.write(format_args!("1")),
+            false if is("RST") => instrument.reset(),
+            false if is("CLS") => self.errors.clear(),
+            false if is("OPC") || is("WAI") => {}
+            _ => return Err(Error::UndefinedHeader),
+        }
+        Ok(())
+    }
+
+    fn query<I: Instrument, W: fmt::Write>(
+        &mut self,
+        header: Header,
+        instrument: &mut I,
+        responses: &mut Responses<W>,
+    ) -> Result<(), Error> {
+        match header {
+            Header::Frequency => {
+                let frequency = instrument.frequency().ok_or(Error::DataStale)?;
+                responses.write(format_args!("{:.04}", frequency));
+            }
+            Header::FilteredFrequency => {
+                let frequency = instrument.filtered_frequency().ok_or(Error::DataStale)?;
+                responses.write(format_args!("{:.06}", frequency));
+            }
+            Header::Telemetry(quantity) => {
+                let value = instrument.telemetry(quantity).ok_or(Error::DataStale)?;
+                responses.write(format_args!("{:.04}", value));
+            }
+            // tau, ADEV, MDEV, TDEV for each averaging time with an ADEV so far
+            Header::Stability => {
+                let stability = instrument.stability();
+                if stability.adev(0).is_none() {
+                    return Err(Error::DataStale);
+                }
+                for (i, d) in stability.deviations().filter(|d| d.adev.is_some()).enumerate() {
+                    let (adev, mdev, tdev) = (d.adev.unwrap_or(NAN), d.mdev.unwrap_or(NAN), d.tdev.unwrap_or(NAN));
+                    if i == 0 {
+                        responses.write(format_args!("{},{:.03e},{:.03e},{:.03e}", d.tau, adev, mdev, tdev));
+                    } else {
+                        responses.continue_write(format_args!(",{},{:.03e},{:.03e},{:.03e}", d.tau, adev, mdev, tdev));
+                    }
+                }
+            }
+            Header::Dac => responses.write(format_args!("{}", instrument.dac_code())),
+            Header::TimeConstant => responses.write(format_args!("{}", instrument.time_constant())),
+            Header::Gain(term) => responses.write(format_args!("{}", instrument.gain(term))),
+            Header::Holdover => responses.write(format_args!("{}", instrument.holdover() as u8)),
+            Header::Lock => responses.write(format_args!("{}", instrument.is_locked() as u8)),
+            Header::Records => responses.write(format_args!("{}", instrument.records() as u8)),
+            Header::NextError => match self.pop_error() {
+                Some(e) => responses.write(format_args!("{}", e)),
+                None => responses.write(format_args!("0,\"No error\"")),
+            },
+            Header::ErrorCount => responses.write(format_args!("{}", self.errors.len())),
+            Header::Version => responses.write(format_args!("{}", VERSION)),
+            Header::Local => return Err(Error::UndefinedHeader),
+        }
+        Ok(())
+    }
+
+    fn set<I: Instrument>(&mut self, header: Header, parameter: &str, instrument: &mut I) -> Result<(), Error> {
+        match header {
+            Header::Dac => {
+                if parameter.eq_ignore_ascii_case("AUTO") {
+                    instrument.set_dac_code(None)
+                } else {
+                    instrument.set_dac_code(Some(integer(parameter, u16::MAX as u32)? as u16))
+                }
+            }
+            Header::TimeConstant => instrument.set_time_constant(integer(parameter, u32::MAX)?),
+            Header::Gain(term) => {
+                let factor = number(parameter)?;
+                if factor < 0.0 {
+                    return Err(Error::DataOutOfRange);
+                }
+                instrument.set_gain(term, factor)
+            }
+            Header::Holdover => instrument.set_holdover(boolean(parameter)?),
+            Header::Records => instrument.set_records(boolean(parameter)?),
+            Header::Local => Err(Error::ParameterNotAllowed),
+            _ => Err(Error::UndefinedHeader),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use std::collections::VecDeque;
+
+    use crate::console::Term;
+    use crate::line::LineBuffer;
+    use crate::scanner::Quantity;
+    use crate::scpi::{Error, IDENTIFICATION, Instrument, is_scpi, Scpi};
+    use crate::stability::StabilityAnalysis;
+
+    struct MockInstrument {
+        frequency: Option<f64>,
+        dac_code: u16,
+        manual: bool,
+        time_constant: u32,
+        gains: [f64; 3],
+        holdover: bool,
+        records: bool,
+        stability: StabilityAnalysis,
+    }
+
+    impl Default for MockInstrument {
+        fn default() -> Self {
+            Self {
+                frequency: Some(10_000_000.0123),
+                dac_code: 32000,
+                manual: false,
+                time_constant: 600,
+                gains: [0.1, 0.001, 0.05],
+                holdover: false,
+                records: false,
+                stability: StabilityAnalysis::new(10_000_000.0, 1.0),
+            }
+        }
+    }
+
+    impl Instrument for MockInstrument {
+        fn reset(&mut self) {
+            *self = Self {
+                frequency: self.frequency,
+                stability: core::mem::replace(&mut self.stability, StabilityAnalysis::new(10_000_000.0, 1.0)),
+                ..Self::default()
+            };
+        }
+
+        fn frequency(&self) -> Option<f64> {
+            self.frequency
+        }
+
+        fn filtered_frequency(&self) -> Option<f64> {
+            self.frequency
+        }
+
+        fn telemetry(&self, quantity: Quantity) -> Option<f64> {
+            match quantity {
+                Quantity::OcxoTemperature => Some(65.25),
+                _ => None,
+            }
+        }
+
+        fn is_locked(&self) -> bool {
+            !self.manual && !self.holdover
+        }
+
+        fn stability(&self) -> &StabilityAnalysis {
+            &self.stability
+        }
+
+        fn dac_code(&self) -> u16 {
+            self.dac_code
+        }
+
+        fn set_dac_code(&mut self, code: Option<u16>) -> Result<(), Error> {
+            self.manual = code.is_some();
+            if let Some(code) = code {
+                self.dac_code = code;
+            }
+            Ok(())
+        }
+
+        fn time_constant(&self) -> u32 {
+            self.time_constant
+        }
+
+        fn set_time_constant(&mut self, seconds: u32) -> Result<(), Error> {
+            if !(150..=2400).contains(&seconds) {
+                return Err(Error::DataOutOfRange);
+            }
+            self.time_constant = seconds;
+            Ok(())
+        }
+
+        fn gain(&self, term: Term) -> f64 {
+            self.gains[term as usize]
+        }
+
+        fn set_gain(&mut self, term: Term, factor: f64) -> Result<(), Error> {
+            self.gains[term as usize] = factor;
+            Ok(())
+        }
+
+        fn holdover(&self) -> bool {
+            self.holdover
+        }
+
+        fn set_holdover(&mut self, holdover: bool) -> Result<(), Error> {
+            self.holdover = holdover;
+            Ok(())
+        }
+
+        fn records(&self) -> bool {
+            self.records
+        }
+
+        fn set_records(&mut self, records: bool) -> Result<(), Error> {
+            self.records = records;
+            Ok(())
+        }
+    }
+
+    /// Bytes in, lines out, the way the firmware feeds the UART
+    #[derive(Default)]
+    struct Transport {
+        rx: VecDeque<u8>,
+        tx: String,
+    }
+
+    struct Bench {
+        transport: Transport,
+        line: LineBuffer<64>,
+        scpi: Scpi<4>,
+        instrument: MockInstrument,
+    }
+
+    impl Bench {
+        fn new() -> Self {
+            Self {
+                transport: Transport::default(),
+                line: LineBuffer::new(),
+                scpi: Scpi::new(),
+                instrument: MockInstrument::default(),
+            }
+        }
+
+        fn send(&mut self, input: &str) -> String {
+            self.transport.rx.extend(input.bytes());
+            while let Some(byte) = self.transport.rx.pop_front() {
+                if let Some(Ok(line)) = self.line.push(byte) {
+                    self.scpi.execute(&line, &mut self.instrument, &mut self.transport.tx);
+                }
+            }
+            core::mem::take(&mut self.transport.tx)
+        }
+    }
+
+    #[test]
+    fn queries() {
+        let mut bench = Bench::new();
+        assert_eq!(format!("{}\n", IDENTIFICATION), bench.send("*IDN?\r\n"));
+        assert_eq!("10000000.0123\n", bench.send("meas:freq?\n"));
+        assert_eq!("65.2500;65.2500\n", bench.send("MEASURE:TEMPERATURE?;:MEAS:TEMP:OCXO?\n"));
+        assert_eq!("32000;1;1999.0\n", bench.send("SOUR:DAC?;:STAT:LOCK?;:SYST:VERS?\n"));
+        assert_eq!("0,\"No error\"\n", bench.send("SYST:ERR?\n"));
+        assert!(bench.scpi.is_remote());
+
+        for f in [0.0, 0.2, -0.1, 0.1].iter() {
+            bench.instrument.stability.add(10_000_000.0 + f);
+        }
+        assert_eq!("1,1.683e-8,1.683e-8,9.718e-9,2,7.071e-9,9.910e37,9.910e37;1\n",
+                   bench.send("MEAS:STAB?;:STAT:LOCK?\n"));
+        assert_eq!("", bench.send("SYST:LOC\n"));
+        assert!(!bench.scpi.is_remote());
+    }
+
+    #[test]
+    fn settings() {
+        let mut bench = Bench::new();
+        assert_eq!("", bench.send("SOUR:DAC 1.2e3\nconf:loop:tc 1200;hold on;GAIN:I 0.002\n"));
+        assert!(bench.instrument.manual);
+        assert_eq!("1200;1;0.002;0\n", bench.send("CONF:LOOP:TC?;HOLD?;GAIN:I?;:STAT:LOCK?\n"));
+        assert_eq!("1200\n", bench.send("SOURCE:DAC?\n"));
+
+        assert_eq!("", bench.send("SYST:TEL ON\n"));
+        assert!(bench.instrument.records);
+        assert_eq!("1\n", bench.send("SYST:TEL:STAT?\n"));
+
+        bench.send("SOUR:DAC AUTO;:CONF:LOOP:HOLD 0\n");
+        assert!(!bench.instrument.manual);
+        assert!(!bench.instrument.holdover);
+
+        bench.send("*RST\n");
+        assert_eq!("600;0.001\n", bench.send("CONF:LOOP:TC?;GAIN:I?\n"));
+    }
+
+    #[test]
+    fn scpi_lines() {
+        assert!(is_scpi("*IDN?"));
+        assert!(is_scpi(":MEAS:FREQ?"));
+        assert!(is_scpi("  meas:freq?;*IDN?"));
+        assert!(is_scpi("SOUR:DAC 1.2e3"));
+        assert!(!is_scpi("stauts"));
+        assert!(!is_scpi("set tau 1:2"));
+        assert!(!is_scpi(""));
+    }
+
+    #[test]
+    fn error_queue() {
+        let mut bench = Bench::new();
+        // discards the rest of the line
+        assert_eq!("", bench.send("MEAS:FREQ?x;*IDN?\n"));
+        assert_eq!("-102,\"Syntax error\"\n", bench.send("SYST:ERR?\n"));
+
+        bench.send("MEAS:TEMP:DIE?\n");
+        bench.send("SOUR:DAC\n");
+        bench.send("SOUR:DAC 70000\n");
+        bench.send("CONF:LOOP:TC 10\n");
+        bench.send("CONF:LOOP:GAIN:P abc\n");
+        assert_eq!("4\n", bench.send("SYST:ERR:COUN?\n"));
+        assert_eq!(
+            "-230,\"Data corrupt or stale\";-109,\"Missing parameter\";-222,\"Data out of range\";\
+             -350,\"Queue overflow\";0,\"No error\"\n",
+            bench.send("SYST:ERR?;ERR?;ERR?;ERR:NEXT?;:SYST:ERR?\n"),
+        );
+        assert_eq!(32000, bench.instrument.dac_code);
+
+        bench.send("MEAS:FREQ? 1\n");
+        bench.send("*CLS\n");
+        assert_eq!("0\n", bench.send("SYST:ERR:COUN?\n"));
+
+        bench.instrument.frequency = None;
+        bench.send("MEAS:STAB?\n");
+        assert_eq!(Some(Error::DataStale), bench.scpi.pop_error());
+        bench.send("FOO:BAR?\n");
+        bench.send("MEAS::FREQ?\n");
+        bench.send("MEAS:FREQ?\n");
+        assert_eq!(Some(Error::UndefinedHeader), bench.scpi.pop_error());
+        assert_eq!(Some(Error::SyntaxError), bench.scpi.pop_error());
+        assert_eq!(Some(Error::DataStale), bench.scpi.pop_error());
+    }
+}
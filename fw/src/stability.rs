@@ -0,0 +1,412 @@
+//! Streaming overlapping Allan, modified Allan and time deviation at octave-spaced averaging times
+
+use core::fmt;
+
+/// Averaging factors `m = 1, 2, 4, ..., 2^(OCTAVES - 1)`
+pub const OCTAVES: usize = 10;
+
+/// Octaves with `m <= OVERLAP` are fully overlapping, longer ones only keep every `m / OVERLAP`th
+/// phase sample, so that each of them costs the same fixed amount of memory
+const OVERLAP: usize = 4;
+
+/// Enough phase samples for the `x[i], x[i+m], x[i+2m], x[i+3m]` taps of the modified Allan variance
+const HISTORY: usize = 3 * OVERLAP + 1;
+
+/// Phase samples between moving the phase origin to the latest one, see `StabilityAnalysis::rebase`
+const REBASE_INTERVAL: u32 = 4096;
+
+#[derive(Copy, Clone, Default)]
+struct PhaseSample {
+    /// Phase, seconds
+    x: f64,
+    /// Running sum of phase, used to get `m`-sample phase averages in O(1)
+    x_sum: f64,
+}
+
+#[derive(Copy, Clone)]
+struct Octave {
+    history: [PhaseSample; HISTORY],
+    head: usize,
+    len: usize,
+    avar_sum: f64,
+    avar_terms: u32,
+    mvar_sum: f64,
+    mvar_terms: u32,
+}
+
+impl Octave {
+    const fn new() -> Self {
+        Self {
+            history: [PhaseSample { x: 0.0, x_sum: 0.0 }; HISTORY],
+            head: 0,
+            len: 0,
+            avar_sum: 0.0,
+            avar_terms: 0,
+            mvar_sum: 0.0,
+            mvar_terms: 0,
+        }
+    }
+
+    /// `lag` is `m` expressed in decimated samples. The first modified Allan variance window
+    /// starts at `x[0]` and needs the (zero) running sum just before it, which is still
+    /// in the history unless the octave is decimated.
+    fn add(&mut self, sample: PhaseSample, lag: usize, decimated: bool) {
+        self.head = (self.head + 1) % HISTORY;
+        self.history[self.head] = sample;
+        self.len = (self.len + 1).min(HISTORY);
+
+        let tap = |k: usize| self.history[(self.head + HISTORY - k * lag) % HISTORY];
+
+        if self.len > 2 * lag {
+            let d = tap(0).x - 2.0 * tap(1).x + tap(2).x;
+            self.avar_sum += d * d;
+            self.avar_terms += 1;
+        }
+
+        if self.len > 3 * lag || (!decimated && self.len == 3 * lag) {
+            let d = tap(0).x_sum - 3.0 * tap(1).x_sum + 3.0 * tap(2).x_sum - tap(3).x_sum;
+            self.mvar_sum += d * d;
+            self.mvar_terms += 1;
+        }
+    }
+
+    /// Subtracts `origin` as a phase ramp, `newest` and `step` are the sample offsets from it of
+    /// the newest history entry and between entries. Every entry is shifted, including the zero
+    /// running sum a first modified Allan variance window may still need.
+    fn rebase(&mut self, origin: PhaseSample, newest: i64, step: i64) {
+        for k in 0..HISTORY {
+            let sample = &mut self.history[(self.head + HISTORY - k) % HISTORY];
+            let offset = (newest - k as i64 * step) as f64;
+            sample.x -= origin.x;
+            sample.x_sum -= origin.x_sum + origin.x * offset;
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Deviation {
+    pub tau: f64,
+    pub adev: Option<f64>,
+    pub mdev: Option<f64>,
+    pub tdev: Option<f64>,
+}
+
+pub struct StabilityAnalysis {
+    nominal_frequency: f64,
+    tau0: f64,
+    /// Fractional frequency of the first sample. None of the estimators are sensitive to
+    /// a constant frequency offset, removing it keeps the phase sums small.
+    reference: Option<f64>,
+    phase: PhaseSample,
+    phase_samples: u32,
+    octaves: [Octave; OCTAVES],
+}
+
+impl StabilityAnalysis {
+    pub fn new(nominal_frequency: f64, tau0: f64) -> Self {
+        Self {
+            nominal_frequency,
+            tau0,
+            reference: None,
+            phase: Default::default(),
+            phase_samples: 0,
+            octaves: [Octave::new(); OCTAVES],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.nominal_frequency, self.tau0);
+    }
+
+    fn m(octave: usize) -> usize {
+        1 << octave
+    }
+
+    fn add_phase(&mut self) {
+        for (octave, o) in self.octaves.iter_mut().enumerate() {
+            let m = Self::m(octave);
+            let decimation = (m / OVERLAP).max(1);
+            if self.phase_samples as usize % decimation == 0 {
+                o.add(self.phase, m / decimation, decimation > 1);
+            }
+        }
+        self.phase_samples += 1;
+    }
+
+    /// The estimators only take differences between phase samples in an octave's history. The
+    /// running sums grow without bound against the first sample's frequency, so the origin moves
+    /// to the latest sample every so often, keeping the third differences of `x_sum` precise.
+    fn rebase(&mut self) {
+        let origin = self.phase;
+        let latest = self.phase_samples as i64 - 1;
+        for (octave, o) in self.octaves.iter_mut().enumerate() {
+            let decimation = (Self::m(octave) / OVERLAP).max(1) as i64;
+            o.rebase(origin, latest / decimation * decimation - latest, decimation);
+        }
+        self.phase = PhaseSample::default();
+    }
+
+    /// Takes a frequency measurement averaged over `tau0`
+    pub fn add(&mut self, frequency: f64) {
+        let y = frequency / self.nominal_frequency - 1.0;
+        let reference = *self.reference.get_or_insert(y);
+
+        if self.phase_samples == 0 {
+            self.add_phase();
+        }
+
+        self.phase.x += (y - reference) * self.tau0;
+        self.phase.x_sum += self.phase.x;
+        self.add_phase();
+        if self.phase_samples % REBASE_INTERVAL == 0 {
+            self.rebase();
+        }
+    }
+
+    /// Number of frequency samples seen
+    pub fn samples(&self) -> u32 {
+        self.phase_samples.saturating_sub(1)
+    }
+
+    pub fn tau(&self, octave: usize) -> f64 {
+        Self::m(octave) as f64 * self.tau0
+    }
+
+    pub fn avar(&self, octave: usize) -> Option<f64> {
+        let o = &self.octaves[octave];
+        if o.avar_terms == 0 {
+            return None;
+        }
+        let tau = self.tau(octave);
+        Some(o.avar_sum / (2.0 * tau * tau * o.avar_terms as f64))
+    }
+
+    pub fn mvar(&self, octave: usize) -> Option<f64> {
+        let o = &self.octaves[octave];
+        if o.mvar_terms == 0 {
+            return None;
+        }
+        let tau = self.tau(octave);
+        let m = Self::m(octave) as f64;
+        Some(o.mvar_sum / (2.0 * m * m * tau * tau * o.mvar_terms as f64))
+    }
+
+    pub fn adev(&self, octave: usize) -> Option<f64> {
+        self.avar(octave).map(libm::sqrt)
+    }
+
+    pub fn mdev(&self, octave: usize) -> Option<f64> {
+        self.mvar(octave).map(libm::sqrt)
+    }
+
+    pub fn tdev(&self, octave: usize) -> Option<f64> {
+        let tau = self.tau(octave);
+        self.mdev(octave).map(|mdev| mdev * tau / libm::sqrt(3.0))
+    }
+
+    pub fn deviation(&self, octave: usize) -> Deviation {
+        Deviation {
+            tau: self.tau(octave),
+            adev: self.adev(octave),
+            mdev: self.mdev(octave),
+            tdev: self.tdev(octave),
+        }
+    }
+
+    pub fn deviations(&self) -> impl Iterator<Item=Deviation> + '_ {
+        (0..OCTAVES).map(move |octave| self.deviation(octave))
+    }
+}
+
+impl fmt::Display for StabilityAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "tau\tadev\tmdev\ttdev")?;
+        for d in self.deviations() {
+            if let (Some(adev), Some(mdev), Some(tdev)) = (d.adev, d.mdev, d.tdev) {
+                writeln!(f, "{}s\t{:.03e}\t{:.03e}\t{:.03e}s", d.tau, adev, mdev, tdev)?;
+            } else if let Some(adev) = d.adev {
+                writeln!(f, "{}s\t{:.03e}\t-\t-", d.tau, adev)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::v1::*;
+
+    use rand::Rng;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use rand_distr::StandardNormal;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    use crate::stability::{StabilityAnalysis, OCTAVES, OVERLAP};
+
+    const RNG_SEED: [u8; 32] = [3, 0, 0, 0, 17, 0, 0, 0, 100, 1, 0, 0, 7, 30, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    const F0: f64 = 10e6;
+
+    fn white_fm(n: usize, sigma: f64) -> Vec<f64> {
+        let mut rng = StdRng::from_seed(RNG_SEED);
+        (0..n).map(|_| rng.sample::<f64, _>(StandardNormal) * sigma).collect()
+    }
+
+    /// Kasdin & Walter fractional integration of white noise, `S_y(f) ~ 1/f`
+    fn flicker_fm(n: usize, sigma: f64) -> Vec<f64> {
+        let white = white_fm(n, sigma);
+        let mut h = vec![1.0; n];
+        for k in 1..n {
+            h[k] = h[k - 1] * (k as f64 - 0.5) / k as f64;
+        }
+        (0..n).map(|i| (0..=i).map(|k| h[k] * white[i - k]).sum()).collect()
+    }
+
+    fn phase(y: &[f64]) -> Vec<f64> {
+        let mut x = vec![0.0];
+        for y in y {
+            x.push(x.last().unwrap() + y);
+        }
+        x
+    }
+
+    fn reference_adev(y: &[f64], m: usize) -> f64 {
+        let x = phase(y);
+        let n = x.len() - 2 * m;
+        let sum: f64 = (0..n).map(|i| (x[i + 2 * m] - 2.0 * x[i + m] + x[i]).powi(2)).sum();
+        (sum / (2.0 * (m * m) as f64 * n as f64)).sqrt()
+    }
+
+    fn reference_mdev(y: &[f64], m: usize) -> f64 {
+        let x = phase(y);
+        let n = x.len() - 3 * m + 1;
+        let sum: f64 = (0..n).map(|j| {
+            (j..j + m).map(|i| x[i + 2 * m] - 2.0 * x[i + m] + x[i]).sum::<f64>().powi(2)
+        }).sum();
+        (sum / (2.0 * (m * m * m * m) as f64 * n as f64)).sqrt()
+    }
+
+    fn analyze(y: &[f64]) -> StabilityAnalysis {
+        let mut stability = StabilityAnalysis::new(F0, 1.0);
+        for y in y {
+            stability.add(F0 * (1.0 + y));
+        }
+        stability
+    }
+
+    #[test]
+    fn no_results_until_enough_samples() {
+        let mut stability = StabilityAnalysis::new(F0, 1.0);
+        stability.add(F0);
+        assert!(stability.adev(0).is_none());
+        assert!(stability.mdev(0).is_none());
+        stability.add(F0);
+        assert!(stability.adev(0).is_some());
+        assert!(stability.mdev(0).is_some());
+        stability.add(F0);
+        assert!(stability.adev(1).is_none());
+        stability.add(F0);
+        assert!(stability.adev(1).is_some());
+        assert!(stability.mdev(1).is_none());
+        stability.add(F0);
+        assert!(stability.mdev(1).is_some());
+        assert_eq!(5, stability.samples());
+    }
+
+    #[test]
+    fn constant_frequency_offset_is_ignored() {
+        let mut y = white_fm(2000, 1e-11);
+        let expected = analyze(&y);
+        for y in y.iter_mut() {
+            *y += 1e-7;
+        }
+        let stability = analyze(&y);
+
+        for octave in 0..OCTAVES {
+            assert_approx_eq!(expected.adev(octave).unwrap(), stability.adev(octave).unwrap(), 1e-14);
+        }
+    }
+
+    #[test]
+    fn fully_overlapping_octaves_match_reference() {
+        let y = flicker_fm(2000, 1e-11);
+        let stability = analyze(&y);
+
+        for octave in 0..OCTAVES {
+            let m = 1 << octave;
+            if m > OVERLAP {
+                break;
+            }
+            let adev = reference_adev(&y, m);
+            let mdev = reference_mdev(&y, m);
+            assert_approx_eq!(adev, stability.adev(octave).unwrap(), adev * 1e-6);
+            assert_approx_eq!(mdev, stability.mdev(octave).unwrap(), mdev * 1e-6);
+            assert_approx_eq!(mdev * m as f64 / 3f64.sqrt(), stability.tdev(octave).unwrap(), mdev * m as f64 * 1e-6);
+        }
+    }
+
+    /// The phase against the first sample's frequency, and its running sum, keep growing
+    #[test]
+    fn long_runs_off_the_first_frequency() {
+        let mut y = white_fm(100_000, 1e-11);
+        for y in y.iter_mut().skip(1) {
+            *y += 1e-8;
+        }
+        let stability = analyze(&y);
+
+        for octave in 0..=2 {
+            let m = 1 << octave;
+            let adev = reference_adev(&y, m);
+            let mdev = reference_mdev(&y, m);
+            assert_approx_eq!(adev, stability.adev(octave).unwrap(), adev * 1e-7);
+            assert_approx_eq!(mdev, stability.mdev(octave).unwrap(), mdev * 1e-7);
+        }
+    }
+
+    #[test]
+    fn white_fm_noise() {
+        let sigma = 1e-11;
+        let y = white_fm(20000, sigma);
+        let stability = analyze(&y);
+
+        for octave in 0..7 {
+            let m = (1 << octave) as f64;
+            let adev = stability.adev(octave).unwrap();
+            let mdev = stability.mdev(octave).unwrap();
+
+            // σ_y(τ) = σ / √m
+            assert_approx_eq!(sigma / m.sqrt(), adev, 0.15 * adev);
+            assert_approx_eq!(reference_adev(&y, 1 << octave), adev, 0.15 * adev);
+            assert_approx_eq!(reference_mdev(&y, 1 << octave), mdev, 0.15 * mdev);
+            if m >= 8.0 {
+                // Mod σ²_y / σ²_y → 0.5
+                assert_approx_eq!(0.5f64.sqrt(), mdev / adev, 0.1);
+            }
+        }
+    }
+
+    #[test]
+    fn flicker_fm_noise() {
+        let y = flicker_fm(8192, 1e-11);
+        let stability = analyze(&y);
+
+        let floor = stability.adev(3).unwrap();
+        for octave in 1..7 {
+            let m = (1 << octave) as f64;
+            let adev = stability.adev(octave).unwrap();
+            let mdev = stability.mdev(octave).unwrap();
+
+            // σ_y(τ) doesn't depend on τ
+            assert_approx_eq!(floor, adev, 0.25 * floor);
+            assert_approx_eq!(reference_adev(&y, 1 << octave), adev, 0.15 * adev);
+            assert_approx_eq!(reference_mdev(&y, 1 << octave), mdev, 0.15 * mdev);
+            if m >= 8.0 {
+                // Mod σ²_y / σ²_y → 0.67
+                assert_approx_eq!(0.67f64.sqrt(), mdev / adev, 0.1);
+            }
+        }
+    }
+}